@@ -12,12 +12,20 @@ pub enum BinaryOp {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum UnaryOp {
     Not,
     UnaryMinus,
+    BitNot,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -25,18 +33,102 @@ pub enum Expr {
     Nil,
 
     Identifier(String),
+    IntegerLiteral(i64),
     NumberLiteral(f64),
     StringLiteral(String),
+    Interpolation(Vec<Expr>),
     BooleanLiteral(bool),
 
-    Assign(String, Box<Expr>),
-    Function(Vec<String>, Vec<Stmt>),
-    Call(Box<Expr>, Vec<Expr>),
+    Block(Vec<Stmt>, Option<Box<Expr>>),
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    Match(Box<Expr>, Vec<MatchArm>),
+
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+
+    /// A range of integers, from the start (inclusive) to the end (exclusive).
+    Range(Box<Expr>, Box<Expr>),
+
+    Get(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+
+    Assign(Box<Expr>, Box<Expr>),
+    CompoundAssign(Box<Expr>, BinaryOp, Box<Expr>),
+
+    /// `x++` or `x--`, which applies the operator to the target and one, and
+    /// evaluates to the target's previous value. `++x` and `--x` are parsed as
+    /// compound assignments instead.
+    PostIncrement(Box<Expr>, BinaryOp),
+
+    Function(Vec<Param>, Vec<Stmt>),
+    Call(Box<Expr>, Vec<Arg>),
 
     UnaryOp(UnaryOp, Box<Expr>),
     BinaryOp(BinaryOp, Box<Expr>, Box<Expr>),
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum Param {
+    Required(String),
+
+    /// A parameter with a default value, which is evaluated when the function
+    /// is called without an argument for it.
+    Optional(String, Expr),
+
+    /// Collects any extra positional arguments into a list. This can only be
+    /// the last parameter.
+    Rest(String),
+}
+
+impl Param {
+    pub fn name(&self) -> &str {
+        match self {
+            Param::Required(name) | Param::Optional(name, _) | Param::Rest(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Arg {
+    Positional(Expr),
+
+    /// Passes each item of a list as a separate positional argument.
+    Spread(Expr),
+
+    /// Passes an argument to the parameter with the given name. Keyword
+    /// arguments come after any positional arguments.
+    Keyword(String, Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// Matches anything, written as `_`.
+    Wildcard,
+
+    /// Matches anything, and binds it to a new local variable.
+    Binding(String),
+
+    /// Matches values that are equal to a literal.
+    Literal(Expr),
+
+    /// Matches a list whose items match the given patterns.
+    ///
+    /// If there is a rest pattern (written as `..` or `..name`), the list can
+    /// have extra items, which get matched against it as a new list.
+    List(Vec<Pattern>, Option<Box<Pattern>>),
+
+    /// Matches a map which has the given keys, and whose values match the
+    /// given patterns. Any other entries in the map are ignored.
+    Map(Vec<(Expr, Pattern)>),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
     Return(Expr),
@@ -44,5 +136,64 @@ pub enum Stmt {
     Declaration(String, Expr),
     If(Expr, Vec<Stmt>, Vec<Stmt>),
     While(Expr, Vec<Stmt>),
+
+    /// Runs the body once for each value produced by iterating over the
+    /// expression, binding it to a new local variable with the given name.
+    For(String, Expr, Vec<Stmt>),
+
+    /// Suspends the current generator, passing a value to the code that is
+    /// iterating over it. Any function containing a `yield` is a generator.
+    Yield(Expr),
+
     Block(Vec<Stmt>),
+    Throw(Expr),
+
+    /// A `try` block, with an optional `catch` block (along with the name that
+    /// the caught value is bound to) and an optional `finally` block. At least
+    /// one of the two will be present.
+    Try(Vec<Stmt>, Option<(String, Vec<Stmt>)>, Option<Vec<Stmt>>),
+
+    /// Imports a module, binding it to a variable with the given name.
+    Import(String, String),
+
+    /// Imports the given names from a module.
+    ImportFrom(String, Vec<String>),
+}
+
+impl Expr {
+    /// Returns whether the expression refers to a place that can be assigned to.
+    pub fn is_place(&self) -> bool {
+        matches!(
+            self,
+            Expr::Identifier(_) | Expr::Get(_, _) | Expr::Index(_, _)
+        )
+    }
+
+    /// Lowers an expression that is being used as a statement.
+    ///
+    /// `if` expressions become `Stmt::If` (with `else if` chains nested in the
+    /// else branch), and the values of their branches are discarded.
+    pub fn into_stmt(self) -> Stmt {
+        match self {
+            Expr::If(condition, when_true, when_false) => Stmt::If(
+                *condition,
+                when_true.into_stmts(),
+                when_false.map_or_else(Vec::new, |e| e.into_stmts()),
+            ),
+            other => Stmt::ExprStmt(other),
+        }
+    }
+
+    fn into_stmts(self) -> Vec<Stmt> {
+        match self {
+            Expr::Block(mut stmts, value) => {
+                if let Some(value) = value {
+                    stmts.push(value.into_stmt());
+                }
+
+                stmts
+            }
+            other => vec![other.into_stmt()],
+        }
+    }
 }
@@ -1,5 +1,6 @@
 pub mod tokens;
 
+use std::borrow::Cow;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::mem;
@@ -17,12 +18,75 @@ fn is_id_continue(ch: char) -> bool {
     ch == '_' || ch.is_ascii_digit()
 }
 
+/// Converts a word into a keyword token, or an identifier if it isn't one.
+fn identifier_token(word: &str) -> Token<'_> {
+    match word {
+        "as" => Token::As,
+        "catch" => Token::Catch,
+        "else" => Token::Else,
+        "false" => Token::False,
+        "finally" => Token::Finally,
+        "fn" => Token::Fn,
+        "for" => Token::For,
+        "from" => Token::From,
+        "if" => Token::If,
+        "import" => Token::Import,
+        "in" => Token::In,
+        "match" => Token::Match,
+        "nil" => Token::Nil,
+        "return" => Token::Return,
+        "this" => Token::This,
+        "throw" => Token::Throw,
+        "true" => Token::True,
+        "try" => Token::Try,
+        "let" => Token::Let,
+        "while" => Token::While,
+        "yield" => Token::Yield,
+        id => Token::Identifier(id),
+    }
+}
+
+/// Returns whether a string is a valid identifier - that is, it's made up of
+/// identifier characters and isn't a keyword.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(ch) if is_id_start(ch) => {}
+        _ => return false,
+    }
+
+    chars.all(|ch| is_id_start(ch) || is_id_continue(ch))
+        && matches!(identifier_token(name), Token::Identifier(_))
+}
+
+/// Removes the `_` separators from a number literal, or returns `None` if any
+/// of them aren't between two digits (as in `1_`, `1__0` or `0x_1`).
+fn strip_separators(literal: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = literal.chars().collect();
+
+    for (i, &ch) in chars.iter().enumerate() {
+        if ch == '_' {
+            let after_digit = i > 0 && chars[i - 1].is_digit(radix);
+            let before_digit = chars.get(i + 1).is_some_and(|ch| ch.is_digit(radix));
+
+            if !after_digit || !before_digit {
+                return None;
+            }
+        }
+    }
+
+    Some(chars.into_iter().filter(|&ch| ch != '_').collect())
+}
+
 pub type Location = usize;
 
 #[derive(Debug, PartialEq)]
 pub enum LexicalError {
     InvalidCharacter { ch: char, location: Location },
     UnterminatedString { location: Location },
+    InvalidEscape { location: Location },
+    InvalidNumber { location: Location },
 }
 
 impl Display for LexicalError {
@@ -34,6 +98,12 @@ impl Display for LexicalError {
             LexicalError::UnterminatedString { location } => {
                 write!(f, "String starting at {} was not terminated", location)
             }
+            LexicalError::InvalidEscape { location } => {
+                write!(f, "Invalid escape sequence found at {}", location)
+            }
+            LexicalError::InvalidNumber { location } => {
+                write!(f, "Invalid number found at {}", location)
+            }
         }
     }
 }
@@ -42,11 +112,20 @@ impl Error for LexicalError {}
 
 pub type SpanResult<'input> = Result<(Location, Token<'input>, Location), LexicalError>;
 
+/// Tracks a `${...}` interpolation that the lexer is currently inside of.
+struct Interpolation {
+    /// The number of unclosed braces within the interpolated expression.
+    depth: usize,
+    /// Whether the enclosing string is a triple-quoted string.
+    multiline: bool,
+}
+
 pub struct Lexer<'input> {
     source: &'input str,
     chars: CharIndices<'input>,
     lookahead: Option<(usize, char)>,
     lookahead2: Option<(usize, char)>,
+    interpolations: Vec<Interpolation>,
 }
 
 impl<'input> Lexer<'input> {
@@ -60,6 +139,7 @@ impl<'input> Lexer<'input> {
             chars,
             lookahead,
             lookahead2,
+            interpolations: vec![],
         }
     }
 
@@ -100,57 +180,290 @@ impl<'input> Lexer<'input> {
         self.take_while(|ch| ch.is_whitespace());
     }
 
+    fn offset(&self) -> usize {
+        self.lookahead.map_or(self.source.len(), |(i, _)| i)
+    }
+
     fn read_string(&mut self, pos: usize) -> SpanResult<'input> {
-        match self.take_until(|ch| ch == '"') {
-            Some(i) => {
-                self.bump();
-                Ok((pos, Token::String(&self.source[pos + 1..i]), i + 1))
+        if let (Some((_, '"')), Some((_, '"'))) = (self.lookahead, self.lookahead2) {
+            self.bump();
+            self.bump();
+
+            self.read_string_contents(pos, pos + 3, true, false)
+        } else {
+            self.read_string_contents(pos, pos + 1, false, false)
+        }
+    }
+
+    fn read_string_contents(
+        &mut self,
+        pos: usize,
+        start: usize,
+        multiline: bool,
+        interpolated: bool,
+    ) -> SpanResult<'input> {
+        // The string is only copied if we need to process an escape sequence.
+        let mut unescaped: Option<String> = None;
+        let mut chunk_start = start;
+
+        loop {
+            match self.bump() {
+                Some((i, '"')) => {
+                    if multiline {
+                        match (self.lookahead, self.lookahead2) {
+                            (Some((_, '"')), Some((_, '"'))) => {
+                                self.bump();
+                                self.bump();
+                            }
+                            _ => continue,
+                        }
+                    }
+
+                    let end = self.offset();
+                    let contents = self.finish_string(unescaped, start, chunk_start, i);
+
+                    let token = if interpolated {
+                        Token::StringEnd(contents)
+                    } else {
+                        Token::String(contents)
+                    };
+
+                    return Ok((pos, token, end));
+                }
+
+                Some((i, '$')) if matches!(self.lookahead, Some((_, '{'))) => {
+                    self.bump();
+
+                    self.interpolations.push(Interpolation {
+                        depth: 0,
+                        multiline,
+                    });
+
+                    let end = self.offset();
+                    let contents = self.finish_string(unescaped, start, chunk_start, i);
+
+                    let token = if interpolated {
+                        Token::StringMiddle(contents)
+                    } else {
+                        Token::StringStart(contents)
+                    };
+
+                    return Ok((pos, token, end));
+                }
+
+                Some((i, '\\')) => {
+                    let s = unescaped.get_or_insert_with(String::new);
+                    s.push_str(&self.source[chunk_start..i]);
+
+                    s.push(self.read_escape(i)?);
+
+                    chunk_start = self.offset();
+                }
+
+                Some(_) => {}
+
+                None => return Err(LexicalError::UnterminatedString { location: pos }),
             }
-            None => Err(LexicalError::UnterminatedString { location: pos }),
         }
     }
 
-    fn read_number(&mut self, pos: usize) -> SpanResult<'input> {
-        let mut end = self.take_while(|ch| ch.is_ascii_digit());
+    fn finish_string(
+        &self,
+        unescaped: Option<String>,
+        start: usize,
+        chunk_start: usize,
+        end: usize,
+    ) -> Cow<'input, str> {
+        match unescaped {
+            Some(mut s) => {
+                s.push_str(&self.source[chunk_start..end]);
+                Cow::Owned(s)
+            }
+            None => Cow::Borrowed(&self.source[start..end]),
+        }
+    }
+
+    fn read_escape(&mut self, pos: usize) -> Result<char, LexicalError> {
+        let error = LexicalError::InvalidEscape { location: pos };
+
+        let ch = match self.bump() {
+            Some((_, 'n')) => '\n',
+            Some((_, 'r')) => '\r',
+            Some((_, 't')) => '\t',
+            Some((_, '0')) => '\0',
+            Some((_, '\\')) => '\\',
+            Some((_, '"')) => '"',
+            Some((_, '$')) => '$',
+            Some((_, 'u')) => {
+                if let Some((_, '{')) = self.bump() {
+                    let start = self.offset();
+                    self.take_while(|ch| ch.is_ascii_hexdigit());
+                    let end = self.offset();
+
+                    match self.bump() {
+                        Some((_, '}')) if end > start && end - start <= 6 => {
+                            u32::from_str_radix(&self.source[start..end], 16)
+                                .ok()
+                                .and_then(std::char::from_u32)
+                                .ok_or(error)?
+                        }
+                        _ => return Err(error),
+                    }
+                } else {
+                    return Err(error);
+                }
+            }
+            _ => return Err(error),
+        };
+
+        Ok(ch)
+    }
+
+    fn read_raw_string(&mut self, pos: usize) -> SpanResult<'input> {
+        let mut hashes = 0;
+
+        while let Some((_, '#')) = self.lookahead {
+            self.bump();
+            hashes += 1;
+        }
+
+        match self.bump() {
+            Some((_, '"')) => {}
+            Some((i, ch)) => return Err(LexicalError::InvalidCharacter { ch, location: i }),
+            None => return Err(LexicalError::UnterminatedString { location: pos }),
+        }
+
+        let start = self.offset();
+
+        while let Some((i, ch)) = self.bump() {
+            if ch == '"'
+                && self.source[i + 1..]
+                    .chars()
+                    .take_while(|&c| c == '#')
+                    .count()
+                    >= hashes
+            {
+                for _ in 0..hashes {
+                    self.bump();
+                }
+
+                return Ok((
+                    pos,
+                    Token::String(Cow::Borrowed(&self.source[start..i])),
+                    self.offset(),
+                ));
+            }
+        }
+
+        Err(LexicalError::UnterminatedString { location: pos })
+    }
+
+    fn read_number(&mut self, pos: usize, first: char) -> SpanResult<'input> {
+        let error = LexicalError::InvalidNumber { location: pos };
+
+        if first == '0' {
+            let radix = match self.lookahead {
+                Some((_, 'x')) => Some(16),
+                Some((_, 'o')) => Some(8),
+                Some((_, 'b')) => Some(2),
+                _ => None,
+            };
+
+            if let Some(radix) = radix {
+                self.bump();
+
+                let start = self.offset();
+                self.take_while(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+                let end = self.offset();
+
+                let digits = match strip_separators(&self.source[start..end], radix) {
+                    Some(digits) => digits,
+                    None => return Err(error),
+                };
+
+                // Prefixed literals are allowed to use the full 64 bits, which makes
+                // it possible to write bitmasks like `0xFFFF_FFFF_FFFF_FFFF`.
+                return match u64::from_str_radix(&digits, radix) {
+                    Ok(value) => Ok((pos, Token::Integer(value as i64), end)),
+                    Err(_) => Err(error),
+                };
+            }
+        }
+
+        let mut is_float = false;
+
+        self.take_while(|ch| ch.is_ascii_digit() || ch == '_');
 
         if let Some((_, '.')) = self.lookahead {
             // Check if it's a decimal or a field access
             if let Some((_, next_ch)) = self.lookahead2 {
                 if next_ch.is_ascii_digit() {
+                    is_float = true;
                     self.bump();
-                    end = self.take_while(|ch| ch.is_ascii_digit());
+                    self.take_while(|ch| ch.is_ascii_digit() || ch == '_');
                 }
             }
         }
 
-        let end = end.unwrap_or_else(|| self.source.len());
+        if let Some((_, 'e')) | Some((_, 'E')) = self.lookahead {
+            is_float = true;
+            self.bump();
+
+            if let Some((_, '+')) | Some((_, '-')) = self.lookahead {
+                self.bump();
+            }
+
+            self.take_while(|ch| ch.is_ascii_digit() || ch == '_');
+        }
+
+        let end = self.offset();
+
+        // Catch things like `123abc`, rather than lexing them as two tokens.
+        if let Some((_, ch)) = self.lookahead {
+            if is_id_start(ch) {
+                return Err(error);
+            }
+        }
+
+        let digits = match strip_separators(&self.source[pos..end], 10) {
+            Some(digits) => digits,
+            None => return Err(error),
+        };
 
-        Ok((
-            pos,
-            Token::Number(self.source[pos..end].parse().expect("unparsable number")),
-            end,
-        ))
+        let token = if is_float {
+            digits.parse().ok().map(Token::Number)
+        } else {
+            digits.parse().ok().map(Token::Integer)
+        };
+
+        match token {
+            Some(token) => Ok((pos, token, end)),
+            None => Err(error),
+        }
+    }
+
+    /// Lexes an operator which has a compound assignment form (e.g. `+` and `+=`).
+    fn read_operator(
+        &mut self,
+        pos: usize,
+        len: usize,
+        operator: Token<'input>,
+        assignment: Token<'input>,
+    ) -> SpanResult<'input> {
+        if let Some((_, '=')) = self.lookahead {
+            self.bump();
+            Ok((pos, assignment, pos + len + 1))
+        } else {
+            Ok((pos, operator, pos + len))
+        }
     }
 
     fn read_identifier(&mut self, pos: usize) -> SpanResult<'input> {
         let end = self
             .take_while(|ch| is_id_start(ch) || is_id_continue(ch))
-            .unwrap_or_else(|| self.source.len());
-
-        let token = match &self.source[pos..end] {
-            "else" => Token::Else,
-            "false" => Token::False,
-            "fn" => Token::Fn,
-            "for" => Token::For,
-            "if" => Token::If,
-            "nil" => Token::Nil,
-            "return" => Token::Return,
-            "this" => Token::This,
-            "true" => Token::True,
-            "let" => Token::Let,
-            "while" => Token::While,
-            id => Token::Identifier(id),
-        };
+            .unwrap_or(self.source.len());
+
+        let token = identifier_token(&self.source[pos..end]);
 
         Ok((pos, token, end))
     }
@@ -164,25 +477,90 @@ impl<'input> Iterator for Lexer<'input> {
 
         if let Some((i, ch)) = self.bump() {
             match ch {
-                '{' => Some(Ok((i, Token::OpenBrace, i + 1))),
-                '}' => Some(Ok((i, Token::CloseBrace, i + 1))),
+                '{' => {
+                    if let Some(interpolation) = self.interpolations.last_mut() {
+                        interpolation.depth += 1;
+                    }
+
+                    Some(Ok((i, Token::OpenBrace, i + 1)))
+                }
+
+                '}' => match self.interpolations.last_mut() {
+                    Some(Interpolation {
+                        depth: 0,
+                        multiline,
+                    }) => {
+                        let multiline = *multiline;
+                        self.interpolations.pop();
+                        Some(self.read_string_contents(i, i + 1, multiline, true))
+                    }
+                    Some(interpolation) => {
+                        interpolation.depth -= 1;
+                        Some(Ok((i, Token::CloseBrace, i + 1)))
+                    }
+                    None => Some(Ok((i, Token::CloseBrace, i + 1))),
+                },
+
                 '(' => Some(Ok((i, Token::OpenParen, i + 1))),
                 ')' => Some(Ok((i, Token::CloseParen, i + 1))),
                 '[' => Some(Ok((i, Token::OpenBracket, i + 1))),
                 ']' => Some(Ok((i, Token::CloseBracket, i + 1))),
                 ';' => Some(Ok((i, Token::Semicolon, i + 1))),
                 ',' => Some(Ok((i, Token::Comma, i + 1))),
-                '.' => Some(Ok((i, Token::Dot, i + 1))),
-                '+' => Some(Ok((i, Token::Plus, i + 1))),
-                '-' => Some(Ok((i, Token::Minus, i + 1))),
-                '*' => Some(Ok((i, Token::Star, i + 1))),
+                ':' => Some(Ok((i, Token::Colon, i + 1))),
+
+                '.' => {
+                    if let Some((_, '.')) = self.lookahead {
+                        self.bump();
+
+                        if let Some((_, '.')) = self.lookahead {
+                            self.bump();
+                            Some(Ok((i, Token::DotDotDot, i + 3)))
+                        } else {
+                            Some(Ok((i, Token::DotDot, i + 2)))
+                        }
+                    } else {
+                        Some(Ok((i, Token::Dot, i + 1)))
+                    }
+                }
+
+                '~' => Some(Ok((i, Token::Tilde, i + 1))),
+                '+' => {
+                    if let Some((_, '+')) = self.lookahead {
+                        self.bump();
+                        Some(Ok((i, Token::PlusPlus, i + 2)))
+                    } else {
+                        Some(self.read_operator(i, 1, Token::Plus, Token::PlusEqual))
+                    }
+                }
+
+                '-' => {
+                    if let Some((_, '-')) = self.lookahead {
+                        self.bump();
+                        Some(Ok((i, Token::MinusMinus, i + 2)))
+                    } else {
+                        Some(self.read_operator(i, 1, Token::Minus, Token::MinusEqual))
+                    }
+                }
+
+                '%' => Some(self.read_operator(i, 1, Token::Percent, Token::PercentEqual)),
+                '^' => Some(self.read_operator(i, 1, Token::Caret, Token::CaretEqual)),
+
+                '*' => {
+                    if let Some((_, '*')) = self.lookahead {
+                        self.bump();
+                        Some(self.read_operator(i, 2, Token::StarStar, Token::StarStarEqual))
+                    } else {
+                        Some(self.read_operator(i, 1, Token::Star, Token::StarEqual))
+                    }
+                }
 
                 '/' => {
                     if let Some((_, '/')) = self.lookahead {
                         self.skip_to_line_end();
                         self.next()
                     } else {
-                        Some(Ok((i, Token::Slash, i + 1)))
+                        Some(self.read_operator(i, 1, Token::Slash, Token::SlashEqual))
                     }
                 }
 
@@ -199,6 +577,9 @@ impl<'input> Iterator for Lexer<'input> {
                     if let Some((_, '=')) = self.lookahead {
                         self.bump();
                         Some(Ok((i, Token::EqualEqual, i + 2)))
+                    } else if let Some((_, '>')) = self.lookahead {
+                        self.bump();
+                        Some(Ok((i, Token::FatArrow, i + 2)))
                     } else {
                         Some(Ok((i, Token::Equal, i + 1)))
                     }
@@ -208,6 +589,14 @@ impl<'input> Iterator for Lexer<'input> {
                     if let Some((_, '=')) = self.lookahead {
                         self.bump();
                         Some(Ok((i, Token::GreaterEqual, i + 2)))
+                    } else if let Some((_, '>')) = self.lookahead {
+                        self.bump();
+                        Some(self.read_operator(
+                            i,
+                            2,
+                            Token::GreaterGreater,
+                            Token::GreaterGreaterEqual,
+                        ))
                     } else {
                         Some(Ok((i, Token::Greater, i + 1)))
                     }
@@ -217,6 +606,9 @@ impl<'input> Iterator for Lexer<'input> {
                     if let Some((_, '=')) = self.lookahead {
                         self.bump();
                         Some(Ok((i, Token::LessEqual, i + 2)))
+                    } else if let Some((_, '<')) = self.lookahead {
+                        self.bump();
+                        Some(self.read_operator(i, 2, Token::LessLess, Token::LessLessEqual))
                     } else {
                         Some(Ok((i, Token::Less, i + 1)))
                     }
@@ -227,7 +619,7 @@ impl<'input> Iterator for Lexer<'input> {
                         self.bump();
                         Some(Ok((i, Token::AmpAmp, i + 2)))
                     } else {
-                        Some(Err(LexicalError::InvalidCharacter { ch, location: i }))
+                        Some(self.read_operator(i, 1, Token::Amp, Token::AmpEqual))
                     }
                 }
 
@@ -236,13 +628,18 @@ impl<'input> Iterator for Lexer<'input> {
                         self.bump();
                         Some(Ok((i, Token::PipePipe, i + 2)))
                     } else {
-                        Some(Err(LexicalError::InvalidCharacter { ch, location: i }))
+                        Some(self.read_operator(i, 1, Token::Pipe, Token::PipeEqual))
                     }
                 }
 
                 '"' => Some(self.read_string(i)),
+
+                'r' if matches!(self.lookahead, Some((_, '"')) | Some((_, '#'))) => {
+                    Some(self.read_raw_string(i))
+                }
+
                 ch if is_id_start(ch) => Some(self.read_identifier(i)),
-                ch if ch.is_ascii_digit() => Some(self.read_number(i)),
+                ch if ch.is_ascii_digit() => Some(self.read_number(i, ch)),
 
                 ch => Some(Err(LexicalError::InvalidCharacter { ch, location: i })),
             }
@@ -290,7 +687,7 @@ mod test {
     #[test]
     fn operators() {
         lex(
-            ", . + - * / = == ! != > >= < <= && ||",
+            ", . + - * / = == ! != > >= < <= && || :",
             vec![
                 (0, Token::Comma, 1),
                 (2, Token::Dot, 3),
@@ -308,6 +705,69 @@ mod test {
                 (29, Token::LessEqual, 31),
                 (32, Token::AmpAmp, 34),
                 (35, Token::PipePipe, 37),
+                (38, Token::Colon, 39),
+            ],
+        );
+
+        lex(
+            "% ** & | ^ ~ << >>",
+            vec![
+                (0, Token::Percent, 1),
+                (2, Token::StarStar, 4),
+                (5, Token::Amp, 6),
+                (7, Token::Pipe, 8),
+                (9, Token::Caret, 10),
+                (11, Token::Tilde, 12),
+                (13, Token::LessLess, 15),
+                (16, Token::GreaterGreater, 18),
+            ],
+        );
+
+        lex(
+            "=> .. 1..x ...xs",
+            vec![
+                (0, Token::FatArrow, 2),
+                (3, Token::DotDot, 5),
+                (6, Token::Integer(1), 7),
+                (7, Token::DotDot, 9),
+                (9, Token::Identifier("x"), 10),
+                (11, Token::DotDotDot, 14),
+                (14, Token::Identifier("xs"), 16),
+            ],
+        );
+    }
+
+    #[test]
+    fn compound_assignment() {
+        lex(
+            "+= -= *= /= %= **= &= |= ^= <<= >>=",
+            vec![
+                (0, Token::PlusEqual, 2),
+                (3, Token::MinusEqual, 5),
+                (6, Token::StarEqual, 8),
+                (9, Token::SlashEqual, 11),
+                (12, Token::PercentEqual, 14),
+                (15, Token::StarStarEqual, 18),
+                (19, Token::AmpEqual, 21),
+                (22, Token::PipeEqual, 24),
+                (25, Token::CaretEqual, 27),
+                (28, Token::LessLessEqual, 31),
+                (32, Token::GreaterGreaterEqual, 35),
+            ],
+        );
+    }
+
+    #[test]
+    fn increment() {
+        lex(
+            "x++ --y + +",
+            vec![
+                (0, Token::Identifier("x"), 1),
+                (1, Token::PlusPlus, 3),
+                (4, Token::MinusMinus, 6),
+                (6, Token::Identifier("y"), 7),
+                (8, Token::Plus, 9),
+                (10, Token::Plus, 11),
             ],
         );
     }
@@ -317,9 +777,9 @@ mod test {
         lex(
             "123; // comment\n 123",
             vec![
-                (0, Token::Number(123.0), 3),
+                (0, Token::Integer(123), 3),
                 (3, Token::Semicolon, 4),
-                (17, Token::Number(123.0), 20),
+                (17, Token::Integer(123), 20),
             ],
         );
     }
@@ -328,13 +788,99 @@ mod test {
     fn string() {
         lex(
             "\"hello, world\"",
-            vec![(0, Token::String("hello, world"), 14)],
+            vec![(0, Token::String("hello, world".into()), 14)],
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        lex(
+            r#""a\"b\\c\nd\te\u{1F415}""#,
+            vec![(0, Token::String("a\"b\\c\nd\te\u{1F415}".into()), 24)],
+        );
+
+        assert_eq!(
+            Some(Err(LexicalError::InvalidEscape { location: 2 })),
+            Lexer::new(r#""a\qb""#).next()
+        );
+
+        assert_eq!(
+            Some(Err(LexicalError::InvalidEscape { location: 1 })),
+            Lexer::new(r#""\u{110000}""#).next()
+        );
+
+        assert_eq!(
+            Some(Err(LexicalError::InvalidEscape { location: 1 })),
+            Lexer::new(r#""\u{}""#).next()
+        );
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(
+            Some(Err(LexicalError::UnterminatedString { location: 0 })),
+            Lexer::new("\"abc").next()
+        );
+
+        assert_eq!(
+            Some(Err(LexicalError::UnterminatedString { location: 0 })),
+            Lexer::new("\"\"\"abc\"").next()
+        );
+    }
+
+    #[test]
+    fn string_interpolation() {
+        lex(
+            r#""a${b}c${ {d} }e""#,
+            vec![
+                (0, Token::StringStart("a".into()), 4),
+                (4, Token::Identifier("b"), 5),
+                (5, Token::StringMiddle("c".into()), 9),
+                (10, Token::OpenBrace, 11),
+                (11, Token::Identifier("d"), 12),
+                (12, Token::CloseBrace, 13),
+                (14, Token::StringEnd("e".into()), 17),
+            ],
+        );
+
+        lex(
+            r#""${"${x}"}""#,
+            vec![
+                (0, Token::StringStart("".into()), 3),
+                (3, Token::StringStart("".into()), 6),
+                (6, Token::Identifier("x"), 7),
+                (7, Token::StringEnd("".into()), 9),
+                (9, Token::StringEnd("".into()), 11),
+            ],
+        );
+
+        lex(
+            r#""\${a} $b""#,
+            vec![(0, Token::String("${a} $b".into()), 10)],
+        );
+    }
+
+    #[test]
+    fn raw_string() {
+        lex(r#"r"a\nb""#, vec![(0, Token::String("a\\nb".into()), 7)]);
+
+        lex(
+            r###"r##"a"#b"##"###,
+            vec![(0, Token::String("a\"#b".into()), 11)],
+        );
+    }
+
+    #[test]
+    fn multiline_string() {
+        lex(
+            "\"\"\"a\n\"b\"\\tc\"\"\"",
+            vec![(0, Token::String("a\n\"b\"\tc".into()), 14)],
         );
     }
 
     #[test]
     fn integer() {
-        lex("123", vec![(0, Token::Number(123.0), 3)]);
+        lex("123", vec![(0, Token::Integer(123), 3)]);
     }
 
     #[test]
@@ -342,12 +888,66 @@ mod test {
         lex("123.45", vec![(0, Token::Number(123.45), 6)]);
     }
 
+    #[test]
+    fn prefixed_numbers() {
+        lex("0xFF", vec![(0, Token::Integer(255), 4)]);
+        lex("0o17", vec![(0, Token::Integer(15), 4)]);
+        lex("0b1010", vec![(0, Token::Integer(10), 6)]);
+        lex("0xdead_beef", vec![(0, Token::Integer(0xdead_beef), 11)]);
+        lex("0xFFFF_FFFF_FFFF_FFFF", vec![(0, Token::Integer(-1), 21)]);
+    }
+
+    #[test]
+    fn exponents() {
+        lex("1e10", vec![(0, Token::Number(1e10), 4)]);
+        lex("2.5E-3", vec![(0, Token::Number(2.5e-3), 6)]);
+        lex("4e+2", vec![(0, Token::Number(400.0), 4)]);
+    }
+
+    #[test]
+    fn separators() {
+        lex("1_000_000", vec![(0, Token::Integer(1_000_000), 9)]);
+        lex("1_000.000_1", vec![(0, Token::Number(1_000.000_1), 11)]);
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for source in &[
+            "0x",
+            "0b102",
+            "0xZZ",
+            "1e",
+            "2.5e+",
+            "123abc",
+            "9223372036854775808",
+            "123_",
+            "1__0",
+            "0x_",
+            "0x_1",
+            "1_.5",
+            "1.5_e3",
+            "1e_3",
+        ] {
+            assert_eq!(
+                Some(Err(LexicalError::InvalidNumber { location: 0 })),
+                Lexer::new(source).next(),
+                "{} should be invalid",
+                source
+            );
+        }
+
+        assert_eq!(
+            Some(Err(LexicalError::InvalidNumber { location: 4 })),
+            Lexer::new("1 + 0b2").nth(2)
+        );
+    }
+
     #[test]
     fn number_field_access() {
         lex(
             "123.prop",
             vec![
-                (0, Token::Number(123.0), 3),
+                (0, Token::Integer(123), 3),
                 (3, Token::Dot, 4),
                 (4, Token::Identifier("prop"), 8),
             ],
@@ -363,16 +963,26 @@ mod test {
 
     #[test]
     fn keywords() {
+        lex("as", vec![(0, Token::As, 2)]);
+        lex("catch", vec![(0, Token::Catch, 5)]);
         lex("else", vec![(0, Token::Else, 4)]);
         lex("false", vec![(0, Token::False, 5)]);
+        lex("finally", vec![(0, Token::Finally, 7)]);
         lex("fn", vec![(0, Token::Fn, 2)]);
         lex("for", vec![(0, Token::For, 3)]);
+        lex("from", vec![(0, Token::From, 4)]);
         lex("if", vec![(0, Token::If, 2)]);
+        lex("import", vec![(0, Token::Import, 6)]);
+        lex("in", vec![(0, Token::In, 2)]);
+        lex("match", vec![(0, Token::Match, 5)]);
         lex("nil", vec![(0, Token::Nil, 3)]);
         lex("return", vec![(0, Token::Return, 6)]);
         lex("this", vec![(0, Token::This, 4)]);
+        lex("throw", vec![(0, Token::Throw, 5)]);
         lex("true", vec![(0, Token::True, 4)]);
+        lex("try", vec![(0, Token::Try, 3)]);
         lex("let", vec![(0, Token::Let, 3)]);
         lex("while", vec![(0, Token::While, 5)]);
+        lex("yield", vec![(0, Token::Yield, 5)]);
     }
 }
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fmt;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -11,46 +12,85 @@ pub enum Token<'input> {
     CloseParen,
     Semicolon,
     Comma,
+    Colon,
     Dot,
+    DotDot,
+    DotDotDot,
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
+    StarStar,
     Not,
     NotEqual,
     Equal,
     EqualEqual,
+    FatArrow,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    Amp,
     AmpAmp,
+    Pipe,
     PipePipe,
+    Caret,
+    Tilde,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    StarStarEqual,
+    AmpEqual,
+    PipeEqual,
+    CaretEqual,
+    LessLessEqual,
+    GreaterGreaterEqual,
+    PlusPlus,
+    MinusMinus,
 
     // Literals
     Identifier(&'input str),
-    String(&'input str),
+    String(Cow<'input, str>),
+    StringStart(Cow<'input, str>),
+    StringMiddle(Cow<'input, str>),
+    StringEnd(Cow<'input, str>),
+    Integer(i64),
     Number(f64),
 
     // Keywords
+    As,
+    Catch,
     Else,
     False,
+    Finally,
     Fn,
     For,
+    From,
     If,
+    Import,
+    In,
+    Match,
     Nil,
     Return,
     This,
+    Throw,
     True,
+    Try,
     Let,
     While,
+    Yield,
 }
 
 impl<'input> fmt::Display for Token<'input> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::Token::*;
 
-        match *self {
+        match self {
             OpenBrace => write!(f, "{{"),
             CloseBrace => write!(f, "}}"),
             OpenBracket => write!(f, "["),
@@ -59,37 +99,76 @@ impl<'input> fmt::Display for Token<'input> {
             CloseParen => write!(f, ")"),
             Semicolon => write!(f, ";"),
             Comma => write!(f, ","),
+            Colon => write!(f, ":"),
             Dot => write!(f, "."),
+            DotDot => write!(f, ".."),
+            DotDotDot => write!(f, "..."),
             Plus => write!(f, "+"),
             Minus => write!(f, "-"),
             Star => write!(f, "*"),
             Slash => write!(f, "/"),
+            Percent => write!(f, "%"),
+            StarStar => write!(f, "**"),
             Not => write!(f, "!"),
             NotEqual => write!(f, "!="),
             Equal => write!(f, "="),
             EqualEqual => write!(f, "=="),
+            FatArrow => write!(f, "=>"),
             Greater => write!(f, ">"),
             GreaterEqual => write!(f, ">="),
             Less => write!(f, "<"),
             LessEqual => write!(f, "<="),
+            LessLess => write!(f, "<<"),
+            GreaterGreater => write!(f, ">>"),
+            Amp => write!(f, "&"),
             AmpAmp => write!(f, "&&"),
+            Pipe => write!(f, "|"),
             PipePipe => write!(f, "||"),
+            Caret => write!(f, "^"),
+            Tilde => write!(f, "~"),
+            PlusEqual => write!(f, "+="),
+            MinusEqual => write!(f, "-="),
+            StarEqual => write!(f, "*="),
+            SlashEqual => write!(f, "/="),
+            PercentEqual => write!(f, "%="),
+            StarStarEqual => write!(f, "**="),
+            AmpEqual => write!(f, "&="),
+            PipeEqual => write!(f, "|="),
+            CaretEqual => write!(f, "^="),
+            LessLessEqual => write!(f, "<<="),
+            GreaterGreaterEqual => write!(f, ">>="),
+            PlusPlus => write!(f, "++"),
+            MinusMinus => write!(f, "--"),
 
             Identifier(i) => write!(f, "{}", i),
             String(s) => write!(f, "\"{}\"", s),
-            Number(n) => write!(f, "{}", n),
+            StringStart(s) => write!(f, "\"{}${{", s),
+            StringMiddle(s) => write!(f, "}}{}${{", s),
+            StringEnd(s) => write!(f, "}}{}\"", s),
+            Integer(n) => write!(f, "{}", n),
+            Number(n) => write!(f, "{:?}", n),
 
+            As => write!(f, "as"),
+            Catch => write!(f, "catch"),
             Else => write!(f, "else"),
             False => write!(f, "false"),
+            Finally => write!(f, "finally"),
             Fn => write!(f, "fn"),
             For => write!(f, "for"),
+            From => write!(f, "from"),
             If => write!(f, "if"),
+            Import => write!(f, "import"),
+            In => write!(f, "in"),
+            Match => write!(f, "match"),
             Nil => write!(f, "nil"),
             Return => write!(f, "return"),
             This => write!(f, "this"),
+            Throw => write!(f, "throw"),
             True => write!(f, "true"),
+            Try => write!(f, "try"),
             Let => write!(f, "let"),
             While => write!(f, "while"),
+            Yield => write!(f, "yield"),
         }
     }
 }
@@ -1,42 +1,116 @@
 mod grammar;
 
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
 use lalrpop_util::ParseError as LParseError;
 
 use crate::ast::{Expr, Stmt};
 use crate::lexer::tokens::Token;
-use crate::lexer::{Lexer, LexicalError, Location};
+use crate::lexer::{Lexer, LexicalError, Location, SpanResult};
 use grammar::{ExprParser, ProgramParser};
 
-pub type ParseError<'input> = LParseError<Location, Token<'input>, LexicalError>;
+#[derive(Debug, PartialEq)]
+pub enum SyntaxError {
+    Lexical(LexicalError),
+    InvalidAssignmentTarget { location: Location },
+    PositionalAfterKeyword { location: Location },
+    DuplicateKeyword { name: String, location: Location },
+    IntegerOverflow { location: Location },
+    InvalidModuleName { path: String, location: Location },
+}
+
+impl From<LexicalError> for SyntaxError {
+    fn from(err: LexicalError) -> SyntaxError {
+        SyntaxError::Lexical(err)
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::Lexical(e) => e.fmt(f),
+            SyntaxError::InvalidAssignmentTarget { location } => {
+                write!(f, "Invalid assignment target found at {}", location)
+            }
+            SyntaxError::PositionalAfterKeyword { location } => write!(
+                f,
+                "Positional argument found after keyword arguments at {}",
+                location
+            ),
+            SyntaxError::DuplicateKeyword { name, location } => {
+                write!(
+                    f,
+                    "Duplicate keyword argument {} found at {}",
+                    name, location
+                )
+            }
+            SyntaxError::IntegerOverflow { location } => {
+                write!(f, "Integer literal out of range found at {}", location)
+            }
+            SyntaxError::InvalidModuleName { path, location } => write!(
+                f,
+                "Module name {} is not a valid identifier at {} - use `import \"{}\" as name` instead",
+                module_name(path),
+                location,
+                path
+            ),
+        }
+    }
+}
+
+impl Error for SyntaxError {}
+
+pub type ParseError<'input> = LParseError<Location, Token<'input>, SyntaxError>;
+
+fn tokens(
+    input: &str,
+) -> impl Iterator<Item = Result<(Location, Token<'_>, Location), SyntaxError>> {
+    Lexer::new(input).map(|token: SpanResult<'_>| token.map_err(SyntaxError::from))
+}
+
+/// Gets the name that a module is bound to when it is imported without an
+/// alias - the last segment of its path, minus any extension.
+///
+/// This is also used to name modules that have been loaded, so it accepts
+/// Windows paths as well.
+pub fn module_name(path: &str) -> &str {
+    let file = path.rsplit(['/', '\\']).next().unwrap_or(path);
+
+    match file.find('.') {
+        Some(i) if i > 0 => &file[..i],
+        _ => file,
+    }
+}
 
 pub fn parse_program(input: &str) -> Result<Vec<Stmt>, ParseError<'_>> {
-    let lexer = Lexer::new(input);
     let parser = ProgramParser::new();
-    parser.parse(lexer)
+    parser.parse(tokens(input))
 }
 
 pub fn parse_expr(input: &str) -> Result<Expr, ParseError<'_>> {
-    let lexer = Lexer::new(input);
     let parser = ExprParser::new();
-    parser.parse(lexer)
+    parser.parse(tokens(input))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ast::{BinaryOp, Expr, Stmt, UnaryOp};
-    use crate::lexer::Lexer;
+    use crate::ast::{Arg, BinaryOp, Expr, MatchArm, Param, Pattern, Stmt, UnaryOp};
 
     fn stmt(input: &str, expected: Vec<Stmt>) {
-        let lexer = Lexer::new(input);
-        let parser = ProgramParser::new();
-        assert_eq!(expected, parser.parse(lexer).unwrap());
+        assert_eq!(expected, parse_program(input).unwrap());
     }
 
     fn expr(input: &str, expected: Expr) {
-        let lexer = Lexer::new(input);
-        let parser = ExprParser::new();
-        assert_eq!(expected, parser.parse(lexer).unwrap());
+        assert_eq!(expected, parse_expr(input).unwrap());
+    }
+
+    fn params(names: &[&str]) -> Vec<Param> {
+        names
+            .iter()
+            .map(|name| Param::Required(name.to_string()))
+            .collect()
     }
 
     #[test]
@@ -44,11 +118,86 @@ mod test {
         expr("nil", Expr::Nil);
         expr("true", Expr::BooleanLiteral(true));
         expr("false", Expr::BooleanLiteral(false));
-        expr("123", Expr::NumberLiteral(123.0));
+        expr("123", Expr::IntegerLiteral(123));
+        expr("123.0", Expr::NumberLiteral(123.0));
         expr("123.45", Expr::NumberLiteral(123.45));
+        expr("1e3", Expr::NumberLiteral(1000.0));
         expr("\"string\"", Expr::StringLiteral("string".to_string()));
     }
 
+    #[test]
+    fn interpolation() {
+        expr(
+            r#""Hello, ${name}!""#,
+            Expr::Interpolation(vec![
+                Expr::StringLiteral("Hello, ".to_string()),
+                Expr::Identifier("name".to_string()),
+                Expr::StringLiteral("!".to_string()),
+            ]),
+        );
+
+        expr(
+            r#""${a}${b + 1}""#,
+            Expr::Interpolation(vec![
+                Expr::Identifier("a".to_string()),
+                Expr::BinaryOp(
+                    BinaryOp::Add,
+                    Box::new(Expr::Identifier("b".to_string())),
+                    Box::new(Expr::IntegerLiteral(1)),
+                ),
+            ]),
+        );
+    }
+
+    #[test]
+    fn lists() {
+        expr("[]", Expr::List(vec![]));
+
+        expr(
+            "[1, [2],]",
+            Expr::List(vec![
+                Expr::IntegerLiteral(1),
+                Expr::List(vec![Expr::IntegerLiteral(2)]),
+            ]),
+        );
+    }
+
+    #[test]
+    fn maps() {
+        expr("[:]", Expr::Map(vec![]));
+
+        expr(
+            "[\"a\": 1, b: [2]]",
+            Expr::Map(vec![
+                (
+                    Expr::StringLiteral("a".to_string()),
+                    Expr::IntegerLiteral(1),
+                ),
+                (
+                    Expr::Identifier("b".to_string()),
+                    Expr::List(vec![Expr::IntegerLiteral(2)]),
+                ),
+            ]),
+        );
+    }
+
+    #[test]
+    fn field_and_index() {
+        expr(
+            "a.b[0](c)",
+            Expr::Call(
+                Box::new(Expr::Index(
+                    Box::new(Expr::Get(
+                        Box::new(Expr::Identifier("a".to_string())),
+                        "b".to_string(),
+                    )),
+                    Box::new(Expr::IntegerLiteral(0)),
+                )),
+                vec![Arg::Positional(Expr::Identifier("c".to_string()))],
+            ),
+        );
+    }
+
     #[test]
     fn identifiers() {
         expr("id", Expr::Identifier("id".to_string()));
@@ -60,21 +209,170 @@ mod test {
     fn assignment() {
         expr(
             "x = 10",
-            Expr::Assign("x".to_string(), Box::new(Expr::NumberLiteral(10.0))),
+            Expr::Assign(
+                Box::new(Expr::Identifier("x".to_string())),
+                Box::new(Expr::IntegerLiteral(10)),
+            ),
         );
 
         expr(
             "x = y = 10",
             Expr::Assign(
-                "x".to_string(),
+                Box::new(Expr::Identifier("x".to_string())),
                 Box::new(Expr::Assign(
-                    "y".to_string(),
-                    Box::new(Expr::NumberLiteral(10.0)),
+                    Box::new(Expr::Identifier("y".to_string())),
+                    Box::new(Expr::IntegerLiteral(10)),
                 )),
             ),
         );
     }
 
+    #[test]
+    fn place_assignment() {
+        expr(
+            "a.b.c = x",
+            Expr::Assign(
+                Box::new(Expr::Get(
+                    Box::new(Expr::Get(
+                        Box::new(Expr::Identifier("a".to_string())),
+                        "b".to_string(),
+                    )),
+                    "c".to_string(),
+                )),
+                Box::new(Expr::Identifier("x".to_string())),
+            ),
+        );
+
+        expr(
+            "xs[i][j] -= y",
+            Expr::CompoundAssign(
+                Box::new(Expr::Index(
+                    Box::new(Expr::Index(
+                        Box::new(Expr::Identifier("xs".to_string())),
+                        Box::new(Expr::Identifier("i".to_string())),
+                    )),
+                    Box::new(Expr::Identifier("j".to_string())),
+                )),
+                BinaryOp::Subtract,
+                Box::new(Expr::Identifier("y".to_string())),
+            ),
+        );
+
+        expr(
+            "f().x = 1",
+            Expr::Assign(
+                Box::new(Expr::Get(
+                    Box::new(Expr::Call(
+                        Box::new(Expr::Identifier("f".to_string())),
+                        vec![],
+                    )),
+                    "x".to_string(),
+                )),
+                Box::new(Expr::IntegerLiteral(1)),
+            ),
+        );
+    }
+
+    #[test]
+    fn invalid_assignment() {
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::InvalidAssignmentTarget { location: 0 }
+            }),
+            parse_expr("1 + 2 = 3")
+        );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::InvalidAssignmentTarget { location: 4 }
+            }),
+            parse_expr("x = f() += 3")
+        );
+    }
+
+    #[test]
+    fn compound_assignment() {
+        expr(
+            "x += 10",
+            Expr::CompoundAssign(
+                Box::new(Expr::Identifier("x".to_string())),
+                BinaryOp::Add,
+                Box::new(Expr::IntegerLiteral(10)),
+            ),
+        );
+
+        expr(
+            "x <<= y = 2 * 3",
+            Expr::CompoundAssign(
+                Box::new(Expr::Identifier("x".to_string())),
+                BinaryOp::ShiftLeft,
+                Box::new(Expr::Assign(
+                    Box::new(Expr::Identifier("y".to_string())),
+                    Box::new(Expr::BinaryOp(
+                        BinaryOp::Multiply,
+                        Box::new(Expr::IntegerLiteral(2)),
+                        Box::new(Expr::IntegerLiteral(3)),
+                    )),
+                )),
+            ),
+        );
+    }
+
+    #[test]
+    fn increment() {
+        expr(
+            "xs[i]++",
+            Expr::PostIncrement(
+                Box::new(Expr::Index(
+                    Box::new(Expr::Identifier("xs".to_string())),
+                    Box::new(Expr::Identifier("i".to_string())),
+                )),
+                BinaryOp::Add,
+            ),
+        );
+
+        expr(
+            "--a.b",
+            Expr::CompoundAssign(
+                Box::new(Expr::Get(
+                    Box::new(Expr::Identifier("a".to_string())),
+                    "b".to_string(),
+                )),
+                BinaryOp::Subtract,
+                Box::new(Expr::IntegerLiteral(1)),
+            ),
+        );
+
+        expr(
+            "-x-- ** 2",
+            Expr::UnaryOp(
+                UnaryOp::UnaryMinus,
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Power,
+                    Box::new(Expr::PostIncrement(
+                        Box::new(Expr::Identifier("x".to_string())),
+                        BinaryOp::Subtract,
+                    )),
+                    Box::new(Expr::IntegerLiteral(2)),
+                )),
+            ),
+        );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::InvalidAssignmentTarget { location: 2 }
+            }),
+            parse_expr("++f()")
+        );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::InvalidAssignmentTarget { location: 0 }
+            }),
+            parse_expr("1++")
+        );
+    }
+
     #[test]
     fn logic() {
         expr(
@@ -111,8 +409,8 @@ mod test {
             "10 > 5",
             Expr::BinaryOp(
                 BinaryOp::GreaterThan,
-                Box::new(Expr::NumberLiteral(10.0)),
-                Box::new(Expr::NumberLiteral(5.0)),
+                Box::new(Expr::IntegerLiteral(10)),
+                Box::new(Expr::IntegerLiteral(5)),
             ),
         );
 
@@ -120,8 +418,8 @@ mod test {
             "10 >= 5",
             Expr::BinaryOp(
                 BinaryOp::GreaterEquals,
-                Box::new(Expr::NumberLiteral(10.0)),
-                Box::new(Expr::NumberLiteral(5.0)),
+                Box::new(Expr::IntegerLiteral(10)),
+                Box::new(Expr::IntegerLiteral(5)),
             ),
         );
 
@@ -129,8 +427,8 @@ mod test {
             "10 < 5",
             Expr::BinaryOp(
                 BinaryOp::LessThan,
-                Box::new(Expr::NumberLiteral(10.0)),
-                Box::new(Expr::NumberLiteral(5.0)),
+                Box::new(Expr::IntegerLiteral(10)),
+                Box::new(Expr::IntegerLiteral(5)),
             ),
         );
 
@@ -138,8 +436,8 @@ mod test {
             "10 <= 5",
             Expr::BinaryOp(
                 BinaryOp::LessEquals,
-                Box::new(Expr::NumberLiteral(10.0)),
-                Box::new(Expr::NumberLiteral(5.0)),
+                Box::new(Expr::IntegerLiteral(10)),
+                Box::new(Expr::IntegerLiteral(5)),
             ),
         );
     }
@@ -150,8 +448,8 @@ mod test {
             "1 + 1",
             Expr::BinaryOp(
                 BinaryOp::Add,
-                Box::new(Expr::NumberLiteral(1.0)),
-                Box::new(Expr::NumberLiteral(1.0)),
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::IntegerLiteral(1)),
             ),
         );
     }
@@ -162,8 +460,8 @@ mod test {
             "1 - 1",
             Expr::BinaryOp(
                 BinaryOp::Subtract,
-                Box::new(Expr::NumberLiteral(1.0)),
-                Box::new(Expr::NumberLiteral(1.0)),
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::IntegerLiteral(1)),
             ),
         );
     }
@@ -174,8 +472,8 @@ mod test {
             "1 * 1",
             Expr::BinaryOp(
                 BinaryOp::Multiply,
-                Box::new(Expr::NumberLiteral(1.0)),
-                Box::new(Expr::NumberLiteral(1.0)),
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::IntegerLiteral(1)),
             ),
         );
     }
@@ -186,8 +484,95 @@ mod test {
             "1 / 1",
             Expr::BinaryOp(
                 BinaryOp::Divide,
-                Box::new(Expr::NumberLiteral(1.0)),
-                Box::new(Expr::NumberLiteral(1.0)),
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::IntegerLiteral(1)),
+            ),
+        );
+    }
+
+    #[test]
+    fn modulo() {
+        expr(
+            "1 % 1",
+            Expr::BinaryOp(
+                BinaryOp::Modulo,
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::IntegerLiteral(1)),
+            ),
+        );
+    }
+
+    #[test]
+    fn power() {
+        expr(
+            "2 ** 3 ** 2",
+            Expr::BinaryOp(
+                BinaryOp::Power,
+                Box::new(Expr::IntegerLiteral(2)),
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Power,
+                    Box::new(Expr::IntegerLiteral(3)),
+                    Box::new(Expr::IntegerLiteral(2)),
+                )),
+            ),
+        );
+
+        expr(
+            "-2 ** -2",
+            Expr::UnaryOp(
+                UnaryOp::UnaryMinus,
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Power,
+                    Box::new(Expr::IntegerLiteral(2)),
+                    Box::new(Expr::UnaryOp(
+                        UnaryOp::UnaryMinus,
+                        Box::new(Expr::IntegerLiteral(2)),
+                    )),
+                )),
+            ),
+        );
+    }
+
+    #[test]
+    fn bitwise() {
+        expr(
+            "1 | 2 ^ 3 & 4 << 5",
+            Expr::BinaryOp(
+                BinaryOp::BitOr,
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::BitXor,
+                    Box::new(Expr::IntegerLiteral(2)),
+                    Box::new(Expr::BinaryOp(
+                        BinaryOp::BitAnd,
+                        Box::new(Expr::IntegerLiteral(3)),
+                        Box::new(Expr::BinaryOp(
+                            BinaryOp::ShiftLeft,
+                            Box::new(Expr::IntegerLiteral(4)),
+                            Box::new(Expr::IntegerLiteral(5)),
+                        )),
+                    )),
+                )),
+            ),
+        );
+
+        expr(
+            "x >> 1 + 2 == ~y",
+            Expr::BinaryOp(
+                BinaryOp::Equals,
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::ShiftRight,
+                    Box::new(Expr::Identifier("x".to_string())),
+                    Box::new(Expr::BinaryOp(
+                        BinaryOp::Add,
+                        Box::new(Expr::IntegerLiteral(1)),
+                        Box::new(Expr::IntegerLiteral(2)),
+                    )),
+                )),
+                Box::new(Expr::UnaryOp(
+                    UnaryOp::BitNot,
+                    Box::new(Expr::Identifier("y".to_string())),
+                )),
             ),
         );
     }
@@ -211,7 +596,7 @@ mod test {
         expr(
             "x = -1 * 2 + 3 > 4 != 5",
             Expr::Assign(
-                "x".to_string(),
+                Box::new(Expr::Identifier("x".to_string())),
                 Box::new(Expr::BinaryOp(
                     BinaryOp::NotEquals,
                     Box::new(Expr::BinaryOp(
@@ -222,15 +607,15 @@ mod test {
                                 BinaryOp::Multiply,
                                 Box::new(Expr::UnaryOp(
                                     UnaryOp::UnaryMinus,
-                                    Box::new(Expr::NumberLiteral(1.0)),
+                                    Box::new(Expr::IntegerLiteral(1)),
                                 )),
-                                Box::new(Expr::NumberLiteral(2.0)),
+                                Box::new(Expr::IntegerLiteral(2)),
                             )),
-                            Box::new(Expr::NumberLiteral(3.0)),
+                            Box::new(Expr::IntegerLiteral(3)),
                         )),
-                        Box::new(Expr::NumberLiteral(4.0)),
+                        Box::new(Expr::IntegerLiteral(4)),
                     )),
-                    Box::new(Expr::NumberLiteral(5.0)),
+                    Box::new(Expr::IntegerLiteral(5)),
                 )),
             ),
         );
@@ -257,14 +642,14 @@ mod test {
                 BinaryOp::Multiply,
                 Box::new(Expr::BinaryOp(
                     BinaryOp::Multiply,
-                    Box::new(Expr::NumberLiteral(2.0)),
+                    Box::new(Expr::IntegerLiteral(2)),
                     Box::new(Expr::BinaryOp(
                         BinaryOp::Add,
-                        Box::new(Expr::NumberLiteral(1.0)),
-                        Box::new(Expr::NumberLiteral(2.0)),
+                        Box::new(Expr::IntegerLiteral(1)),
+                        Box::new(Expr::IntegerLiteral(2)),
                     )),
                 )),
-                Box::new(Expr::NumberLiteral(3.0)),
+                Box::new(Expr::IntegerLiteral(3)),
             ),
         );
     }
@@ -275,19 +660,79 @@ mod test {
             "id(1, x)",
             Expr::Call(
                 Box::new(Expr::Identifier("id".to_string())),
-                vec![Expr::NumberLiteral(1.0), Expr::Identifier("x".to_string())],
+                vec![
+                    Arg::Positional(Expr::IntegerLiteral(1)),
+                    Arg::Positional(Expr::Identifier("x".to_string())),
+                ],
+            ),
+        );
+    }
+
+    #[test]
+    fn call_arguments() {
+        let id = |name: &str| Expr::Identifier(name.to_string());
+
+        expr(
+            "f(a, ...b, c: 1)",
+            Expr::Call(
+                Box::new(id("f")),
+                vec![
+                    Arg::Positional(id("a")),
+                    Arg::Spread(id("b")),
+                    Arg::Keyword("c".to_string(), Expr::IntegerLiteral(1)),
+                ],
             ),
         );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::PositionalAfterKeyword { location: 8 }
+            }),
+            parse_expr("f(a: 1, b)")
+        );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::DuplicateKeyword {
+                    name: "a".to_string(),
+                    location: 8
+                }
+            }),
+            parse_expr("f(a: 1, a: 2)")
+        );
+    }
+
+    #[test]
+    fn parameters() {
+        expr(
+            "fn(a, b = 1, ...rest) {}",
+            Expr::Function(
+                vec![
+                    Param::Required("a".to_string()),
+                    Param::Optional("b".to_string(), Expr::IntegerLiteral(1)),
+                    Param::Rest("rest".to_string()),
+                ],
+                vec![],
+            ),
+        );
+
+        expr(
+            "|...xs| xs",
+            Expr::Function(
+                vec![Param::Rest("xs".to_string())],
+                vec![Stmt::Return(Expr::Identifier("xs".to_string()))],
+            ),
+        );
+
+        assert!(parse_expr("fn(...a, b) {}").is_err());
+        assert!(parse_expr("|a = 1| a").is_err());
     }
 
     #[test]
     fn declaration() {
         stmt(
             "let x = 10;",
-            vec![Stmt::Declaration(
-                "x".to_string(),
-                Expr::NumberLiteral(10.0),
-            )],
+            vec![Stmt::Declaration("x".to_string(), Expr::IntegerLiteral(10))],
         );
     }
 
@@ -298,7 +743,7 @@ mod test {
             vec![Stmt::Declaration(
                 "test".to_string(),
                 Expr::Function(
-                    vec!["a".to_string(), "b".to_string()],
+                    params(&["a", "b"]),
                     vec![Stmt::Return(Expr::BinaryOp(
                         BinaryOp::Add,
                         Box::new(Expr::Identifier("a".to_string())),
@@ -316,7 +761,7 @@ mod test {
             vec![Stmt::Declaration(
                 "test".to_string(),
                 Expr::Function(
-                    vec!["a".to_string(), "b".to_string()],
+                    params(&["a", "b"]),
                     vec![Stmt::Return(Expr::BinaryOp(
                         BinaryOp::Add,
                         Box::new(Expr::Identifier("a".to_string())),
@@ -327,6 +772,83 @@ mod test {
         );
     }
 
+    #[test]
+    fn anonymous_function() {
+        expr(
+            "fn(x) { return x; }",
+            Expr::Function(
+                params(&["x"]),
+                vec![Stmt::Return(Expr::Identifier("x".to_string()))],
+            ),
+        );
+
+        expr(
+            "fn() {}()",
+            Expr::Call(Box::new(Expr::Function(vec![], vec![])), vec![]),
+        );
+
+        stmt(
+            "f = fn(a, b) { a; };",
+            vec![Stmt::ExprStmt(Expr::Assign(
+                Box::new(Expr::Identifier("f".to_string())),
+                Box::new(Expr::Function(
+                    params(&["a", "b"]),
+                    vec![Stmt::ExprStmt(Expr::Identifier("a".to_string()))],
+                )),
+            ))],
+        );
+    }
+
+    #[test]
+    fn lambda() {
+        expr(
+            "|x| x * 2",
+            Expr::Function(
+                params(&["x"]),
+                vec![Stmt::Return(Expr::BinaryOp(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::Identifier("x".to_string())),
+                    Box::new(Expr::IntegerLiteral(2)),
+                ))],
+            ),
+        );
+
+        expr(
+            "|| nil",
+            Expr::Function(vec![], vec![Stmt::Return(Expr::Nil)]),
+        );
+
+        expr(
+            "map(xs, |a, b| { a })",
+            Expr::Call(
+                Box::new(Expr::Identifier("map".to_string())),
+                vec![
+                    Arg::Positional(Expr::Identifier("xs".to_string())),
+                    Arg::Positional(Expr::Function(
+                        params(&["a", "b"]),
+                        vec![Stmt::Return(Expr::Block(
+                            vec![],
+                            Some(Box::new(Expr::Identifier("a".to_string()))),
+                        ))],
+                    )),
+                ],
+            ),
+        );
+
+        // The body of a lambda extends as far to the right as it can.
+        expr(
+            "|x| x || y",
+            Expr::Function(
+                params(&["x"]),
+                vec![Stmt::Return(Expr::BinaryOp(
+                    BinaryOp::Or,
+                    Box::new(Expr::Identifier("x".to_string())),
+                    Box::new(Expr::Identifier("y".to_string())),
+                ))],
+            ),
+        );
+    }
+
     #[test]
     fn if_stmt() {
         stmt(
@@ -335,7 +857,7 @@ mod test {
                 Expr::BinaryOp(
                     BinaryOp::GreaterThan,
                     Box::new(Expr::Identifier("x".to_string())),
-                    Box::new(Expr::NumberLiteral(10.0)),
+                    Box::new(Expr::IntegerLiteral(10)),
                 ),
                 vec![Stmt::Return(Expr::BooleanLiteral(true))],
                 vec![],
@@ -348,7 +870,7 @@ mod test {
                 Expr::BinaryOp(
                     BinaryOp::GreaterThan,
                     Box::new(Expr::Identifier("x".to_string())),
-                    Box::new(Expr::NumberLiteral(10.0)),
+                    Box::new(Expr::IntegerLiteral(10)),
                 ),
                 vec![Stmt::Return(Expr::BooleanLiteral(true))],
                 vec![Stmt::Return(Expr::BooleanLiteral(false))],
@@ -356,17 +878,324 @@ mod test {
         );
     }
 
+    #[test]
+    fn else_if_stmt() {
+        stmt(
+            "if a { return 1; } else if b { return 2; } else { return 3; }",
+            vec![Stmt::If(
+                Expr::Identifier("a".to_string()),
+                vec![Stmt::Return(Expr::IntegerLiteral(1))],
+                vec![Stmt::If(
+                    Expr::Identifier("b".to_string()),
+                    vec![Stmt::Return(Expr::IntegerLiteral(2))],
+                    vec![Stmt::Return(Expr::IntegerLiteral(3))],
+                )],
+            )],
+        );
+
+        stmt(
+            "if a { 1 } else { 2 } x;",
+            vec![
+                Stmt::If(
+                    Expr::Identifier("a".to_string()),
+                    vec![Stmt::ExprStmt(Expr::IntegerLiteral(1))],
+                    vec![Stmt::ExprStmt(Expr::IntegerLiteral(2))],
+                ),
+                Stmt::ExprStmt(Expr::Identifier("x".to_string())),
+            ],
+        );
+    }
+
+    #[test]
+    fn if_expr() {
+        expr(
+            "if a { 1 } else if b { 2 }",
+            Expr::If(
+                Box::new(Expr::Identifier("a".to_string())),
+                Box::new(Expr::Block(vec![], Some(Box::new(Expr::IntegerLiteral(1))))),
+                Some(Box::new(Expr::If(
+                    Box::new(Expr::Identifier("b".to_string())),
+                    Box::new(Expr::Block(vec![], Some(Box::new(Expr::IntegerLiteral(2))))),
+                    None,
+                ))),
+            ),
+        );
+
+        stmt(
+            "let x = if a { y = 1; y } else { if b { 2 } else { 3 } };",
+            vec![Stmt::Declaration(
+                "x".to_string(),
+                Expr::If(
+                    Box::new(Expr::Identifier("a".to_string())),
+                    Box::new(Expr::Block(
+                        vec![Stmt::ExprStmt(Expr::Assign(
+                            Box::new(Expr::Identifier("y".to_string())),
+                            Box::new(Expr::IntegerLiteral(1)),
+                        ))],
+                        Some(Box::new(Expr::Identifier("y".to_string()))),
+                    )),
+                    Some(Box::new(Expr::Block(
+                        vec![],
+                        Some(Box::new(Expr::If(
+                            Box::new(Expr::Identifier("b".to_string())),
+                            Box::new(Expr::Block(vec![], Some(Box::new(Expr::IntegerLiteral(2))))),
+                            Some(Box::new(Expr::Block(
+                                vec![],
+                                Some(Box::new(Expr::IntegerLiteral(3))),
+                            ))),
+                        ))),
+                    ))),
+                ),
+            )],
+        );
+
+        stmt(
+            "if a { } else { } ;",
+            vec![Stmt::ExprStmt(Expr::If(
+                Box::new(Expr::Identifier("a".to_string())),
+                Box::new(Expr::Block(vec![], None)),
+                Some(Box::new(Expr::Block(vec![], None))),
+            ))],
+        );
+
+        // `if` can be used as an operand, but at the start of a statement it
+        // is parsed as a statement.
+        expr(
+            "1 + if a { 2 } else { 3 } * 4",
+            Expr::BinaryOp(
+                BinaryOp::Add,
+                Box::new(Expr::IntegerLiteral(1)),
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::If(
+                        Box::new(Expr::Identifier("a".to_string())),
+                        Box::new(Expr::Block(vec![], Some(Box::new(Expr::IntegerLiteral(2))))),
+                        Some(Box::new(Expr::Block(
+                            vec![],
+                            Some(Box::new(Expr::IntegerLiteral(3))),
+                        ))),
+                    )),
+                    Box::new(Expr::IntegerLiteral(4)),
+                )),
+            ),
+        );
+
+        stmt(
+            "if a { } -1;",
+            vec![
+                Stmt::If(Expr::Identifier("a".to_string()), vec![], vec![]),
+                Stmt::ExprStmt(Expr::UnaryOp(
+                    UnaryOp::UnaryMinus,
+                    Box::new(Expr::IntegerLiteral(1)),
+                )),
+            ],
+        );
+    }
+
+    #[test]
+    fn match_expr() {
+        expr(
+            "match x { 1 => a, [y, _] if y > 0 => { y } [\"k\": [..rest]] => b, -2.5 => c }",
+            Expr::Match(
+                Box::new(Expr::Identifier("x".to_string())),
+                vec![
+                    MatchArm {
+                        pattern: Pattern::Literal(Expr::IntegerLiteral(1)),
+                        guard: None,
+                        body: Expr::Identifier("a".to_string()),
+                    },
+                    MatchArm {
+                        pattern: Pattern::List(
+                            vec![Pattern::Binding("y".to_string()), Pattern::Wildcard],
+                            None,
+                        ),
+                        guard: Some(Expr::BinaryOp(
+                            BinaryOp::GreaterThan,
+                            Box::new(Expr::Identifier("y".to_string())),
+                            Box::new(Expr::IntegerLiteral(0)),
+                        )),
+                        body: Expr::Block(
+                            vec![],
+                            Some(Box::new(Expr::Identifier("y".to_string()))),
+                        ),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Map(vec![(
+                            Expr::StringLiteral("k".to_string()),
+                            Pattern::List(
+                                vec![],
+                                Some(Box::new(Pattern::Binding("rest".to_string()))),
+                            ),
+                        )]),
+                        guard: None,
+                        body: Expr::Identifier("b".to_string()),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Literal(Expr::NumberLiteral(-2.5)),
+                        guard: None,
+                        body: Expr::Identifier("c".to_string()),
+                    },
+                ],
+            ),
+        );
+
+        // Negative literals in patterns are negated while parsing, so one that
+        // can't be negated is an error rather than an overflow.
+        expr(
+            "match x { -0x7FFF_FFFF_FFFF_FFFF => a }",
+            Expr::Match(
+                Box::new(Expr::Identifier("x".to_string())),
+                vec![MatchArm {
+                    pattern: Pattern::Literal(Expr::IntegerLiteral(-i64::MAX)),
+                    guard: None,
+                    body: Expr::Identifier("a".to_string()),
+                }],
+            ),
+        );
+
+        assert_eq!(
+            Err(ParseError::User {
+                error: SyntaxError::IntegerOverflow { location: 10 }
+            }),
+            parse_expr("match x { -0x8000000000000000 => a }")
+        );
+
+        // Matches can be used as statements without a semicolon.
+        stmt(
+            "match x { _ => {} } y;",
+            vec![
+                Stmt::ExprStmt(Expr::Match(
+                    Box::new(Expr::Identifier("x".to_string())),
+                    vec![MatchArm {
+                        pattern: Pattern::Wildcard,
+                        guard: None,
+                        body: Expr::Block(vec![], None),
+                    }],
+                )),
+                Stmt::ExprStmt(Expr::Identifier("y".to_string())),
+            ],
+        );
+    }
+
+    #[test]
+    fn import() {
+        stmt(
+            "import \"lib/util.ein\"; import \"lib/util\" as u; import { a, b } from \"m\";",
+            vec![
+                Stmt::Import("lib/util.ein".to_string(), "util".to_string()),
+                Stmt::Import("lib/util".to_string(), "u".to_string()),
+                Stmt::ImportFrom("m".to_string(), vec!["a".to_string(), "b".to_string()]),
+            ],
+        );
+
+        stmt(
+            "import \"lib\\\\util.ein\";",
+            vec![Stmt::Import(
+                "lib\\util.ein".to_string(),
+                "util".to_string(),
+            )],
+        );
+    }
+
+    #[test]
+    fn invalid_module_name() {
+        for source in &[
+            "import \"lib/my-mod\";",
+            "import \"2d.ein\";",
+            "import \"lib/match\";",
+        ] {
+            assert!(matches!(
+                parse_program(source),
+                Err(ParseError::User {
+                    error: SyntaxError::InvalidModuleName { location: 0, .. }
+                })
+            ));
+        }
+
+        stmt(
+            "import \"lib/my-mod\" as my_mod;",
+            vec![Stmt::Import("lib/my-mod".to_string(), "my_mod".to_string())],
+        );
+
+        assert_eq!(
+            "Module name my-mod is not a valid identifier at 0 - use `import \"lib/my-mod\" as name` instead",
+            parse_program("import \"lib/my-mod\";").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn try_stmt() {
+        let ret = |n| vec![Stmt::Return(Expr::IntegerLiteral(n))];
+
+        stmt(
+            "try { return 1; } catch (e) { return 2; }
+             try { return 1; } finally { return 3; }
+             try { return 1; } catch (e) { return 2; } finally { return 3; }
+             throw 4;",
+            vec![
+                Stmt::Try(ret(1), Some(("e".to_string(), ret(2))), None),
+                Stmt::Try(ret(1), None, Some(ret(3))),
+                Stmt::Try(ret(1), Some(("e".to_string(), ret(2))), Some(ret(3))),
+                Stmt::Throw(Expr::IntegerLiteral(4)),
+            ],
+        );
+
+        assert!(parse_program("try { return 1; }").is_err());
+    }
+
     #[test]
     fn while_stmt() {
         stmt(
             "while true { return 123; }",
             vec![Stmt::While(
                 Expr::BooleanLiteral(true),
-                vec![Stmt::Return(Expr::NumberLiteral(123.0))],
+                vec![Stmt::Return(Expr::IntegerLiteral(123))],
+            )],
+        )
+    }
+
+    #[test]
+    fn for_stmt() {
+        stmt(
+            "for x in 0..10 { yield x; }",
+            vec![Stmt::For(
+                "x".to_string(),
+                Expr::Range(
+                    Box::new(Expr::IntegerLiteral(0)),
+                    Box::new(Expr::IntegerLiteral(10)),
+                ),
+                vec![Stmt::Yield(Expr::Identifier("x".to_string()))],
             )],
         )
     }
 
+    #[test]
+    fn range() {
+        expr(
+            "a + 1..b * 2",
+            Expr::Range(
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Add,
+                    Box::new(Expr::Identifier("a".to_string())),
+                    Box::new(Expr::IntegerLiteral(1)),
+                )),
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::Identifier("b".to_string())),
+                    Box::new(Expr::IntegerLiteral(2)),
+                )),
+            ),
+        );
+
+        expr(
+            "Fiber.yield",
+            Expr::Get(
+                Box::new(Expr::Identifier("Fiber".to_string())),
+                "yield".to_string(),
+            ),
+        );
+    }
+
     #[test]
     fn block_stmt() {
         stmt(
@@ -374,10 +1203,10 @@ mod test {
             vec![Stmt::Block(vec![
                 Stmt::ExprStmt(Expr::BinaryOp(
                     BinaryOp::Add,
-                    Box::new(Expr::NumberLiteral(1.0)),
-                    Box::new(Expr::NumberLiteral(1.0)),
+                    Box::new(Expr::IntegerLiteral(1)),
+                    Box::new(Expr::IntegerLiteral(1)),
                 )),
-                Stmt::Return(Expr::NumberLiteral(123.0)),
+                Stmt::Return(Expr::IntegerLiteral(123)),
             ])],
         )
     }
@@ -387,9 +1216,9 @@ mod test {
         stmt(
             "\nlet x = 1;\nlet y = 2;\n\nlet z = 3;\n",
             vec![
-                Stmt::Declaration("x".to_string(), Expr::NumberLiteral(1.0)),
-                Stmt::Declaration("y".to_string(), Expr::NumberLiteral(2.0)),
-                Stmt::Declaration("z".to_string(), Expr::NumberLiteral(3.0)),
+                Stmt::Declaration("x".to_string(), Expr::IntegerLiteral(1)),
+                Stmt::Declaration("y".to_string(), Expr::IntegerLiteral(2)),
+                Stmt::Declaration("z".to_string(), Expr::IntegerLiteral(3)),
             ],
         );
     }
@@ -1,144 +1,1015 @@
-use ein_syntax::ast::{BinaryOp, Expr, Stmt, UnaryOp};
+use std::rc::Rc;
 
-use crate::Value;
+use hashbrown::HashMap;
+
+use ein_syntax::ast::{Arg, BinaryOp, Expr, Param, Pattern, Stmt, UnaryOp};
+
+use crate::compiler::{Compiler, Variable};
+use crate::{RuntimeError, Value};
 
 #[derive(Debug)]
 pub enum Instruction {
     // Stack control
     Return,
     Pop,
+    Duplicate(u8),
+    MoveDown(u8),
+    PopBelow(u8),
 
     // Loads
     LoadNil,
     LoadTrue,
     LoadFalse,
-    LoadConstant(u8),
-    LoadGlobal(u8),
+    LoadConstant(u16),
+    LoadGlobal(u16),
+    LoadLocal(u8),
+    LoadUpvalue(u8),
 
     // Stores
-    DefineGlobal(u8),
-    StoreGlobal(u8),
+    DefineGlobal(u16),
+    StoreGlobal(u16),
+    StoreLocal(u8),
+    StoreUpvalue(u8),
 
     // Jumps
-    Jump(u8),
-    JumpIfTrue(u8),
-    JumpIfFalse(u8),
-    Loop(u8),
+    Jump(u16),
+    JumpIfTrue(u16),
+    JumpIfFalse(u16),
+    JumpIfDone(u16),
+    Loop(u16),
 
     // Operators
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Not,
     Add,
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
     Negate,
+
+    // Strings
+    ToString,
+    Concat(u8),
+
+    // Collections
+    BuildList(u8),
+    BuildMap(u8),
+    AppendList,
+    InsertMap,
+    ExtendList,
+    GetIndex,
+    SetIndex,
+    GetField(u16),
+    SetField(u16),
+    BuildRange,
+
+    // Iterators
+    Iterate,
+    IterNext,
+    Yield,
+
+    // Functions
+    Closure(u16),
+    Call(u8),
+    CallUnpacked,
+    IsMissing(u8),
+    CloseUpvalue,
+
+    // Modules
+    Import(u16),
+
+    // Exceptions
+    Throw,
+    Catch,
+    Rethrow,
+
+    // Pattern matching
+    IsList(u8),
+    IsListAtLeast(u8),
+    IsMap,
+    HasKey(u16),
+    SliceList(u8),
+    NoMatch,
+}
+
+impl Instruction {
+    /// The change in the number of values on the stack after the instruction
+    /// runs.
+    pub fn stack_effect(&self) -> isize {
+        match self {
+            Instruction::Return => -1,
+            Instruction::Throw => -1,
+            Instruction::Catch => 0,
+            Instruction::Rethrow => -1,
+            Instruction::Pop => -1,
+            Instruction::Duplicate(count) => *count as isize,
+            Instruction::MoveDown(_) => 0,
+            Instruction::PopBelow(count) => -(*count as isize),
+
+            Instruction::LoadNil
+            | Instruction::LoadTrue
+            | Instruction::LoadFalse
+            | Instruction::LoadConstant(_)
+            | Instruction::LoadGlobal(_)
+            | Instruction::LoadLocal(_)
+            | Instruction::LoadUpvalue(_) => 1,
+
+            Instruction::DefineGlobal(_) => -1,
+            Instruction::StoreGlobal(_)
+            | Instruction::StoreLocal(_)
+            | Instruction::StoreUpvalue(_) => 0,
+
+            Instruction::Jump(_)
+            | Instruction::JumpIfTrue(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::JumpIfDone(_)
+            | Instruction::Loop(_) => 0,
+
+            Instruction::Equal
+            | Instruction::Greater
+            | Instruction::GreaterEqual
+            | Instruction::Less
+            | Instruction::LessEqual
+            | Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::Modulo
+            | Instruction::Power
+            | Instruction::BitAnd
+            | Instruction::BitOr
+            | Instruction::BitXor
+            | Instruction::ShiftLeft
+            | Instruction::ShiftRight => -1,
+
+            Instruction::Not | Instruction::BitNot | Instruction::Negate => 0,
+
+            Instruction::ToString => 0,
+            Instruction::Concat(count) => 1 - *count as isize,
+
+            Instruction::BuildList(count) => 1 - *count as isize,
+            Instruction::BuildMap(count) => 1 - *count as isize * 2,
+            Instruction::AppendList | Instruction::ExtendList => -1,
+            Instruction::InsertMap => -2,
+            Instruction::GetIndex => -1,
+            Instruction::SetIndex => -2,
+            Instruction::GetField(_) => 0,
+            Instruction::SetField(_) => -1,
+            Instruction::BuildRange => -1,
+
+            Instruction::Iterate => 0,
+            Instruction::IterNext => 1,
+            Instruction::Yield => 0,
+
+            Instruction::Closure(_) => 1,
+            Instruction::Call(count) => -(*count as isize),
+            Instruction::CallUnpacked => -2,
+            Instruction::IsMissing(_) => 1,
+            Instruction::CloseUpvalue => -1,
+
+            Instruction::Import(_) => 1,
+
+            Instruction::IsList(_)
+            | Instruction::IsListAtLeast(_)
+            | Instruction::IsMap
+            | Instruction::HasKey(_)
+            | Instruction::SliceList(_)
+            | Instruction::NoMatch => 0,
+        }
+    }
+}
+
+/// Gets the instruction that implements an arithmetic or bitwise operator, for
+/// use in compound assignments.
+fn operator_instruction(op: &BinaryOp) -> Instruction {
+    match op {
+        BinaryOp::Add => Instruction::Add,
+        BinaryOp::Subtract => Instruction::Subtract,
+        BinaryOp::Multiply => Instruction::Multiply,
+        BinaryOp::Divide => Instruction::Divide,
+        BinaryOp::Modulo => Instruction::Modulo,
+        BinaryOp::Power => Instruction::Power,
+        BinaryOp::BitAnd => Instruction::BitAnd,
+        BinaryOp::BitOr => Instruction::BitOr,
+        BinaryOp::BitXor => Instruction::BitXor,
+        BinaryOp::ShiftLeft => Instruction::ShiftLeft,
+        BinaryOp::ShiftRight => Instruction::ShiftRight,
+        other => panic!("{:?} cannot be used in a compound assignment", other),
+    }
+}
+
+/// Emits the instruction that loads the value of a variable.
+fn emit_load(compiler: &mut Compiler, name: &str) {
+    match compiler.resolve(name) {
+        Variable::Local(slot) => compiler.add_instruction(Instruction::LoadLocal(slot)),
+        Variable::Upvalue(i) => compiler.add_instruction(Instruction::LoadUpvalue(i)),
+        Variable::Global => {
+            let constant = compiler.add_constant(Value::String(name.to_string()));
+            compiler.add_instruction(Instruction::LoadGlobal(constant))
+        }
+    };
+}
+
+/// Emits the instruction that stores the value on top of the stack in a
+/// variable, leaving it on the stack.
+fn emit_store(compiler: &mut Compiler, name: &str) {
+    match compiler.resolve(name) {
+        Variable::Local(slot) => compiler.add_instruction(Instruction::StoreLocal(slot)),
+        Variable::Upvalue(i) => compiler.add_instruction(Instruction::StoreUpvalue(i)),
+        Variable::Global => {
+            let constant = compiler.add_constant(Value::String(name.to_string()));
+            compiler.add_instruction(Instruction::StoreGlobal(constant))
+        }
+    };
+}
+
+/// Emits the instructions that define a variable in the current scope, using
+/// the value on top of the stack.
+fn emit_define(compiler: &mut Compiler, name: &str) {
+    if compiler.is_global_scope() {
+        let constant = compiler.add_constant(Value::String(name.to_string()));
+        compiler.add_instruction(Instruction::DefineGlobal(constant));
+    } else {
+        let slot = compiler.top_slot();
+        compiler.add_local(name, slot);
+    }
+}
+
+/// Compiles a function body into a separate chunk, and emits the instruction
+/// that creates a closure from it.
+///
+/// The body is preceded by code that evaluates the default value of each
+/// optional parameter that wasn't given an argument.
+fn emit_function(compiler: &mut Compiler, name: Option<&str>, params: &[Param], body: &[Stmt]) {
+    compiler.begin_function(name, params);
+
+    for (i, param) in params.iter().enumerate() {
+        if let Param::Optional(_, default) = param {
+            compiler.add_instruction(Instruction::IsMissing(i as u8));
+
+            let skip_jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
+
+            compiler.add_instruction(Instruction::Pop);
+            default.emit(compiler);
+            compiler.add_instruction(Instruction::StoreLocal((i + 1) as u8));
+            compiler.add_instruction(Instruction::Pop);
+
+            let end_jump = compiler.add_instruction(Instruction::Jump(0));
+
+            compiler.patch_jump(skip_jump);
+            compiler.add_instruction(Instruction::Pop);
+            compiler.patch_jump(end_jump);
+        }
+    }
+
+    for stmt in body {
+        stmt.emit(compiler);
+    }
+
+    let function = compiler.end_function();
+    let constant = compiler.add_constant(Value::Function(Rc::new(function)));
+    compiler.add_instruction(Instruction::Closure(constant));
+}
+
+/// Emits a list literal.
+///
+/// `BuildList` can only take 255 items, so the items past that are appended to
+/// the list one at a time.
+fn emit_list(compiler: &mut Compiler, items: &[Expr]) {
+    let (built, appended) = items.split_at(items.len().min(u8::MAX as usize));
+
+    for item in built {
+        item.emit(compiler);
+    }
+
+    compiler.add_instruction(Instruction::BuildList(built.len() as u8));
+
+    for item in appended {
+        item.emit(compiler);
+        compiler.add_instruction(Instruction::AppendList);
+    }
+}
+
+/// Emits a map literal, using `emit_entry` to push the key and value of each
+/// entry. As with lists, the entries past the 255th are inserted one at a time.
+fn emit_map<T, F>(compiler: &mut Compiler, entries: &[T], emit_entry: F)
+where
+    F: Fn(&mut Compiler, &T),
+{
+    let (built, inserted) = entries.split_at(entries.len().min(u8::MAX as usize));
+
+    for entry in built {
+        emit_entry(compiler, entry);
+    }
+
+    compiler.add_instruction(Instruction::BuildMap(built.len() as u8));
+
+    for entry in inserted {
+        emit_entry(compiler, entry);
+        compiler.add_instruction(Instruction::InsertMap);
+    }
+}
+
+/// Emits a block in its own scope.
+fn emit_scoped(compiler: &mut Compiler, stmts: &[Stmt]) {
+    compiler.begin_scope();
+
+    for stmt in stmts {
+        stmt.emit(compiler);
+    }
+
+    compiler.end_scope();
+}
+
+/// Converts a literal expression (as used in a pattern) into a value.
+fn literal_value(expr: &Expr) -> Value {
+    match expr {
+        Expr::Nil => Value::Nil,
+        Expr::BooleanLiteral(v) => Value::Boolean(*v),
+        Expr::IntegerLiteral(v) => Value::Integer(*v),
+        Expr::NumberLiteral(v) => Value::Number(*v),
+        Expr::StringLiteral(v) => Value::String(v.clone()),
+        other => panic!("{:?} is not a literal", other),
+    }
+}
+
+/// Emits the instructions that load the part of a matched value that a nested
+/// pattern applies to. Each step of the path is a list index or a map key.
+fn emit_path(compiler: &mut Compiler, slot: u8, path: &[Value]) {
+    compiler.add_instruction(Instruction::LoadLocal(slot));
+
+    for key in path {
+        let constant = compiler.add_constant(key.clone());
+        compiler.add_instruction(Instruction::LoadConstant(constant));
+        compiler.add_instruction(Instruction::GetIndex);
+    }
+}
+
+/// Emits a single test of a pattern, which jumps to the next arm if it fails.
+fn emit_pattern_test(
+    compiler: &mut Compiler,
+    slot: u8,
+    path: &[Value],
+    instructions: Vec<Instruction>,
+    fail_jumps: &mut Vec<usize>,
+) {
+    emit_path(compiler, slot, path);
+
+    for instruction in instructions {
+        compiler.add_instruction(instruction);
+    }
+
+    fail_jumps.push(compiler.add_instruction(Instruction::JumpIfFalse(0)));
+    compiler.add_instruction(Instruction::Pop);
+}
+
+/// Emits the tests that decide whether a pattern matches.
+///
+/// A nested pattern is only tested once the patterns that contain it have
+/// matched, so the path to it is guaranteed to be valid.
+fn emit_pattern_tests(
+    compiler: &mut Compiler,
+    pattern: &Pattern,
+    slot: u8,
+    path: &mut Vec<Value>,
+    fail_jumps: &mut Vec<usize>,
+) {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binding(_) => {}
+
+        Pattern::Literal(literal) => {
+            let constant = compiler.add_constant(literal_value(literal));
+            emit_pattern_test(
+                compiler,
+                slot,
+                path,
+                vec![Instruction::LoadConstant(constant), Instruction::Equal],
+                fail_jumps,
+            );
+        }
+
+        Pattern::List(items, rest) => {
+            if items.len() > u8::MAX as usize {
+                compiler.too_large("a list pattern cannot contain more than 255 items");
+            }
+
+            let len = items.len() as u8;
+
+            let instruction = match rest {
+                Some(_) => Instruction::IsListAtLeast(len),
+                None => Instruction::IsList(len),
+            };
+
+            emit_pattern_test(compiler, slot, path, vec![instruction], fail_jumps);
+
+            for (i, item) in items.iter().enumerate() {
+                path.push(Value::Integer(i as i64));
+                emit_pattern_tests(compiler, item, slot, path, fail_jumps);
+                path.pop();
+            }
+        }
+
+        Pattern::Map(entries) => {
+            if entries.is_empty() {
+                emit_pattern_test(compiler, slot, path, vec![Instruction::IsMap], fail_jumps);
+            }
+
+            for (key, value) in entries {
+                let key = literal_value(key);
+                let constant = compiler.add_constant(key.clone());
+
+                let instruction = Instruction::HasKey(constant);
+                emit_pattern_test(compiler, slot, path, vec![instruction], fail_jumps);
+
+                path.push(key);
+                emit_pattern_tests(compiler, value, slot, path, fail_jumps);
+                path.pop();
+            }
+        }
+    }
+}
+
+/// Emits the instructions that bind the variables of a matched pattern to new
+/// locals.
+fn emit_pattern_bindings(
+    compiler: &mut Compiler,
+    pattern: &Pattern,
+    slot: u8,
+    path: &mut Vec<Value>,
+) {
+    match pattern {
+        Pattern::Wildcard | Pattern::Literal(_) => {}
+
+        Pattern::Binding(name) => {
+            emit_path(compiler, slot, path);
+
+            let local = compiler.top_slot();
+            compiler.add_local(name, local);
+        }
+
+        Pattern::List(items, rest) => {
+            for (i, item) in items.iter().enumerate() {
+                path.push(Value::Integer(i as i64));
+                emit_pattern_bindings(compiler, item, slot, path);
+                path.pop();
+            }
+
+            if let Some(rest) = rest {
+                if let Pattern::Binding(name) = &**rest {
+                    emit_path(compiler, slot, path);
+                    compiler.add_instruction(Instruction::SliceList(items.len() as u8));
+
+                    let local = compiler.top_slot();
+                    compiler.add_local(name, local);
+                }
+            }
+        }
+
+        Pattern::Map(entries) => {
+            for (key, value) in entries {
+                path.push(literal_value(key));
+                emit_pattern_bindings(compiler, value, slot, path);
+                path.pop();
+            }
+        }
+    }
 }
 
 pub trait Emit {
-    fn emit(&self, chunk: &mut Chunk);
+    fn emit(&self, compiler: &mut Compiler);
 }
 
 impl Emit for Expr {
-    fn emit(&self, chunk: &mut Chunk) {
+    fn emit(&self, compiler: &mut Compiler) {
         match self {
             Expr::Nil => {
-                chunk.add_instruction(Instruction::LoadNil);
+                compiler.add_instruction(Instruction::LoadNil);
             }
 
-            Expr::Identifier(name) => {
-                let constant = chunk.add_constant(Value::String(name.clone()));
-                chunk.add_instruction(Instruction::LoadGlobal(constant));
+            Expr::Identifier(name) => emit_load(compiler, name),
+
+            Expr::IntegerLiteral(v) => {
+                let constant = compiler.add_constant(Value::Integer(*v));
+                compiler.add_instruction(Instruction::LoadConstant(constant));
             }
 
             Expr::NumberLiteral(v) => {
-                let constant = chunk.add_constant(Value::Number(*v));
-                chunk.add_instruction(Instruction::LoadConstant(constant));
+                let constant = compiler.add_constant(Value::Number(*v));
+                compiler.add_instruction(Instruction::LoadConstant(constant));
             }
 
             Expr::StringLiteral(v) => {
-                let constant = chunk.add_constant(Value::String(v.clone()));
-                chunk.add_instruction(Instruction::LoadConstant(constant));
+                let constant = compiler.add_constant(Value::String(v.clone()));
+                compiler.add_instruction(Instruction::LoadConstant(constant));
+            }
+
+            Expr::Interpolation(parts) => {
+                if parts.is_empty() {
+                    let constant = compiler.add_constant(Value::String(String::new()));
+                    compiler.add_instruction(Instruction::LoadConstant(constant));
+                    return;
+                }
+
+                // `Concat` can only join 255 strings, so longer strings are
+                // joined in batches, each of which starts with the previous
+                // batch's result.
+                let mut pending = 0;
+
+                for part in parts {
+                    part.emit(compiler);
+
+                    if !matches!(part, Expr::StringLiteral(_)) {
+                        compiler.add_instruction(Instruction::ToString);
+                    }
+
+                    pending += 1;
+
+                    if pending == u8::MAX {
+                        compiler.add_instruction(Instruction::Concat(pending));
+                        pending = 1;
+                    }
+                }
+
+                if pending > 1 {
+                    compiler.add_instruction(Instruction::Concat(pending));
+                }
             }
 
             Expr::BooleanLiteral(v) => {
-                chunk.add_instruction(if *v {
+                compiler.add_instruction(if *v {
                     Instruction::LoadTrue
                 } else {
                     Instruction::LoadFalse
                 });
             }
 
-            Expr::Assign(name, value) => {
-                value.emit(chunk);
+            // Any locals that the block declares (whether by `let`, `fn` or
+            // `import`) are underneath its value on the stack when it ends, so
+            // they get popped from below it.
+            Expr::Block(stmts, value) => {
+                compiler.begin_scope();
+
+                stmts.emit(compiler);
+
+                match value {
+                    Some(value) => value.emit(compiler),
+                    None => {
+                        compiler.add_instruction(Instruction::LoadNil);
+                    }
+                }
+
+                compiler.end_block_scope();
+            }
+
+            Expr::If(condition, when_true, when_false) => {
+                condition.emit(compiler);
+
+                let else_jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
+
+                compiler.add_instruction(Instruction::Pop);
+
+                when_true.emit(compiler);
+
+                let then_jump = compiler.add_instruction(Instruction::Jump(0));
+
+                compiler.patch_jump(else_jump);
+                compiler.add_instruction(Instruction::Pop);
+
+                match when_false {
+                    Some(when_false) => when_false.emit(compiler),
+                    None => {
+                        compiler.add_instruction(Instruction::LoadNil);
+                    }
+                }
+
+                compiler.patch_jump(then_jump);
+            }
+
+            // The value being matched is stored in a hidden local, and each arm
+            // tests it in turn. The result is stored in a slot reserved below
+            // it, as with blocks.
+            Expr::Match(value, arms) => {
+                compiler.add_instruction(Instruction::LoadNil);
+                let result_slot = compiler.top_slot();
+
+                value.emit(compiler);
+                let value_slot = compiler.top_slot();
+
+                let mut end_jumps = vec![];
+
+                for arm in arms {
+                    compiler.begin_scope();
+
+                    let mut fail_jumps = vec![];
+                    emit_pattern_tests(
+                        compiler,
+                        &arm.pattern,
+                        value_slot,
+                        &mut vec![],
+                        &mut fail_jumps,
+                    );
+                    emit_pattern_bindings(compiler, &arm.pattern, value_slot, &mut vec![]);
+
+                    let guard_jump = arm.guard.as_ref().map(|guard| {
+                        guard.emit(compiler);
+
+                        let jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
+                        compiler.add_instruction(Instruction::Pop);
+                        jump
+                    });
+
+                    arm.body.emit(compiler);
+                    compiler.add_instruction(Instruction::StoreLocal(result_slot));
+                    compiler.add_instruction(Instruction::Pop);
+
+                    match guard_jump {
+                        // If the guard fails, the bindings have to be popped
+                        // before moving on to the next arm.
+                        Some(guard_jump) => {
+                            compiler.exit_scope();
+                            end_jumps.push(compiler.add_instruction(Instruction::Jump(0)));
+
+                            compiler.patch_jump(guard_jump);
+                            compiler.add_instruction(Instruction::Pop);
+                            compiler.end_scope();
+                        }
+
+                        None => {
+                            compiler.end_scope();
+                            end_jumps.push(compiler.add_instruction(Instruction::Jump(0)));
+                        }
+                    }
+
+                    if !fail_jumps.is_empty() {
+                        let next_arm =
+                            guard_jump.map(|_| compiler.add_instruction(Instruction::Jump(0)));
+
+                        for jump in fail_jumps {
+                            compiler.patch_jump(jump);
+                        }
+
+                        compiler.add_instruction(Instruction::Pop);
+
+                        if let Some(next_arm) = next_arm {
+                            compiler.patch_jump(next_arm);
+                        }
+                    }
+                }
+
+                compiler.add_instruction(Instruction::NoMatch);
+
+                for jump in end_jumps {
+                    compiler.patch_jump(jump);
+                }
+
+                compiler.add_instruction(Instruction::Pop);
+            }
+
+            Expr::List(items) => emit_list(compiler, items),
+
+            Expr::Map(entries) => emit_map(compiler, entries, |compiler, (key, value)| {
+                key.emit(compiler);
+                value.emit(compiler);
+            }),
+
+            Expr::Range(start, end) => {
+                start.emit(compiler);
+                end.emit(compiler);
+                compiler.add_instruction(Instruction::BuildRange);
+            }
+
+            Expr::Get(target, name) => {
+                target.emit(compiler);
+
+                let constant = compiler.add_constant(Value::String(name.clone()));
+                compiler.add_instruction(Instruction::GetField(constant));
+            }
+
+            Expr::Index(target, index) => {
+                target.emit(compiler);
+                index.emit(compiler);
+                compiler.add_instruction(Instruction::GetIndex);
+            }
+
+            Expr::Assign(target, value) => match &**target {
+                Expr::Identifier(name) => {
+                    value.emit(compiler);
+                    emit_store(compiler, name);
+                }
+
+                Expr::Get(target, name) => {
+                    target.emit(compiler);
+                    value.emit(compiler);
+
+                    let constant = compiler.add_constant(Value::String(name.clone()));
+                    compiler.add_instruction(Instruction::SetField(constant));
+                }
+
+                Expr::Index(target, index) => {
+                    target.emit(compiler);
+                    index.emit(compiler);
+                    value.emit(compiler);
+                    compiler.add_instruction(Instruction::SetIndex);
+                }
+
+                other => panic!("{:?} is not a valid assignment target", other),
+            },
+
+            // The target's sub-expressions are only evaluated once - they get
+            // duplicated on the stack, so they can be used for both the load and
+            // the store.
+            Expr::CompoundAssign(target, op, value) => match &**target {
+                Expr::Identifier(name) => {
+                    emit_load(compiler, name);
+
+                    value.emit(compiler);
+                    compiler.add_instruction(operator_instruction(op));
+
+                    emit_store(compiler, name);
+                }
+
+                Expr::Get(target, name) => {
+                    target.emit(compiler);
+                    compiler.add_instruction(Instruction::Duplicate(1));
+
+                    let constant = compiler.add_constant(Value::String(name.clone()));
+                    compiler.add_instruction(Instruction::GetField(constant));
+
+                    value.emit(compiler);
+                    compiler.add_instruction(operator_instruction(op));
+
+                    compiler.add_instruction(Instruction::SetField(constant));
+                }
+
+                Expr::Index(target, index) => {
+                    target.emit(compiler);
+                    index.emit(compiler);
+                    compiler.add_instruction(Instruction::Duplicate(2));
+                    compiler.add_instruction(Instruction::GetIndex);
+
+                    value.emit(compiler);
+                    compiler.add_instruction(operator_instruction(op));
+
+                    compiler.add_instruction(Instruction::SetIndex);
+                }
+
+                other => panic!("{:?} is not a valid assignment target", other),
+            },
 
-                let constant = chunk.add_constant(Value::String(name.clone()));
-                chunk.add_instruction(Instruction::StoreGlobal(constant));
+            // This works like a compound assignment, but a copy of the old value
+            // is moved below the target, so that it's left behind once the new
+            // value is popped.
+            Expr::PostIncrement(target, op) => {
+                let one = compiler.add_constant(Value::Integer(1));
+
+                match &**target {
+                    Expr::Identifier(name) => {
+                        emit_load(compiler, name);
+                        compiler.add_instruction(Instruction::Duplicate(1));
+
+                        compiler.add_instruction(Instruction::LoadConstant(one));
+                        compiler.add_instruction(operator_instruction(op));
+
+                        emit_store(compiler, name);
+                    }
+
+                    Expr::Get(target, name) => {
+                        target.emit(compiler);
+                        compiler.add_instruction(Instruction::Duplicate(1));
+
+                        let constant = compiler.add_constant(Value::String(name.clone()));
+                        compiler.add_instruction(Instruction::GetField(constant));
+                        compiler.add_instruction(Instruction::Duplicate(1));
+                        compiler.add_instruction(Instruction::MoveDown(2));
+
+                        compiler.add_instruction(Instruction::LoadConstant(one));
+                        compiler.add_instruction(operator_instruction(op));
+
+                        compiler.add_instruction(Instruction::SetField(constant));
+                    }
+
+                    Expr::Index(target, index) => {
+                        target.emit(compiler);
+                        index.emit(compiler);
+                        compiler.add_instruction(Instruction::Duplicate(2));
+                        compiler.add_instruction(Instruction::GetIndex);
+                        compiler.add_instruction(Instruction::Duplicate(1));
+                        compiler.add_instruction(Instruction::MoveDown(3));
+
+                        compiler.add_instruction(Instruction::LoadConstant(one));
+                        compiler.add_instruction(operator_instruction(op));
+
+                        compiler.add_instruction(Instruction::SetIndex);
+                    }
+
+                    other => panic!("{:?} is not a valid assignment target", other),
+                }
+
+                compiler.add_instruction(Instruction::Pop);
             }
 
-            Expr::Function(_, _) => unimplemented!(),
+            Expr::Function(params, body) => emit_function(compiler, None, params, body),
+
+            Expr::Call(callee, args) => {
+                callee.emit(compiler);
 
-            Expr::Call(_, _) => unimplemented!(),
+                let positional = args.iter().all(|arg| matches!(arg, Arg::Positional(_)));
+
+                if positional && args.len() <= u8::MAX as usize {
+                    for arg in args {
+                        if let Arg::Positional(value) = arg {
+                            value.emit(compiler);
+                        }
+                    }
+
+                    compiler.add_instruction(Instruction::Call(args.len() as u8));
+                    return;
+                }
+
+                // If any arguments are spread or passed by keyword (or there are
+                // too many to fit in a `Call`), the positional arguments are
+                // collected into a list, and the keyword arguments into a map (or
+                // nil, if there aren't any). The parser only allows keyword
+                // arguments at the end, so they're still evaluated in order.
+                compiler.add_instruction(Instruction::BuildList(0));
+
+                let mut keywords = vec![];
+
+                for arg in args {
+                    match arg {
+                        Arg::Positional(value) => {
+                            value.emit(compiler);
+                            compiler.add_instruction(Instruction::AppendList);
+                        }
+
+                        Arg::Spread(value) => {
+                            value.emit(compiler);
+                            compiler.add_instruction(Instruction::ExtendList);
+                        }
+
+                        Arg::Keyword(name, value) => keywords.push((name, value)),
+                    }
+                }
+
+                if keywords.is_empty() {
+                    compiler.add_instruction(Instruction::LoadNil);
+                } else {
+                    emit_map(compiler, &keywords, |compiler, (name, value)| {
+                        let constant = compiler.add_constant(Value::String(name.to_string()));
+                        compiler.add_instruction(Instruction::LoadConstant(constant));
+                        value.emit(compiler);
+                    });
+                }
+
+                compiler.add_instruction(Instruction::CallUnpacked);
+            }
 
             Expr::UnaryOp(op, val) => {
-                val.emit(chunk);
+                val.emit(compiler);
 
-                chunk.add_instruction(match op {
-                    UnaryOp::Not => unimplemented!(),
+                compiler.add_instruction(match op {
+                    UnaryOp::Not => Instruction::Not,
                     UnaryOp::UnaryMinus => Instruction::Negate,
+                    UnaryOp::BitNot => Instruction::BitNot,
                 });
             }
 
             Expr::BinaryOp(op, lhs, rhs) => match op {
                 BinaryOp::And => {
-                    lhs.emit(chunk);
+                    lhs.emit(compiler);
 
-                    let jump = chunk.add_instruction(Instruction::JumpIfFalse(0));
+                    let jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
 
-                    chunk.add_instruction(Instruction::Pop);
-                    rhs.emit(chunk);
+                    compiler.add_instruction(Instruction::Pop);
+                    rhs.emit(compiler);
 
-                    chunk.patch_jump(jump);
+                    compiler.patch_jump(jump);
                 }
 
                 BinaryOp::Or => {
-                    lhs.emit(chunk);
+                    lhs.emit(compiler);
+
+                    let jump = compiler.add_instruction(Instruction::JumpIfTrue(0));
+
+                    compiler.add_instruction(Instruction::Pop);
+                    rhs.emit(compiler);
+
+                    compiler.patch_jump(jump)
+                }
 
-                    let jump = chunk.add_instruction(Instruction::JumpIfTrue(0));
+                BinaryOp::Equals => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Equal);
+                }
 
-                    chunk.add_instruction(Instruction::Pop);
-                    rhs.emit(chunk);
+                BinaryOp::NotEquals => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Equal);
+                    compiler.add_instruction(Instruction::Not);
+                }
 
-                    chunk.patch_jump(jump)
+                BinaryOp::GreaterThan => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Greater);
                 }
 
-                BinaryOp::Equals => unimplemented!(),
-                BinaryOp::NotEquals => unimplemented!(),
-                BinaryOp::GreaterThan => unimplemented!(),
-                BinaryOp::GreaterEquals => unimplemented!(),
-                BinaryOp::LessThan => unimplemented!(),
-                BinaryOp::LessEquals => unimplemented!(),
+                BinaryOp::GreaterEquals => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::GreaterEqual);
+                }
+
+                BinaryOp::LessThan => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Less);
+                }
+
+                BinaryOp::LessEquals => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::LessEqual);
+                }
 
                 BinaryOp::Add => {
-                    lhs.emit(chunk);
-                    rhs.emit(chunk);
-                    chunk.add_instruction(Instruction::Add);
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Add);
                 }
 
                 BinaryOp::Subtract => {
-                    lhs.emit(chunk);
-                    rhs.emit(chunk);
-                    chunk.add_instruction(Instruction::Subtract);
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Subtract);
                 }
 
                 BinaryOp::Multiply => {
-                    lhs.emit(chunk);
-                    rhs.emit(chunk);
-                    chunk.add_instruction(Instruction::Multiply);
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Multiply);
                 }
 
                 BinaryOp::Divide => {
-                    lhs.emit(chunk);
-                    rhs.emit(chunk);
-                    chunk.add_instruction(Instruction::Divide);
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Divide);
+                }
+
+                BinaryOp::Modulo => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Modulo);
+                }
+
+                BinaryOp::Power => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::Power);
+                }
+
+                BinaryOp::BitAnd => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::BitAnd);
+                }
+
+                BinaryOp::BitOr => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::BitOr);
+                }
+
+                BinaryOp::BitXor => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::BitXor);
+                }
+
+                BinaryOp::ShiftLeft => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::ShiftLeft);
+                }
+
+                BinaryOp::ShiftRight => {
+                    lhs.emit(compiler);
+                    rhs.emit(compiler);
+                    compiler.add_instruction(Instruction::ShiftRight);
                 }
             },
         }
@@ -146,74 +1017,269 @@ impl Emit for Expr {
 }
 
 impl Emit for Stmt {
-    fn emit(&self, chunk: &mut Chunk) {
+    fn emit(&self, compiler: &mut Compiler) {
         match self {
-            Stmt::Return(_) => unimplemented!(),
+            Stmt::Return(value) => {
+                value.emit(compiler);
+                compiler.emit_return();
+            }
 
             Stmt::ExprStmt(e) => {
-                e.emit(chunk);
-                chunk.add_instruction(Instruction::Pop);
+                e.emit(compiler);
+                compiler.add_instruction(Instruction::Pop);
             }
 
-            Stmt::Declaration(name, value) => {
-                value.emit(chunk);
+            Stmt::Declaration(name, value) => match value {
+                // Local functions are declared before their body is compiled, so
+                // that they can call themselves.
+                Expr::Function(params, body) if !compiler.is_global_scope() => {
+                    compiler.add_instruction(Instruction::LoadNil);
+                    let slot = compiler.top_slot();
+                    compiler.add_local(name, slot);
+
+                    emit_function(compiler, Some(name), params, body);
+                    compiler.add_instruction(Instruction::StoreLocal(slot));
+                    compiler.add_instruction(Instruction::Pop);
+                }
 
-                let constant = chunk.add_constant(Value::String(name.clone()));
-                chunk.add_instruction(Instruction::DefineGlobal(constant));
-            }
+                Expr::Function(params, body) => {
+                    emit_function(compiler, Some(name), params, body);
+                    emit_define(compiler, name);
+                }
+
+                _ => {
+                    value.emit(compiler);
+                    emit_define(compiler, name);
+                }
+            },
 
             Stmt::If(condition, when_true, when_false) => {
-                condition.emit(chunk);
+                condition.emit(compiler);
 
-                let else_jump = chunk.add_instruction(Instruction::JumpIfFalse(0));
+                let else_jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
 
-                chunk.add_instruction(Instruction::Pop);
+                compiler.add_instruction(Instruction::Pop);
 
-                when_true.emit(chunk);
+                emit_scoped(compiler, when_true);
 
-                let then_jump = chunk.add_instruction(Instruction::Jump(0));
+                let then_jump = compiler.add_instruction(Instruction::Jump(0));
 
-                chunk.patch_jump(else_jump);
-                chunk.add_instruction(Instruction::Pop);
+                compiler.patch_jump(else_jump);
+                compiler.add_instruction(Instruction::Pop);
 
-                when_false.emit(chunk);
+                emit_scoped(compiler, when_false);
 
-                chunk.patch_jump(then_jump);
+                compiler.patch_jump(then_jump);
             }
 
             Stmt::While(condition, body) => {
-                let loop_start = chunk.next_instruction();
+                let loop_start = compiler.next_instruction();
+
+                condition.emit(compiler);
+
+                let exit_jump = compiler.add_instruction(Instruction::JumpIfFalse(0));
+
+                compiler.add_instruction(Instruction::Pop);
+
+                emit_scoped(compiler, body);
+
+                compiler.emit_loop(loop_start);
+                compiler.patch_jump(exit_jump);
+                compiler.add_instruction(Instruction::Pop);
+            }
+
+            // The iterator is kept on the stack while the loop runs, and each
+            // value it produces is pushed above it, becoming the loop variable.
+            Stmt::For(name, iterable, body) => {
+                iterable.emit(compiler);
+                compiler.add_instruction(Instruction::Iterate);
+
+                let loop_start = compiler.next_instruction();
+
+                compiler.add_instruction(Instruction::IterNext);
+
+                let exit_jump = compiler.add_instruction(Instruction::JumpIfDone(0));
+
+                compiler.begin_scope();
+
+                let slot = compiler.top_slot();
+                compiler.add_local(name, slot);
+
+                for stmt in body {
+                    stmt.emit(compiler);
+                }
+
+                compiler.end_scope();
+
+                compiler.emit_loop(loop_start);
+                compiler.patch_jump(exit_jump);
+                compiler.add_instruction(Instruction::Pop);
+                compiler.add_instruction(Instruction::Pop);
+            }
+
+            Stmt::Yield(value) => {
+                compiler.set_generator();
+
+                // The value that the generator is resumed with is discarded.
+                value.emit(compiler);
+                compiler.add_instruction(Instruction::Yield);
+                compiler.add_instruction(Instruction::Pop);
+            }
+
+            Stmt::Block(stmts) => emit_scoped(compiler, stmts),
+
+            Stmt::Throw(value) => {
+                value.emit(compiler);
+                compiler.add_instruction(Instruction::Throw);
+            }
+
+            Stmt::Try(body, catch, finally) => {
+                compiler.begin_try(finally.clone());
+                emit_scoped(compiler, body);
+                let mut pending = compiler.end_try();
+
+                let exit_jump = compiler.add_instruction(Instruction::Jump(0));
+
+                if let Some((name, catch_body)) = catch {
+                    // The error is on top of the stack when the handler runs, and
+                    // is converted into the value that becomes the catch block's
+                    // first local.
+                    compiler.patch_handlers(&pending);
+                    compiler.add_instruction(Instruction::Catch);
+
+                    if finally.is_some() {
+                        compiler.begin_try(finally.clone());
+                    }
+
+                    compiler.begin_scope();
+                    let slot = compiler.top_slot();
+                    compiler.add_local(name, slot);
+
+                    for stmt in catch_body {
+                        stmt.emit(compiler);
+                    }
+
+                    compiler.end_scope();
+
+                    pending = if finally.is_some() {
+                        compiler.end_try()
+                    } else {
+                        vec![]
+                    };
+                }
+
+                compiler.patch_jump(exit_jump);
+
+                if let Some(finally) = finally {
+                    emit_scoped(compiler, finally);
 
-                condition.emit(chunk);
+                    // If the error wasn't caught (or the catch block raised one
+                    // of its own), the finally block runs before it is rethrown,
+                    // unchanged.
+                    if !pending.is_empty() {
+                        let end_jump = compiler.add_instruction(Instruction::Jump(0));
 
-                let exit_jump = chunk.add_instruction(Instruction::JumpIfFalse(0));
+                        compiler.patch_handlers(&pending);
+                        emit_scoped(compiler, finally);
+                        compiler.add_instruction(Instruction::Rethrow);
 
-                chunk.add_instruction(Instruction::Pop);
+                        compiler.patch_jump(end_jump);
+                    }
+                }
+            }
 
-                body.emit(chunk);
+            Stmt::Import(path, name) => {
+                let constant = compiler.add_constant(Value::String(path.clone()));
+                compiler.add_instruction(Instruction::Import(constant));
 
-                chunk.emit_loop(loop_start);
-                chunk.patch_jump(exit_jump);
-                chunk.add_instruction(Instruction::Pop);
+                emit_define(compiler, name);
             }
 
-            Stmt::Block(_) => unimplemented!(),
+            // Modules are cached after they're first imported, so importing
+            // once per name is cheap.
+            Stmt::ImportFrom(path, names) => {
+                let path = compiler.add_constant(Value::String(path.clone()));
+
+                for name in names {
+                    compiler.add_instruction(Instruction::Import(path));
+
+                    let constant = compiler.add_constant(Value::String(name.clone()));
+                    compiler.add_instruction(Instruction::GetField(constant));
+
+                    emit_define(compiler, name);
+                }
+            }
         }
     }
 }
 
 impl Emit for Vec<Stmt> {
-    fn emit(&self, chunk: &mut Chunk) {
+    fn emit(&self, compiler: &mut Compiler) {
         for stmt in self {
-            stmt.emit(chunk)
+            stmt.emit(compiler)
+        }
+    }
+}
+
+fn jump_offset(distance: usize) -> Result<u16, RuntimeError> {
+    if distance > u16::MAX as usize {
+        return Err(RuntimeError::CompileError {
+            reason: "a jump cannot span more than 65535 instructions".to_string(),
+        });
+    }
+
+    Ok(distance as u16)
+}
+
+/// A constant that can be shared between the instructions that use it.
+///
+/// Floats are compared by their bits, and are kept separate from integers, so
+/// that constants are only shared when they're exactly the same value.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(u64),
+    String(String),
+}
+
+impl ConstantKey {
+    fn from_value(value: &Value) -> Option<ConstantKey> {
+        match value {
+            Value::Nil => Some(ConstantKey::Nil),
+            Value::Boolean(v) => Some(ConstantKey::Boolean(*v)),
+            Value::Integer(v) => Some(ConstantKey::Integer(*v)),
+            Value::Number(v) => Some(ConstantKey::Number(v.to_bits())),
+            Value::String(v) => Some(ConstantKey::String(v.clone())),
+            _ => None,
         }
     }
 }
 
+/// An entry in a chunk's exception handler table.
+///
+/// If an error is raised by one of the instructions from `start` up to (but not
+/// including) `end`, the stack is truncated to `stack_depth` values (relative to
+/// the start of the frame), the error is pushed, and execution continues from
+/// `target`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub stack_depth: usize,
+}
+
 #[derive(Debug)]
 pub struct Chunk {
     constants: Vec<Value>,
     instructions: Vec<Instruction>,
+    handlers: Vec<Handler>,
+
+    /// The index of each constant that has already been added, so that names
+    /// and literals which are used more than once only take up one slot.
+    interned: HashMap<ConstantKey, u16>,
 }
 
 impl Chunk {
@@ -221,6 +1287,8 @@ impl Chunk {
         Chunk {
             constants: vec![],
             instructions: vec![],
+            handlers: vec![],
+            interned: HashMap::new(),
         }
     }
 
@@ -250,44 +1318,87 @@ impl Chunk {
         self.instructions.len()
     }
 
-    pub fn patch_jump(&mut self, addr: usize) {
+    pub fn patch_jump(&mut self, addr: usize) -> Result<(), RuntimeError> {
         let next = self.next_instruction();
 
         match self.get_instruction_mut(addr) {
             Instruction::Jump(old)
             | Instruction::JumpIfTrue(old)
-            | Instruction::JumpIfFalse(old) => {
-                *old = (next - addr) as u8 - 1;
+            | Instruction::JumpIfFalse(old)
+            | Instruction::JumpIfDone(old) => {
+                *old = jump_offset(next - addr - 1)?;
             }
             other => panic!("{:?} is not a jump instruction", other),
         }
+
+        Ok(())
     }
 
-    pub fn emit_loop(&mut self, target: usize) {
+    pub fn emit_loop(&mut self, target: usize) -> Result<(), RuntimeError> {
         let next = self.next_instruction();
+        let offset = jump_offset(next - target + 1)?;
 
-        self.add_instruction(Instruction::Loop((next - target) as u8 + 1));
+        self.add_instruction(Instruction::Loop(offset));
+        Ok(())
     }
 
-    pub fn add_constant(&mut self, value: Value) -> u8 {
+    /// Adds a constant to the chunk, reusing the existing slot if the same
+    /// literal or name has been added before.
+    pub fn add_constant(&mut self, value: Value) -> Result<u16, RuntimeError> {
+        let key = ConstantKey::from_value(&value);
+
+        if let Some(&i) = key.as_ref().and_then(|key| self.interned.get(key)) {
+            return Ok(i);
+        }
+
         let i = self.constants.len();
 
-        if i >= u8::max_value() as usize {
-            panic!("Chunks cannot contain more than 255 constants.");
+        if i > u16::MAX as usize {
+            return Err(RuntimeError::CompileError {
+                reason: "a function cannot contain more than 65536 constants".to_string(),
+            });
         }
 
         self.constants.push(value);
-        i as u8
+
+        if let Some(key) = key {
+            self.interned.insert(key, i as u16);
+        }
+
+        Ok(i as u16)
     }
 
-    pub fn get_constant(&self, idx: u8) -> &Value {
+    pub fn get_constant(&self, idx: u16) -> &Value {
         &self.constants[idx as usize]
     }
 
-    pub fn emit<T>(&mut self, node: &T)
-    where
-        T: Emit,
-    {
-        node.emit(self)
+    pub fn add_handler(&mut self, handler: Handler) -> usize {
+        self.handlers.push(handler);
+        self.handlers.len() - 1
+    }
+
+    /// Points a handler at the next instruction.
+    pub fn patch_handler(&mut self, idx: usize) {
+        self.handlers[idx].target = self.next_instruction();
+    }
+
+    pub fn get_handler(&self, idx: usize) -> &Handler {
+        &self.handlers[idx]
+    }
+
+    /// Finds the innermost handler that covers an instruction.
+    ///
+    /// Handlers are added once the code they cover has been compiled, so inner
+    /// handlers always come before the ones that enclose them.
+    pub fn find_handler(&self, addr: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| handler.start <= addr && addr < handler.end)
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Chunk {
+        Chunk::new()
     }
 }
@@ -0,0 +1,517 @@
+use hashbrown::HashMap;
+
+use ein_syntax::ast::{Param, Stmt};
+
+use crate::bytecode::{Chunk, Emit, Handler, Instruction};
+use crate::function::{Function, Parameter, UpvalueSource};
+use crate::{RuntimeError, Value};
+
+/// A local variable, stored in a stack slot of the function that declared it.
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    slot: usize,
+    captured: bool,
+}
+
+/// A `try` block that is currently being compiled.
+#[derive(Debug)]
+struct TryBlock {
+    /// The start of the range of instructions that is currently being covered,
+    /// if there is one.
+    start: Option<usize>,
+
+    /// The ranges of instructions that have been covered so far. Finally blocks
+    /// that get run on the way out of a `return` aren't covered, as they're
+    /// outside of the try block in the source code.
+    ranges: Vec<(usize, usize)>,
+
+    stack_depth: usize,
+    finally: Option<Vec<Stmt>>,
+}
+
+/// The state of a function that is currently being compiled.
+#[derive(Debug)]
+struct FunctionState {
+    name: Option<String>,
+    params: Vec<Parameter>,
+    rest: Option<String>,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueSource>,
+    scope_depth: usize,
+
+    /// The number of values that will be on the stack (relative to the start of
+    /// the function's frame) when the next instruction runs.
+    ///
+    /// Blocks can be used as expressions, so a local can be declared while
+    /// temporaries are still on the stack - tracking this allows us to work out
+    /// which slot the local will end up in.
+    stack_depth: usize,
+
+    /// The stack depth at each unpatched jump, which gets restored when the jump
+    /// is patched.
+    jump_depths: HashMap<usize, usize>,
+
+    try_blocks: Vec<TryBlock>,
+
+    generator: bool,
+}
+
+impl FunctionState {
+    fn new(name: Option<String>) -> FunctionState {
+        FunctionState {
+            name,
+            params: vec![],
+            rest: None,
+            chunk: Chunk::new(),
+            locals: vec![],
+            upvalues: vec![],
+            scope_depth: 0,
+
+            // The first slot of each frame holds the function being called.
+            stack_depth: 1,
+
+            jump_depths: HashMap::new(),
+
+            try_blocks: vec![],
+
+            generator: false,
+        }
+    }
+}
+
+/// Where the value of a variable can be found at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
+    Local(u8),
+    Upvalue(u8),
+    Global,
+}
+
+#[derive(Debug)]
+pub struct Compiler {
+    // The function at the end of the list is the one currently being compiled,
+    // and the ones before it enclose it.
+    functions: Vec<FunctionState>,
+
+    /// The first error that was found. Compilation carries on afterwards (with
+    /// placeholder operands), so that the code emitting each node doesn't have
+    /// to check for failure.
+    error: Option<RuntimeError>,
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            functions: vec![FunctionState::new(None)],
+            error: None,
+        }
+    }
+
+    pub fn emit<T>(&mut self, node: &T)
+    where
+        T: Emit + ?Sized,
+    {
+        node.emit(self)
+    }
+
+    /// Finishes compiling the top-level code, returning the resulting chunk, or
+    /// the first error that was found if the program exceeded one of the limits
+    /// of the bytecode.
+    pub fn finish(mut self) -> Result<Chunk, RuntimeError> {
+        self.add_instruction(Instruction::Return);
+
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.functions.pop().unwrap().chunk),
+        }
+    }
+
+    /// Records an error, which will be returned by `finish`.
+    fn error(&mut self, error: RuntimeError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Records that the program exceeded one of the limits of the bytecode.
+    pub(crate) fn too_large(&mut self, reason: &str) {
+        self.error(RuntimeError::CompileError {
+            reason: reason.to_string(),
+        });
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().unwrap()
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    pub fn add_instruction(&mut self, instruction: Instruction) -> usize {
+        let state = self.current_mut();
+
+        state.stack_depth = (state.stack_depth as isize + instruction.stack_effect()) as usize;
+
+        let addr = state.chunk.add_instruction(instruction);
+
+        if let Instruction::Jump(_)
+        | Instruction::JumpIfTrue(_)
+        | Instruction::JumpIfFalse(_)
+        | Instruction::JumpIfDone(_) = state.chunk.get_instruction(addr)
+        {
+            let depth = state.stack_depth;
+            state.jump_depths.insert(addr, depth);
+        }
+
+        addr
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> u16 {
+        match self.current_mut().chunk.add_constant(value) {
+            Ok(constant) => constant,
+            Err(error) => {
+                self.error(error);
+                0
+            }
+        }
+    }
+
+    pub fn next_instruction(&self) -> usize {
+        self.current().chunk.next_instruction()
+    }
+
+    pub fn patch_jump(&mut self, addr: usize) {
+        let state = self.current_mut();
+        let result = state.chunk.patch_jump(addr);
+
+        // Any code between the jump and its target may have left the stack at a
+        // different depth (or been unreachable), so use the depth at the jump.
+        state.stack_depth = state.jump_depths.remove(&addr).unwrap();
+
+        if let Err(error) = result {
+            self.error(error);
+        }
+    }
+
+    pub fn emit_loop(&mut self, target: usize) {
+        if let Err(error) = self.current_mut().chunk.emit_loop(target) {
+            self.error(error);
+        }
+    }
+
+    /// Starts a `try` block, covering the instructions that are emitted until
+    /// `end_try` is called.
+    ///
+    /// If a `finally` block is given, it will be emitted before any `return`
+    /// from inside the try block.
+    pub fn begin_try(&mut self, finally: Option<Vec<Stmt>>) {
+        let start = self.next_instruction();
+        let state = self.current_mut();
+        let stack_depth = state.stack_depth;
+
+        state.try_blocks.push(TryBlock {
+            start: Some(start),
+            ranges: vec![],
+            stack_depth,
+            finally,
+        });
+    }
+
+    /// Ends the innermost `try` block, adding handlers for the instructions it
+    /// covered. The handlers must be pointed at the code that handles the error
+    /// with `patch_handlers`.
+    pub fn end_try(&mut self) -> Vec<usize> {
+        let end = self.next_instruction();
+        let state = self.current_mut();
+        let mut block = state.try_blocks.pop().unwrap();
+
+        if let Some(start) = block.start {
+            block.ranges.push((start, end));
+        }
+
+        let stack_depth = block.stack_depth;
+
+        block
+            .ranges
+            .into_iter()
+            .map(|(start, end)| {
+                state.chunk.add_handler(Handler {
+                    start,
+                    end,
+                    target: 0,
+                    stack_depth,
+                })
+            })
+            .collect()
+    }
+
+    /// Points the given handlers at the next instruction. When they run, the
+    /// error will be on top of the stack, and must either be converted with a
+    /// `Catch` or raised again with a `Rethrow`.
+    pub fn patch_handlers(&mut self, handlers: &[usize]) {
+        let state = self.current_mut();
+
+        for &handler in handlers {
+            state.chunk.patch_handler(handler);
+            state.stack_depth = state.chunk.get_handler(handler).stack_depth + 1;
+        }
+    }
+
+    /// Returns the value on top of the stack from the current function, running
+    /// the finally blocks of any `try` blocks that are being returned from.
+    pub fn emit_return(&mut self) {
+        // Try blocks whose ranges have already been ended are the ones whose
+        // finally blocks are currently being emitted, for an enclosing return.
+        let open: Vec<usize> = (0..self.current().try_blocks.len())
+            .filter(|&i| self.current().try_blocks[i].start.is_some())
+            .collect();
+
+        // Each finally block should only be covered by the try blocks that
+        // enclose it, so the range is ended before it is emitted.
+        for &i in open.iter().rev() {
+            let next = self.next_instruction();
+            let block = &mut self.current_mut().try_blocks[i];
+
+            if let Some(start) = block.start.take() {
+                block.ranges.push((start, next));
+            }
+
+            if let Some(finally) = block.finally.clone() {
+                self.emit(&Stmt::Block(finally));
+            }
+        }
+
+        self.add_instruction(Instruction::Return);
+
+        let next = self.next_instruction();
+
+        for i in open {
+            self.current_mut().try_blocks[i].start = Some(next);
+        }
+    }
+
+    /// Marks the current function as a generator.
+    pub fn set_generator(&mut self) {
+        self.current_mut().generator = true;
+    }
+
+    /// The stack slot that the value on top of the stack is stored in.
+    pub fn top_slot(&mut self) -> u8 {
+        let slot = self.current().stack_depth - 1;
+
+        if slot > u8::MAX as usize {
+            self.too_large("a function cannot use more than 256 stack slots");
+            return 0;
+        }
+
+        slot as u8
+    }
+
+    pub fn is_global_scope(&self) -> bool {
+        self.functions.len() == 1 && self.current().scope_depth == 0
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    /// Ends the current scope, popping its locals off of the stack and closing
+    /// any that were captured.
+    pub fn end_scope(&mut self) {
+        self.exit_scope();
+        self.pop_scope();
+    }
+
+    /// Ends the scope of a block expression, whose value is on top of the stack.
+    /// The locals that the block declared are popped from underneath the value,
+    /// closing any that were captured.
+    pub fn end_block_scope(&mut self) {
+        let count = self.scope_locals();
+
+        if count > 0 {
+            self.add_instruction(Instruction::PopBelow(count as u8));
+        }
+
+        self.pop_scope();
+    }
+
+    fn pop_scope(&mut self) {
+        let state = self.current_mut();
+        state.scope_depth -= 1;
+
+        let depth = state.scope_depth;
+        state.locals.retain(|local| local.depth <= depth);
+    }
+
+    /// The number of locals that have been declared in the current scope.
+    fn scope_locals(&self) -> usize {
+        let state = self.current();
+        let depth = state.scope_depth;
+
+        state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth >= depth)
+            .count()
+    }
+
+    /// Emits the instructions that pop the current scope's locals, without
+    /// ending the scope.
+    ///
+    /// This is needed when there is more than one path out of a scope.
+    pub fn exit_scope(&mut self) {
+        let state = self.current();
+        let depth = state.scope_depth;
+
+        let instructions: Vec<_> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth >= depth)
+            .map(|local| {
+                if local.captured {
+                    Instruction::CloseUpvalue
+                } else {
+                    Instruction::Pop
+                }
+            })
+            .collect();
+
+        for instruction in instructions {
+            self.add_instruction(instruction);
+        }
+    }
+
+    /// Declares a local variable in the current scope, stored in the given slot.
+    pub fn add_local(&mut self, name: &str, slot: u8) {
+        let state = self.current_mut();
+        let depth = state.scope_depth;
+
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            slot: slot as usize,
+            captured: false,
+        });
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Variable {
+        let level = self.functions.len() - 1;
+
+        if let Some(i) = self.resolve_local(level, name) {
+            return Variable::Local(self.functions[level].locals[i].slot as u8);
+        }
+
+        match self.resolve_upvalue(level, name) {
+            Some(i) => Variable::Upvalue(i),
+            None => Variable::Global,
+        }
+    }
+
+    fn resolve_local(&self, level: usize, name: &str) -> Option<usize> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<u8> {
+        if level == 0 {
+            return None;
+        }
+
+        let enclosing = level - 1;
+
+        if let Some(i) = self.resolve_local(enclosing, name) {
+            let local = &mut self.functions[enclosing].locals[i];
+            local.captured = true;
+
+            let source = UpvalueSource::Local(local.slot as u8);
+            return Some(self.add_upvalue(level, source));
+        }
+
+        if let Some(i) = self.resolve_upvalue(enclosing, name) {
+            return Some(self.add_upvalue(level, UpvalueSource::Upvalue(i)));
+        }
+
+        None
+    }
+
+    fn add_upvalue(&mut self, level: usize, source: UpvalueSource) -> u8 {
+        let upvalues = &mut self.functions[level].upvalues;
+
+        if let Some(i) = upvalues.iter().position(|u| *u == source) {
+            return i as u8;
+        }
+
+        if upvalues.len() >= u8::MAX as usize {
+            self.too_large("a function cannot capture more than 255 variables");
+            return 0;
+        }
+
+        upvalues.push(source);
+        (upvalues.len() - 1) as u8
+    }
+
+    /// Starts compiling a new function, nested inside the current one.
+    ///
+    /// The parameters are declared as locals, in the slots after the function
+    /// itself, with the rest parameter (if there is one) last. Default values
+    /// are not emitted.
+    pub fn begin_function(&mut self, name: Option<&str>, params: &[Param]) {
+        if params.len() > u8::MAX as usize {
+            self.too_large("a function cannot have more than 255 parameters");
+        }
+
+        let mut state = FunctionState::new(name.map(String::from));
+        state.scope_depth = 1;
+
+        for param in params {
+            match param {
+                Param::Rest(name) => state.rest = Some(name.clone()),
+                other => state.params.push(Parameter {
+                    name: other.name().to_string(),
+                    optional: matches!(other, Param::Optional(_, _)),
+                }),
+            }
+        }
+
+        self.functions.push(state);
+
+        for (i, param) in params.iter().enumerate() {
+            self.add_local(param.name(), (i + 1) as u8);
+        }
+
+        self.current_mut().stack_depth += params.len();
+    }
+
+    /// Finishes compiling the current function, returning to the one that
+    /// encloses it.
+    pub fn end_function(&mut self) -> Function {
+        // If the function didn't return explicitly, it returns nil.
+        self.add_instruction(Instruction::LoadNil);
+        self.add_instruction(Instruction::Return);
+
+        let state = self.functions.pop().unwrap();
+
+        Function {
+            name: state.name,
+            params: state.params,
+            rest: state.rest,
+            chunk: state.chunk,
+            upvalues: state.upvalues,
+            generator: state.generator,
+        }
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::collections::HashMap as StdHashMap;
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::{Key, RuntimeError, Value};
+
+/// A Rust type that can be converted into an Ein value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// A Rust type that can be extracted from an Ein value.
+///
+/// Collections are copied, so changes made to the result are not visible to
+/// scripts.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+fn mismatch(expected: &str, value: &Value) -> RuntimeError {
+    RuntimeError::InvalidOperation {
+        reason: format!("expected {}, but got {}", expected, value),
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Value, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<(), RuntimeError> {
+        match value {
+            Value::Nil => Ok(()),
+            other => Err(mismatch("nil", other)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<bool, RuntimeError> {
+        match value {
+            Value::Boolean(v) => Ok(*v),
+            other => Err(mismatch("a boolean", other)),
+        }
+    }
+}
+
+/// Implements the conversions for integer types that fit in an `i64`.
+///
+/// Converting from a value fails if the integer is out of range for the type.
+macro_rules! integer_impl {
+    ($($t:ty),*) => {
+        $(
+            impl IntoValue for $t {
+                fn into_value(self) -> Value {
+                    Value::Integer(i64::from(self))
+                }
+            }
+
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> Result<$t, RuntimeError> {
+                    match value {
+                        Value::Integer(v) => {
+                            <$t>::try_from(*v).map_err(|_| RuntimeError::IntegerOverflow)
+                        }
+                        other => Err(mismatch("an integer", other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_impl!(i8, i16, i32, i64, u8, u16, u32);
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<f64, RuntimeError> {
+        match value {
+            Value::Integer(v) => Ok(*v as f64),
+            Value::Number(v) => Ok(*v),
+            other => Err(mismatch("a number", other)),
+        }
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Number(f64::from(self))
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<f32, RuntimeError> {
+        f64::from_value(value).map(|v| v as f32)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<String, RuntimeError> {
+        match value {
+            Value::String(v) => Ok(v.clone()),
+            other => Err(mismatch("a string", other)),
+        }
+    }
+}
+
+/// `None` is converted to `nil`, and vice versa.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Option<T>, RuntimeError> {
+        match value {
+            Value::Nil => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let items = self.into_iter().map(IntoValue::into_value).collect();
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, RuntimeError> {
+        match value {
+            Value::List(list) => list.borrow().iter().map(T::from_value).collect(),
+            other => Err(mismatch("a list", other)),
+        }
+    }
+}
+
+impl<T: IntoValue, S> IntoValue for StdHashMap<String, T, S> {
+    fn into_value(self) -> Value {
+        let map = self
+            .into_iter()
+            .map(|(k, v)| (Key::String(k), v.into_value()))
+            .collect::<HashMap<_, _>>();
+
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+/// Only maps where every key is a string can be converted.
+impl<T: FromValue, S: BuildHasher + Default> FromValue for StdHashMap<String, T, S> {
+    fn from_value(value: &Value) -> Result<StdHashMap<String, T, S>, RuntimeError> {
+        match value {
+            Value::Map(map) => map
+                .borrow()
+                .iter()
+                .map(|(k, v)| match k {
+                    Key::String(k) => Ok((k.clone(), T::from_value(v)?)),
+                    other => Err(mismatch("a string key", &Value::from(other.clone()))),
+                })
+                .collect(),
+            other => Err(mismatch("a map", other)),
+        }
+    }
+}
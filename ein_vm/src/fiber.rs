@@ -0,0 +1,136 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::{CallFrame, Closure, Upvalue, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FiberStatus {
+    /// The fiber has been created, but hasn't started running yet.
+    New,
+
+    /// The fiber is currently running.
+    Running,
+
+    /// The fiber has called another fiber, and is waiting for it to yield or
+    /// finish.
+    Waiting,
+
+    /// The fiber has yielded, or transferred control to another fiber, and can
+    /// be resumed.
+    Suspended,
+
+    /// The fiber's function has returned, or raised an error that it didn't
+    /// catch.
+    Done,
+}
+
+/// The execution state of a fiber.
+///
+/// While a fiber is running, its state is moved into the VM, so this is only
+/// populated while the fiber is suspended.
+#[derive(Default)]
+pub(crate) struct FiberState {
+    pub frames: Vec<CallFrame>,
+    pub stack: Vec<Value>,
+    pub open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A lightweight thread of execution, with its own stack and call frames.
+///
+/// Fibers are cooperative - only one runs at a time, and control only passes
+/// between them when they call, yield to or transfer to each other.
+pub struct Fiber {
+    pub(crate) state: RefCell<FiberState>,
+    pub(crate) status: Cell<FiberStatus>,
+
+    /// The fiber that called this one, which it will return to when it yields
+    /// or finishes.
+    pub(crate) caller: RefCell<Option<Rc<Fiber>>>,
+
+    /// Whether the fiber is running a generator function, in which case the
+    /// value it returns is dropped rather than being passed to its caller -
+    /// only the values it yields are part of the iteration.
+    pub(crate) generator: bool,
+}
+
+impl Fiber {
+    /// Creates a fiber that will run the given function when it is first
+    /// resumed.
+    pub fn new(closure: Rc<Closure>) -> Fiber {
+        Fiber::with_args(closure, vec![], vec![])
+    }
+
+    /// Creates a fiber that runs a generator function, with arguments that have
+    /// already been bound to its parameters.
+    pub(crate) fn generator(closure: Rc<Closure>, args: Vec<Value>, missing: Vec<usize>) -> Fiber {
+        let mut fiber = Fiber::with_args(closure, args, missing);
+        fiber.generator = true;
+        fiber
+    }
+
+    fn with_args(closure: Rc<Closure>, args: Vec<Value>, missing: Vec<usize>) -> Fiber {
+        let mut stack = vec![Value::Closure(closure.clone())];
+        stack.extend(args);
+
+        let state = FiberState {
+            stack,
+            frames: vec![CallFrame {
+                closure,
+                pc: 0,
+                base: 0,
+                missing,
+            }],
+            open_upvalues: vec![],
+        };
+
+        Fiber {
+            state: RefCell::new(state),
+            status: Cell::new(FiberStatus::New),
+            caller: RefCell::new(None),
+            generator: false,
+        }
+    }
+
+    /// Creates the fiber that code passed to `VirtualMachine::run` runs on.
+    pub(crate) fn root() -> Fiber {
+        Fiber {
+            state: RefCell::new(FiberState::default()),
+            status: Cell::new(FiberStatus::Running),
+            caller: RefCell::new(None),
+            generator: false,
+        }
+    }
+
+    pub fn status(&self) -> FiberStatus {
+        self.status.get()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.status.get() == FiberStatus::Done
+    }
+}
+
+impl Debug for Fiber {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Fiber")
+            .field("status", &self.status.get())
+            .finish()
+    }
+}
+
+// Closures that were created on the fiber may outlive it, so any variables they
+// captured from its stack need to be closed.
+impl Drop for Fiber {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+
+        for upvalue in state.open_upvalues.drain(..) {
+            let mut upvalue = upvalue.borrow_mut();
+
+            if let Upvalue::Open(_, slot) = *upvalue {
+                *upvalue = Upvalue::Closed(state.stack[slot].clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::{Rc, Weak};
+
+use hashbrown::HashMap;
+
+use crate::{natives, Chunk, Fiber, Key, Module, RuntimeError, Value, VirtualMachine};
+
+/// Describes where a closure should capture one of its upvalues from, when it
+/// gets created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpvalueSource {
+    /// A local variable in the enclosing function's stack frame.
+    Local(u8),
+
+    /// One of the enclosing function's own upvalues.
+    Upvalue(u8),
+}
+
+/// A named parameter of a compiled function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+
+    /// Whether the parameter has a default value, which the function evaluates
+    /// itself if no argument is given.
+    pub optional: bool,
+}
+
+/// A compiled function, not yet bound to any captured variables.
+#[derive(Debug)]
+pub struct Function {
+    pub name: Option<String>,
+    pub params: Vec<Parameter>,
+
+    /// The name of the parameter that collects any extra positional arguments
+    /// into a list, if there is one.
+    pub rest: Option<String>,
+
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueSource>,
+
+    /// Whether the function contains a `yield`. Calling a generator returns an
+    /// iterator, which runs the function's body as values are requested.
+    pub generator: bool,
+}
+
+impl Function {
+    /// The number of parameters that must be given an argument.
+    pub fn required(&self) -> usize {
+        self.params.iter().filter(|param| !param.optional).count()
+    }
+
+    /// Describes the function's parameters, for use in error messages - e.g.
+    /// `add(a, b?, ...rest)`.
+    pub fn signature(&self) -> String {
+        let mut params: Vec<String> = self
+            .params
+            .iter()
+            .map(|param| match param.optional {
+                true => format!("{}?", param.name),
+                false => param.name.clone(),
+            })
+            .collect();
+
+        if let Some(rest) = &self.rest {
+            params.push(format!("...{}", rest));
+        }
+
+        format!(
+            "{}({})",
+            self.name.as_deref().unwrap_or("fn"),
+            params.join(", ")
+        )
+    }
+
+    /// Matches up the arguments of a call with the function's parameters,
+    /// returning the values of the parameters in order (followed by the rest
+    /// parameter, if there is one).
+    ///
+    /// The indices of the optional parameters that weren't given an argument
+    /// are also returned - they're set to nil, and the function will replace
+    /// them with their default values.
+    pub(crate) fn bind_args(
+        &self,
+        mut args: Vec<Value>,
+        keywords: Option<HashMap<Key, Value>>,
+    ) -> Result<(Vec<Value>, Vec<usize>), RuntimeError> {
+        let found = args.len();
+        let has_keywords = keywords.is_some();
+
+        let rest = if args.len() > self.params.len() {
+            if self.rest.is_none() {
+                return Err(self.arity_error(found));
+            }
+
+            args.split_off(self.params.len())
+        } else {
+            vec![]
+        };
+
+        let mut values: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        values.resize(self.params.len(), None);
+
+        for (key, value) in keywords.into_iter().flatten() {
+            let name = Value::from(key).stringify();
+
+            let i = self
+                .params
+                .iter()
+                .position(|param| param.name == name)
+                .ok_or_else(|| self.argument_error(format!("there is no parameter {}", name)))?;
+
+            if values[i].is_some() {
+                return Err(self.argument_error(format!("{} was given more than once", name)));
+            }
+
+            values[i] = Some(value);
+        }
+
+        let mut missing = vec![];
+
+        for (i, (param, value)) in self.params.iter().zip(&values).enumerate() {
+            if value.is_none() {
+                if param.optional {
+                    missing.push(i);
+                } else if has_keywords {
+                    return Err(self.argument_error(format!("{} was not given", param.name)));
+                } else {
+                    return Err(self.arity_error(found));
+                }
+            }
+        }
+
+        let mut values: Vec<Value> = values
+            .into_iter()
+            .map(|value| value.unwrap_or(Value::Nil))
+            .collect();
+
+        if self.rest.is_some() {
+            values.push(Value::List(Rc::new(RefCell::new(rest))));
+        }
+
+        Ok((values, missing))
+    }
+
+    fn arity_error(&self, found: usize) -> RuntimeError {
+        let max = match self.rest {
+            Some(_) => None,
+            None => Some(self.params.len()),
+        };
+
+        natives::arity_error(&self.signature(), self.required(), max, found)
+    }
+
+    fn argument_error(&self, reason: String) -> RuntimeError {
+        RuntimeError::InvalidArguments {
+            signature: self.signature(),
+            reason,
+        }
+    }
+}
+
+/// A variable that has been captured by a closure.
+///
+/// While the variable is still on the stack, the upvalue is 'open', and points
+/// at the variable's slot in the stack of the fiber that declared it. Once the
+/// variable goes out of scope, it gets moved into the upvalue itself, which
+/// 'closes' it.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(Weak<Fiber>, usize),
+    Closed(Value),
+}
+
+/// A function, along with the variables it has captured and the module whose
+/// globals it can access.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub module: Rc<Module>,
+}
+
+/// A method that has been looked up on a value, which will be passed to the
+/// method as its first argument when called.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Value,
+}
+
+pub type NativeFn = dyn Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust.
+pub struct NativeFunction {
+    pub name: String,
+
+    /// The number of arguments the function takes, or `None` if it can take any
+    /// number of arguments.
+    pub arity: Option<usize>,
+
+    pub function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: Option<usize>, function: F) -> NativeFunction
+    where
+        F: Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
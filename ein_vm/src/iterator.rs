@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{Fiber, Key, RuntimeError, Value};
+
+/// The state of an iteration over a value.
+///
+/// Iterators are created by `for` loops, the `iterate` function, the `iter`
+/// methods of lists, ranges and strings, and calls to generator functions.
+#[derive(Debug)]
+pub enum Iter {
+    /// Iterates over the items of a list. Items that are added to the list
+    /// during iteration will be included.
+    List(Rc<RefCell<Vec<Value>>>, usize),
+
+    /// Iterates over the keys of a map, in sorted order. The keys are copied
+    /// when iteration starts.
+    Keys(Vec<Key>, usize),
+
+    Range(i64, i64),
+
+    /// Iterates over the chars of a string, starting from a byte offset.
+    Chars(String, usize),
+
+    /// Runs a generator function on its own fiber, which gets resumed each time
+    /// a value is needed.
+    Generator(Rc<Fiber>),
+
+    /// Calls the `__next` method of a userdata value to get each value, until
+    /// it returns nil.
+    UserData(Value),
+
+    Done,
+}
+
+impl Iter {
+    /// Creates an iterator over a value. If the value is already an iterator, it
+    /// is returned as-is.
+    ///
+    /// Userdata can't be iterated over this way, as its `__iter` and `__next`
+    /// methods have to be called by the VM.
+    pub fn from_value(value: &Value) -> Result<Rc<RefCell<Iter>>, RuntimeError> {
+        let iter = match value {
+            Value::Iterator(iter) => return Ok(iter.clone()),
+            Value::List(list) => Iter::List(list.clone(), 0),
+            Value::Map(map) => {
+                let mut keys: Vec<Key> = map.borrow().keys().cloned().collect();
+                keys.sort();
+                Iter::Keys(keys, 0)
+            }
+            Value::Range(start, end) => Iter::Range(*start, *end),
+            Value::String(s) => Iter::Chars(s.clone(), 0),
+            other => {
+                return Err(RuntimeError::InvalidOperation {
+                    reason: format!("cannot iterate over {}", other),
+                })
+            }
+        };
+
+        Ok(Rc::new(RefCell::new(iter)))
+    }
+
+    /// Returns whether the iterator has run out of values.
+    ///
+    /// This only becomes true once an attempt has been made to get a value past
+    /// the end, as there is no way to know whether a generator will yield again
+    /// without resuming it.
+    pub fn is_done(&self) -> bool {
+        match self {
+            Iter::Generator(fiber) => fiber.is_done(),
+            Iter::Done => true,
+            _ => false,
+        }
+    }
+
+    /// Gets the next value, or returns `None` (and marks the iterator as done)
+    /// if there are no more.
+    ///
+    /// Generators and userdata can't be advanced this way, as they have to be run
+    /// by the VM.
+    pub fn advance(&mut self) -> Option<Value> {
+        let value = match self {
+            Iter::List(list, index) => list.borrow().get(*index).cloned().inspect(|_| {
+                *index += 1;
+            }),
+
+            Iter::Keys(keys, index) => keys.get(*index).cloned().map(|key| {
+                *index += 1;
+                Value::from(key)
+            }),
+
+            Iter::Range(start, end) if *start < *end => {
+                *start += 1;
+                Some(Value::Integer(*start - 1))
+            }
+
+            Iter::Chars(s, offset) => s[*offset..].chars().next().map(|ch| {
+                *offset += ch.len_utf8();
+                Value::String(ch.to_string())
+            }),
+
+            Iter::Generator(fiber) if !fiber.is_done() => {
+                panic!("generators must be resumed by the VM")
+            }
+
+            Iter::UserData(_) => panic!("userdata must be advanced by the VM"),
+
+            _ => None,
+        };
+
+        if value.is_none() {
+            *self = Iter::Done;
+        }
+
+        value
+    }
+}
@@ -1,169 +1,3854 @@
 mod bytecode;
+mod compiler;
+mod convert;
+mod fiber;
+mod function;
+mod iterator;
+mod limits;
 mod macros;
+mod module;
+mod natives;
+#[cfg(feature = "serde")]
+mod serialization;
+mod userdata;
 mod value;
 
+use std::any::TypeId;
+use std::convert::TryFrom;
 use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::mem::size_of;
+use std::path::Path;
+
+use std::cell::{Ref, RefCell};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ein_syntax::parser;
+
+use fiber::FiberState;
 
 use hashbrown::HashMap;
 
-pub use bytecode::{Chunk, Emit, Instruction};
-pub use value::Value;
+use value::compare_integer_number;
+
+pub use bytecode::{Chunk, Emit, Handler, Instruction};
+pub use compiler::Compiler;
+pub use convert::{FromValue, IntoValue};
+pub use fiber::{Fiber, FiberStatus};
+pub use function::{
+    BoundMethod, Closure, Function, NativeFunction, Parameter, Upvalue, UpvalueSource,
+};
+pub use iterator::Iter;
+pub use limits::Limits;
+pub use module::{FileSystemLoader, MemoryLoader, Module, ModuleLoader};
+#[cfg(feature = "serde")]
+pub use serialization::{from_value, to_value, ValueDeserializer, ValueSerializer};
+pub use userdata::{AsAny, Method, UserData};
+pub use value::{Key, Value};
 
+#[derive(Debug)]
 pub enum RuntimeError {
+    InvalidSyntax { reason: String },
+    CompileError { reason: String },
     UndefinedName { name: String },
     InvalidOperation { reason: String },
+    IntegerOverflow,
+    DivisionByZero,
+    InvalidArguments { signature: String, reason: String },
+    NoMatch { value: String },
+    ModuleNotFound { path: String },
+    InvalidModule { path: String, reason: String },
+    ImportCycle { cycle: Vec<String> },
+    Io { reason: String },
+    Thrown { value: Value },
+    InstructionLimitExceeded { limit: u64 },
+    MemoryLimitExceeded { limit: usize },
+    CallDepthExceeded { limit: usize },
+    StackSizeExceeded { limit: usize },
+    Interrupted,
 }
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
+            RuntimeError::InvalidSyntax { reason } => write!(f, "Syntax error: {}", reason),
+            RuntimeError::CompileError { reason } => write!(f, "Compile error: {}", reason),
             RuntimeError::UndefinedName { name } => write!(f, "{} is undefined", name),
             RuntimeError::InvalidOperation { reason } => write!(f, "Invalid operation: {}", reason),
+            RuntimeError::IntegerOverflow => write!(f, "Integer overflow"),
+            RuntimeError::DivisionByZero => write!(f, "Attempted to divide by zero"),
+            RuntimeError::InvalidArguments { signature, reason } => {
+                write!(f, "Invalid arguments to {}: {}", signature, reason)
+            }
+            RuntimeError::NoMatch { value } => write!(f, "No match arm matched {}", value),
+            RuntimeError::ModuleNotFound { path } => write!(f, "Could not find module {}", path),
+            RuntimeError::InvalidModule { path, reason } => {
+                write!(f, "Could not load module {}: {}", path, reason)
+            }
+            RuntimeError::ImportCycle { cycle } => {
+                write!(f, "Import cycle detected: {}", cycle.join(" -> "))
+            }
+            RuntimeError::Io { reason } => write!(f, "IO error: {}", reason),
+            RuntimeError::Thrown { value } => write!(f, "Uncaught exception: {}", value),
+            RuntimeError::InstructionLimitExceeded { limit } => {
+                write!(f, "Exceeded the limit of {} instructions", limit)
+            }
+            RuntimeError::MemoryLimitExceeded { limit } => {
+                write!(f, "Exceeded the memory limit of {} bytes", limit)
+            }
+            RuntimeError::CallDepthExceeded { limit } => {
+                write!(f, "Exceeded the maximum call depth of {}", limit)
+            }
+            RuntimeError::StackSizeExceeded { limit } => {
+                write!(f, "Exceeded the maximum stack size of {} values", limit)
+            }
+            RuntimeError::Interrupted => write!(f, "Interrupted"),
         }
     }
 }
 
-fn is_falsey(value: &Value) -> bool {
-    match value {
-        Value::Nil | Value::Boolean(false) => true,
-        _ => false,
+impl std::error::Error for RuntimeError {}
+
+impl RuntimeError {
+    fn kind(&self) -> &'static str {
+        match self {
+            RuntimeError::InvalidSyntax { .. } => "InvalidSyntax",
+            RuntimeError::CompileError { .. } => "CompileError",
+            RuntimeError::UndefinedName { .. } => "UndefinedName",
+            RuntimeError::InvalidOperation { .. } => "InvalidOperation",
+            RuntimeError::IntegerOverflow => "IntegerOverflow",
+            RuntimeError::DivisionByZero => "DivisionByZero",
+            RuntimeError::InvalidArguments { .. } => "InvalidArguments",
+            RuntimeError::NoMatch { .. } => "NoMatch",
+            RuntimeError::ModuleNotFound { .. } => "ModuleNotFound",
+            RuntimeError::InvalidModule { .. } => "InvalidModule",
+            RuntimeError::ImportCycle { .. } => "ImportCycle",
+            RuntimeError::Io { .. } => "Io",
+            RuntimeError::Thrown { .. } => "Thrown",
+            RuntimeError::InstructionLimitExceeded { .. } => "InstructionLimitExceeded",
+            RuntimeError::MemoryLimitExceeded { .. } => "MemoryLimitExceeded",
+            RuntimeError::CallDepthExceeded { .. } => "CallDepthExceeded",
+            RuntimeError::StackSizeExceeded { .. } => "StackSizeExceeded",
+            RuntimeError::Interrupted => "Interrupted",
+        }
+    }
+
+    /// Returns whether the error was raised to stop the script, either because it
+    /// exceeded one of the VM's limits or because it was interrupted.
+    ///
+    /// These can't be caught by scripts, as that would allow them to keep running.
+    fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            RuntimeError::InstructionLimitExceeded { .. }
+                | RuntimeError::MemoryLimitExceeded { .. }
+                | RuntimeError::CallDepthExceeded { .. }
+                | RuntimeError::StackSizeExceeded { .. }
+                | RuntimeError::Interrupted
+        )
+    }
+
+    /// Converts the error into the value that a `catch` block receives.
+    ///
+    /// Values raised by `throw` are passed through as-is - other errors become
+    /// a map with a `kind` (the name of the error variant) and a `message`.
+    fn into_value(self) -> Value {
+        match self {
+            RuntimeError::Thrown { value } => value,
+            other => {
+                let mut map = HashMap::new();
+
+                map.insert(
+                    Key::String("kind".to_string()),
+                    Value::String(other.kind().to_string()),
+                );
+
+                map.insert(
+                    Key::String("message".to_string()),
+                    Value::String(other.to_string()),
+                );
+
+                Value::Map(Rc::new(RefCell::new(map)))
+            }
+        }
+    }
+}
+
+/// An error that has been caught by a handler, but not yet converted into the
+/// value that a `catch` block receives.
+///
+/// This is kept on the stack while a `finally` block runs, so that if there's
+/// no `catch` block, the original error can be raised again once it finishes.
+struct PendingError(Option<RuntimeError>);
+
+impl UserData for PendingError {
+    fn type_name(&self) -> &str {
+        "error"
+    }
+}
+
+impl PendingError {
+    fn take(value: Value) -> RuntimeError {
+        match value
+            .downcast_mut::<PendingError>()
+            .and_then(|mut e| e.0.take())
+        {
+            Some(error) => error,
+            None => panic!("{} is not a pending error", value),
+        }
+    }
+}
+
+fn fiber_error(reason: &str) -> RuntimeError {
+    RuntimeError::InvalidOperation {
+        reason: reason.to_string(),
     }
 }
 
+/// Gets the fiber that an open upvalue points into, when it isn't the one that
+/// is currently running.
+fn suspended_fiber(fiber: &Weak<Fiber>) -> Rc<Fiber> {
+    // Fibers close their upvalues when they're dropped, so this can't fail.
+    fiber
+        .upgrade()
+        .expect("open upvalue refers to a fiber that no longer exists")
+}
+
+fn is_falsey(value: &Value) -> bool {
+    matches!(value, Value::Nil | Value::Boolean(false))
+}
+
 fn is_truthy(value: &Value) -> bool {
     !is_falsey(value)
 }
 
-pub struct VirtualMachine {
+/// Raises `lhs` to the power of `rhs`.
+///
+/// Raising an integer to a non-negative integer power produces an integer (or an
+/// error, if it overflows). Everything else is done in floating point.
+fn power(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Integer(a), Value::Integer(b)) if b >= 0 => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_pow(b))
+            .map(Value::Integer)
+            .ok_or(RuntimeError::IntegerOverflow),
+        (Value::Integer(a), Value::Integer(b)) => Ok(Value::Number((a as f64).powf(b as f64))),
+        (Value::Integer(a), Value::Number(b)) => Ok(Value::Number((a as f64).powf(b))),
+        (Value::Number(a), Value::Integer(b)) => Ok(Value::Number(a.powf(b as f64))),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.powf(b))),
+        (other_a, other_b) => Err(RuntimeError::InvalidOperation {
+            reason: format!("cannot raise {} to the power of {}", other_a, other_b),
+        }),
+    }
+}
+
+/// Estimates the number of bytes that a value returned by a native function
+/// has allocated on the heap.
+///
+/// Strings are always counted, as they're copied whenever they're passed
+/// around. Other objects are only counted (along with their contents) if
+/// nothing else refers to them yet, so that natives which return an existing
+/// list or map aren't charged for it again.
+fn heap_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::List(list) if Rc::strong_count(list) == 1 => {
+            let items = list.borrow();
+            items.len() * size_of::<Value>() + items.iter().map(heap_size).sum::<usize>()
+        }
+        Value::Map(map) if Rc::strong_count(map) == 1 => map
+            .borrow()
+            .iter()
+            .map(|(key, value)| {
+                let key_size = match key {
+                    Key::String(s) => s.len(),
+                    _ => 0,
+                };
+
+                size_of::<Key>() + size_of::<Value>() + key_size + heap_size(value)
+            })
+            .sum(),
+        Value::Fiber(fiber) if Rc::strong_count(fiber) == 1 => size_of::<Fiber>(),
+        Value::Iterator(iter) if Rc::strong_count(iter) == 1 => size_of::<Iter>(),
+        _ => 0,
+    }
+}
+
+fn map_key(value: &Value) -> Result<Key, RuntimeError> {
+    Key::from_value(value).ok_or_else(|| RuntimeError::InvalidOperation {
+        reason: format!("{} cannot be used as a map key", value),
+    })
+}
+
+fn list_index(list: &[Value], index: &Value) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Integer(i) if *i >= 0 && (*i as usize) < list.len() => Ok(*i as usize),
+        Value::Integer(i) => Err(RuntimeError::InvalidOperation {
+            reason: format!(
+                "index {} is out of bounds for a list of length {}",
+                i,
+                list.len()
+            ),
+        }),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} cannot be used as a list index", other),
+        }),
+    }
+}
+
+fn get_index(target: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match target {
+        Value::List(list) => {
+            let list = list.borrow();
+            let i = list_index(&list, index)?;
+            Ok(list[i].clone())
+        }
+
+        Value::Map(map) => Ok(map
+            .borrow()
+            .get(&map_key(index)?)
+            .cloned()
+            .unwrap_or(Value::Nil)),
+
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} cannot be indexed", other),
+        }),
+    }
+}
+
+fn set_index(target: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let i = list_index(&list, index)?;
+            list[i] = value;
+            Ok(())
+        }
+
+        Value::Map(map) => {
+            map.borrow_mut().insert(map_key(index)?, value);
+            Ok(())
+        }
+
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} cannot be indexed", other),
+        }),
+    }
+}
+
+pub(crate) struct CallFrame {
+    closure: Rc<Closure>,
     pc: usize,
+
+    /// The index of the stack slot that holds the function being called. Its
+    /// arguments and locals are stored in the slots above it.
+    base: usize,
+
+    /// The indices of the optional parameters that weren't given an argument.
+    missing: Vec<usize>,
+}
+
+pub struct VirtualMachine {
+    // The state of the fiber that is currently running. Other fibers keep
+    // their state in the `Fiber` object while they're suspended.
+    frames: Vec<CallFrame>,
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
+
+    /// The upvalues that still point at a slot on the stack.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+
+    /// The fiber that is currently running.
+    fiber: Rc<Fiber>,
+
+    /// The fibers that each active call to `execute` will return in, from the
+    /// outermost to the innermost.
+    executing: Vec<Rc<Fiber>>,
+
+    /// Set when a native function switches to another fiber, in which case its
+    /// result shouldn't be pushed onto the new fiber's stack.
+    fiber_switched: bool,
+
+    /// Globals that are visible from every module, such as native functions.
+    builtins: HashMap<String, Value>,
+
+    /// The module that code passed to `run` is executed in.
+    main: Rc<Module>,
+
+    /// The methods that can be called on lists.
+    list_methods: HashMap<String, Value>,
+
+    /// The methods that can be called on ranges.
+    range_methods: HashMap<String, Value>,
+
+    /// The methods that can be called on strings.
+    string_methods: HashMap<String, Value>,
+
+    /// The methods that can be called on fibers.
+    fiber_methods: HashMap<String, Value>,
+
+    /// The methods that can be called on iterators.
+    iterator_methods: HashMap<String, Value>,
+
+    /// The methods that can be called on each type of userdata, which are
+    /// looked up the first time they're needed.
+    userdata_methods: HashMap<TypeId, HashMap<String, Value>>,
+
+    /// Modules implemented in Rust, keyed by the name they are imported by.
+    native_modules: HashMap<String, Rc<Module>>,
+
+    /// Modules that have been imported, keyed by the identifier that the loader
+    /// resolved them to.
+    modules: HashMap<String, Rc<Module>>,
+
+    /// The modules that are currently being imported, in order, used to detect
+    /// import cycles.
+    importing: Vec<String>,
+
+    loader: Box<dyn ModuleLoader>,
+
+    limits: Limits,
+
+    /// The number of instructions that have been executed during this run.
+    instructions: u64,
+
+    /// The estimated number of bytes that have been allocated during this run.
+    allocated: usize,
+
+    /// Set by the host (possibly from another thread) to stop the running script.
+    interrupt: Arc<AtomicBool>,
 }
 
 impl VirtualMachine {
     pub fn new() -> VirtualMachine {
-        VirtualMachine {
-            pc: 0,
+        let mut vm = VirtualMachine {
+            frames: vec![],
             stack: vec![],
-            globals: HashMap::new(),
-        }
+            open_upvalues: vec![],
+            fiber: Rc::new(Fiber::root()),
+            executing: vec![],
+            fiber_switched: false,
+            builtins: HashMap::new(),
+            main: Rc::new(Module::new("main", None)),
+            list_methods: HashMap::new(),
+            range_methods: HashMap::new(),
+            string_methods: HashMap::new(),
+            fiber_methods: HashMap::new(),
+            iterator_methods: HashMap::new(),
+            userdata_methods: HashMap::new(),
+            native_modules: HashMap::new(),
+            modules: HashMap::new(),
+            importing: vec![],
+            loader: Box::new(MemoryLoader::new()),
+            limits: Limits::default(),
+            instructions: 0,
+            allocated: 0,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        };
+
+        natives::register(&mut vm);
+
+        vm
+    }
+
+    /// Defines a global function that is implemented in Rust.
+    ///
+    /// If `arity` is `None`, the function can be called with any number of
+    /// arguments.
+    pub fn define_native<F>(&mut self, name: &str, arity: Option<usize>, function: F)
+    where
+        F: Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+
+        self.builtins
+            .insert(name.to_string(), Value::NativeFunction(Rc::new(native)));
+    }
+
+    /// Defines a module that scripts can import by its name (e.g. `import "math";`).
+    ///
+    /// These take priority over modules found by the module loader.
+    pub fn define_module(&mut self, module: Module) {
+        self.native_modules
+            .insert(module.name.clone(), Rc::new(module));
+    }
+
+    /// Allows scripts to import the `io` module, which can read from stdin and
+    /// write to stdout and stderr.
+    pub fn grant_io(&mut self) {
+        self.define_module(natives::io::module());
+    }
+
+    /// Allows scripts to import the `fs` module, which can read and write files.
+    pub fn grant_fs(&mut self) {
+        self.define_module(natives::fs::module());
+    }
+
+    /// The globals of the main module.
+    pub fn globals(&self) -> Ref<'_, HashMap<String, Value>> {
+        self.main.globals.borrow()
+    }
+
+    /// Sets the loader that imports are resolved and loaded through.
+    ///
+    /// By default, no modules can be loaded (other than the ones defined with
+    /// `define_module`), so that scripts can't access the disk unless the
+    /// embedder allows it. Use a `FileSystemLoader` to load modules from disk.
+    pub fn set_module_loader<L>(&mut self, loader: L)
+    where
+        L: ModuleLoader + 'static,
+    {
+        self.loader = Box::new(loader);
     }
 
-    pub fn run(&mut self, chunk: &Chunk) -> Result<Option<Value>, RuntimeError> {
-        self.pc = 0;
+    /// Sets the limits on the resources that scripts can use.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Gets a flag that can be set to stop the script that is running, which
+    /// will fail with `RuntimeError::Interrupted`.
+    ///
+    /// The flag is checked whenever a loop repeats or a function is called, and
+    /// is cleared once the script has been stopped. If it's set while no script
+    /// is running, the next one will be stopped as soon as it starts.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    pub fn run(&mut self, chunk: Chunk) -> Result<Option<Value>, RuntimeError> {
+        // If the last run failed, closures that were created during it may still
+        // refer to the stack.
+        self.close_upvalues(0);
+
+        self.frames = vec![];
         self.stack = vec![];
+        self.fiber = Rc::new(Fiber::root());
+        self.executing = vec![];
+        self.importing = vec![];
+        self.reset_usage();
 
-        loop {
-            let instruction = chunk.get_instruction(self.pc);
+        self.run_module(chunk, self.main.clone())
+    }
 
-            println!("[{:04X}] {:?}", self.pc, instruction);
+    /// Starts counting resource usage from zero, as a new call into the VM is
+    /// starting.
+    ///
+    /// The interrupt flag is left alone, so that an interrupt which was
+    /// requested just before the call still stops it.
+    fn reset_usage(&mut self) {
+        self.instructions = 0;
+        self.allocated = 0;
+    }
 
-            self.pc += 1;
+    /// Compiles and runs some source code in the main module.
+    ///
+    /// The source can either be a single expression, in which case its value is
+    /// returned, or a program.
+    pub fn eval(&mut self, source: &str) -> Result<Option<Value>, RuntimeError> {
+        let mut compiler = Compiler::new();
 
-            match instruction {
-                Instruction::Return => {
-                    return Ok(self.stack.last().cloned());
-                }
+        match parser::parse_expr(source) {
+            Ok(expr) => compiler.emit(&expr),
+            Err(_) => {
+                let program =
+                    parser::parse_program(source).map_err(|e| RuntimeError::InvalidSyntax {
+                        reason: e.to_string(),
+                    })?;
 
-                Instruction::Pop => {
-                    self.stack.pop().unwrap();
-                }
+                compiler.emit(&program);
+            }
+        }
 
-                Instruction::LoadNil => {
-                    self.stack.push(Value::Nil);
-                }
+        self.run(compiler.finish()?)
+    }
 
-                Instruction::LoadTrue => {
-                    self.stack.push(Value::Boolean(true));
-                }
+    /// Reads a program from a file and runs it in the main module.
+    pub fn exec_file<P>(&mut self, path: P) -> Result<Option<Value>, RuntimeError>
+    where
+        P: AsRef<Path>,
+    {
+        let source = fs::read_to_string(path).map_err(natives::io::io_error)?;
 
-                Instruction::LoadFalse => {
-                    self.stack.push(Value::Boolean(false));
-                }
+        let program = parser::parse_program(&source).map_err(|e| RuntimeError::InvalidSyntax {
+            reason: e.to_string(),
+        })?;
 
-                Instruction::LoadConstant(i) => {
-                    let constant = chunk.get_constant(*i);
-                    self.stack.push(constant.clone());
-                }
+        let mut compiler = Compiler::new();
+        compiler.emit(&program);
 
-                Instruction::LoadGlobal(i) => {
-                    let constant = chunk.get_constant(*i);
+        self.run(compiler.finish()?)
+    }
 
-                    if let Value::String(name) = constant {
-                        match self.globals.get(name) {
-                            Some(value) => self.stack.push(value.clone()),
-                            None => return Err(RuntimeError::UndefinedName { name: name.clone() }),
-                        }
-                    } else {
-                        panic!("{} is not a valid global name", constant);
-                    }
-                }
+    /// Gets a global variable from the main module, converted to a Rust type.
+    pub fn get_global<T>(&self, name: &str) -> Result<T, RuntimeError>
+    where
+        T: FromValue,
+    {
+        let globals = self.main.globals.borrow();
 
-                Instruction::DefineGlobal(i) => {
-                    let constant = chunk.get_constant(*i);
+        let value = globals
+            .get(name)
+            .ok_or_else(|| RuntimeError::UndefinedName {
+                name: name.to_string(),
+            })?;
 
-                    if let Value::String(name) = constant {
-                        self.globals.insert(name.clone(), self.stack.pop().unwrap());
-                    } else {
-                        panic!("{} is not a valid global name", constant);
-                    }
-                }
+        T::from_value(value)
+    }
 
-                Instruction::StoreGlobal(i) => {
-                    let constant = chunk.get_constant(*i);
+    /// Sets a global variable in the main module.
+    pub fn set_global<T>(&mut self, name: &str, value: T)
+    where
+        T: IntoValue,
+    {
+        self.main.define(name, value.into_value());
+    }
 
-                    if let Value::String(name) = constant {
-                        match self.globals.get_mut(name) {
-                            Some(old_value) => *old_value = self.stack.last().unwrap().clone(),
-                            None => return Err(RuntimeError::UndefinedName { name: name.clone() }),
-                        }
-                    } else {
-                        panic!("{} is not a valid global name", constant);
-                    }
-                }
+    /// Runs the top-level code of a module, returning once it has finished.
+    fn run_module(
+        &mut self,
+        chunk: Chunk,
+        module: Rc<Module>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let function = Function {
+            name: None,
+            params: vec![],
+            rest: None,
+            chunk,
+            upvalues: vec![],
+            generator: false,
+        };
 
-                Instruction::Jump(offset) => {
-                    self.pc += *offset as usize;
-                }
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: vec![],
+            module,
+        });
 
-                Instruction::JumpIfTrue(offset) => {
-                    let val = self.stack.last().unwrap();
+        let depth = self.frames.len();
 
-                    if is_truthy(val) {
-                        self.pc += *offset as usize;
-                    }
-                }
+        self.stack.push(Value::Closure(closure.clone()));
 
-                Instruction::JumpIfFalse(offset) => {
-                    let val = self.stack.last().unwrap();
+        self.frames.push(CallFrame {
+            closure,
+            pc: 0,
+            base: self.stack.len() - 1,
+            missing: vec![],
+        });
 
-                    if is_falsey(val) {
-                        self.pc += *offset as usize;
-                    }
-                }
+        self.execute(self.fiber.clone(), depth)
+    }
 
-                Instruction::Loop(offset) => {
-                    self.pc -= *offset as usize;
-                }
+    /// Imports a module, running it if it hasn't been imported before.
+    fn import(&mut self, path: &str, from: &Module) -> Result<Rc<Module>, RuntimeError> {
+        if let Some(module) = self.native_modules.get(path) {
+            return Ok(module.clone());
+        }
 
-                Instruction::Add => arith_impl!(self, "add", +),
-                Instruction::Subtract => arith_impl!(self, "subtract", -),
-                Instruction::Multiply => arith_impl!(self, "multiply", *),
-                Instruction::Divide => arith_impl!(self, "divide", /),
+        let id = self
+            .loader
+            .resolve(path, from.id.as_deref())
+            .ok_or_else(|| RuntimeError::ModuleNotFound {
+                path: path.to_string(),
+            })?;
 
-                Instruction::Negate => {
-                    let val = self.stack.pop().unwrap();
+        if let Some(module) = self.modules.get(&id) {
+            return Ok(module.clone());
+        }
 
-                    match val {
-                        Value::Number(i) => self.stack.push(Value::Number(-i)),
-                        other => {
-                            return Err(RuntimeError::InvalidOperation {
-                                reason: format!("{} is not a number", other),
-                            })
-                        }
-                    }
+        if let Some(i) = self.importing.iter().position(|p| *p == id) {
+            let mut cycle = self.importing[i..].to_vec();
+            cycle.push(id);
+
+            return Err(RuntimeError::ImportCycle { cycle });
+        }
+
+        let invalid = |reason: String| RuntimeError::InvalidModule {
+            path: id.clone(),
+            reason,
+        };
+
+        let source = self.loader.load(&id).map_err(|e| invalid(e.to_string()))?;
+        let program = parser::parse_program(&source).map_err(|e| invalid(e.to_string()))?;
+
+        let mut compiler = Compiler::new();
+        compiler.emit(&program);
+        let chunk = compiler.finish().map_err(|e| invalid(e.to_string()))?;
+
+        let module = Rc::new(Module::new(parser::module_name(&id), Some(id.clone())));
+
+        self.importing.push(id.clone());
+        let result = self.run_module(chunk, module.clone());
+        self.importing.pop();
+        result?;
+
+        self.modules.insert(id, module.clone());
+
+        Ok(module)
+    }
+
+    /// Calls a function with the given arguments, returning its result.
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let fiber = self.fiber.clone();
+        let depth = self.frames.len();
+        let base = self.stack.len();
+
+        if depth == 0 {
+            self.reset_usage();
+        }
+
+        self.stack.push(callee.clone());
+        self.stack.extend_from_slice(args);
+
+        if let Err(error) = self.call_value(args.len(), None) {
+            self.stack.truncate(base);
+            return Err(error);
+        }
+
+        if self.frames.len() > depth || !Rc::ptr_eq(&self.fiber, &fiber) {
+            Ok(self.execute(fiber, depth)?.unwrap_or(Value::Nil))
+        } else {
+            Ok(self.stack.pop().unwrap())
+        }
+    }
+
+    /// Resumes a fiber, as if it had been called from a script, and runs it until
+    /// it yields or finishes.
+    ///
+    /// Returns the value that was yielded, or the value that the fiber's function
+    /// returned.
+    pub fn resume(&mut self, fiber: &Rc<Fiber>, value: Value) -> Result<Value, RuntimeError> {
+        let caller = self.fiber.clone();
+        let depth = self.frames.len();
+
+        if depth == 0 {
+            self.reset_usage();
+        }
+
+        self.call_fiber(fiber, value)?;
+        self.fiber_switched = false;
+
+        Ok(self.execute(caller, depth)?.unwrap_or(Value::Nil))
+    }
+
+    /// Switches to a fiber that has been called by the current one.
+    fn call_fiber(&mut self, fiber: &Rc<Fiber>, value: Value) -> Result<(), RuntimeError> {
+        match fiber.status() {
+            FiberStatus::New | FiberStatus::Suspended => {}
+            FiberStatus::Done => return Err(fiber_error("cannot call a finished fiber")),
+            _ => return Err(fiber_error("cannot call a fiber that is already running")),
+        }
+
+        fiber.caller.replace(Some(self.fiber.clone()));
+        self.fiber.status.set(FiberStatus::Waiting);
+        self.switch_fiber(fiber.clone(), Some(value));
+
+        Ok(())
+    }
+
+    /// Switches back to the fiber that called the current one.
+    fn yield_fiber(&mut self, value: Value) -> Result<(), RuntimeError> {
+        self.check_can_suspend()?;
+
+        let caller = self
+            .fiber
+            .caller
+            .take()
+            .ok_or_else(|| fiber_error("cannot yield from a fiber that wasn't called"))?;
+
+        self.fiber.status.set(FiberStatus::Suspended);
+        self.switch_fiber(caller, Some(value));
+
+        Ok(())
+    }
+
+    /// Switches to another fiber, without it returning to the current one.
+    fn transfer_fiber(&mut self, fiber: &Rc<Fiber>, value: Value) -> Result<(), RuntimeError> {
+        match fiber.status() {
+            FiberStatus::New | FiberStatus::Suspended => {}
+            FiberStatus::Done => return Err(fiber_error("cannot transfer to a finished fiber")),
+            _ => {
+                return Err(fiber_error(
+                    "cannot transfer to a fiber that is already running",
+                ))
+            }
+        }
+
+        // A fiber that is transferred to can finish without returning control to
+        // the code that resumed it, so this is only allowed when there are no
+        // native functions waiting for a result.
+        if self.executing.len() > 1 {
+            return Err(fiber_error(
+                "cannot transfer to another fiber during a native function call",
+            ));
+        }
+
+        self.check_can_suspend()?;
+
+        self.fiber.status.set(FiberStatus::Suspended);
+        self.switch_fiber(fiber.clone(), Some(value));
+
+        Ok(())
+    }
+
+    /// Checks that the current fiber can be suspended - this isn't possible if a
+    /// native function is waiting on the fiber to return, as there's no way to
+    /// suspend the native function along with it.
+    fn check_can_suspend(&self) -> Result<(), RuntimeError> {
+        if self
+            .executing
+            .iter()
+            .skip(1)
+            .any(|fiber| Rc::ptr_eq(fiber, &self.fiber))
+        {
+            Err(fiber_error(
+                "cannot suspend a fiber during a native function call",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stores the current fiber's state, and starts running another fiber.
+    ///
+    /// If a value is given, it is pushed onto the new fiber's stack - either as
+    /// the argument to its function (if it hasn't started yet and takes one), or
+    /// as the result of the call that suspended it.
+    fn switch_fiber(&mut self, fiber: Rc<Fiber>, value: Option<Value>) {
+        let state = FiberState {
+            frames: std::mem::take(&mut self.frames),
+            stack: std::mem::take(&mut self.stack),
+            open_upvalues: std::mem::take(&mut self.open_upvalues),
+        };
+
+        self.fiber.state.replace(state);
+
+        let state = fiber.state.take();
+        self.frames = state.frames;
+        self.stack = state.stack;
+        self.open_upvalues = state.open_upvalues;
+
+        let starting = fiber.status() == FiberStatus::New;
+        fiber.status.set(FiberStatus::Running);
+        self.fiber = fiber;
+
+        // If the fiber is starting, the value is passed to its function - unless
+        // it has no parameters, or its arguments were supplied up front (as is
+        // done for generators).
+        if let Some(value) = value {
+            if !starting {
+                self.stack.push(value);
+            } else if self.stack.len() == 1 {
+                let function = self.frames[0].closure.function.clone();
+
+                if !function.params.is_empty() || function.rest.is_some() {
+                    // Fibers can only be created from functions that this will
+                    // work for.
+                    let (args, missing) = function
+                        .bind_args(vec![value], None)
+                        .expect("fiber function cannot take an argument");
+
+                    self.stack.extend(args);
+                    self.frames[0].missing = missing;
                 }
             }
         }
+
+        self.fiber_switched = true;
+    }
+
+    /// Calls the value that is on the stack below its arguments.
+    ///
+    /// Script functions get a new call frame, which will be run by the main
+    /// loop. Native functions run immediately, and their result replaces the
+    /// callee and arguments on the stack. Generators don't run at all until
+    /// they're iterated over - the arguments are stored in a new fiber, and an
+    /// iterator for it replaces the callee and arguments.
+    ///
+    /// Keyword arguments can only be passed to script functions.
+    fn call_value(
+        &mut self,
+        arg_count: usize,
+        keywords: Option<HashMap<Key, Value>>,
+    ) -> Result<(), RuntimeError> {
+        self.check_interrupt()?;
+
+        let base = self.stack.len() - arg_count - 1;
+
+        match self.stack[base].clone() {
+            Value::Closure(closure) => {
+                let function = &closure.function;
+
+                // Most calls pass exactly one argument for each parameter, in
+                // which case the arguments are already where they need to be.
+                let missing = if keywords.is_some()
+                    || function.rest.is_some()
+                    || function.params.len() != arg_count
+                {
+                    if function.rest.is_some() {
+                        let extra = arg_count.saturating_sub(function.params.len());
+                        self.allocate(extra * size_of::<Value>())?;
+                    }
+
+                    let args = self.stack.split_off(base + 1);
+                    let (args, missing) = function.bind_args(args, keywords)?;
+                    self.stack.extend(args);
+                    missing
+                } else {
+                    vec![]
+                };
+
+                if function.generator {
+                    let args = self.stack.split_off(base + 1);
+                    self.stack.pop();
+
+                    self.allocate(size_of::<Fiber>() + size_of::<Iter>())?;
+
+                    let fiber = Rc::new(Fiber::generator(closure, args, missing));
+                    let iter = Iter::Generator(fiber);
+                    self.stack
+                        .push(Value::Iterator(Rc::new(RefCell::new(iter))));
+                } else {
+                    if let Some(limit) = self.limits.call_depth {
+                        if self.frames.len() >= limit {
+                            return Err(RuntimeError::CallDepthExceeded { limit });
+                        }
+                    }
+
+                    self.frames.push(CallFrame {
+                        closure,
+                        pc: 0,
+                        base,
+                        missing,
+                    });
+                }
+            }
+
+            Value::NativeFunction(native) => {
+                if keywords.is_some() {
+                    return Err(RuntimeError::InvalidOperation {
+                        reason: format!("{} does not take keyword arguments", native.name),
+                    });
+                }
+
+                if let Some(arity) = native.arity {
+                    if arity != arg_count {
+                        return Err(natives::arity_error(
+                            &native.name,
+                            arity,
+                            Some(arity),
+                            arg_count,
+                        ));
+                    }
+                }
+
+                let args = self.stack.split_off(base + 1);
+                self.stack.pop();
+
+                let result = (native.function)(self, &args)?;
+                self.allocate(heap_size(&result))?;
+
+                // If the function switched fibers, the new fiber has already been
+                // given the value it's expecting.
+                if !std::mem::take(&mut self.fiber_switched) {
+                    self.stack.push(result);
+                }
+            }
+
+            Value::BoundMethod(bound) => {
+                // The receiver is passed implicitly, so it shouldn't be counted
+                // in arity errors.
+                if let Value::NativeFunction(native) = &bound.method {
+                    if let Some(arity) = native.arity {
+                        if arity != arg_count + 1 {
+                            let expected = arity.saturating_sub(1);
+
+                            return Err(natives::arity_error(
+                                &native.name,
+                                expected,
+                                Some(expected),
+                                arg_count,
+                            ));
+                        }
+                    }
+                }
+
+                self.stack[base] = bound.method.clone();
+                self.stack.insert(base + 1, bound.receiver.clone());
+
+                return self.call_value(arg_count + 1, keywords);
+            }
+
+            other => {
+                return Err(RuntimeError::InvalidOperation {
+                    reason: format!("{} is not callable", other),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Gets the upvalue for a stack slot, creating it if the slot has not
+    /// already been captured.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(_, open_slot) = *upvalue.borrow() {
+                if open_slot == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(
+            Rc::downgrade(&self.fiber),
+            slot,
+        )));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Closes all of the upvalues that point at the given stack slot, or any
+    /// slot above it.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+
+            match *upvalue {
+                Upvalue::Open(_, slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    /// Looks up a method of one of the built-in types, and binds it to a value of
+    /// that type.
+    fn bind_method(&mut self, receiver: Value, name: &Value) -> Result<Value, RuntimeError> {
+        let (type_name, methods) = match &receiver {
+            Value::List(_) => ("lists".to_string(), &self.list_methods),
+            Value::Range(..) => ("ranges".to_string(), &self.range_methods),
+            Value::String(_) => ("strings".to_string(), &self.string_methods),
+            Value::Fiber(_) => ("fibers".to_string(), &self.fiber_methods),
+            Value::Iterator(_) => ("iterators".to_string(), &self.iterator_methods),
+            Value::UserData(data) => {
+                let data: &dyn UserData = &*data.borrow();
+                let type_name = format!("{} values", data.type_name());
+
+                (type_name, self.userdata_methods(data))
+            }
+            _ => unreachable!(),
+        };
+
+        let method = match name {
+            Value::String(name) => methods.get(name).cloned(),
+            _ => None,
+        };
+
+        match method {
+            Some(method) => Ok(Value::BoundMethod(Rc::new(BoundMethod {
+                receiver,
+                method,
+            }))),
+            None => Err(RuntimeError::InvalidOperation {
+                reason: format!("{} have no method {}", type_name, name),
+            }),
+        }
+    }
+
+    /// Gets the next value from an iterator.
+    ///
+    /// Generators are resumed instead, in which case `None` is returned - the
+    /// value they yield will be passed back to the current fiber when they
+    /// suspend.
+    fn next_value(&mut self, iter: &RefCell<Iter>) -> Result<Option<Value>, RuntimeError> {
+        let (generator, userdata) = match &*iter.borrow() {
+            Iter::Generator(fiber) if !fiber.is_done() => (Some(fiber.clone()), None),
+            Iter::UserData(value) => (None, Some(value.clone())),
+            _ => (None, None),
+        };
+
+        if let Some(fiber) = generator {
+            self.call_fiber(&fiber, Value::Nil)?;
+            return Ok(None);
+        }
+
+        if let Some(value) = userdata {
+            let value = self.next_userdata(&value)?;
+
+            if value == Value::Nil {
+                *iter.borrow_mut() = Iter::Done;
+            }
+
+            return Ok(Some(value));
+        }
+
+        Ok(Some(iter.borrow_mut().advance().unwrap_or(Value::Nil)))
+    }
+
+    fn get_upvalue(&self, upvalue: &Upvalue) -> Value {
+        match upvalue {
+            Upvalue::Open(fiber, slot) if Weak::as_ptr(fiber) == Rc::as_ptr(&self.fiber) => {
+                self.stack[*slot].clone()
+            }
+            Upvalue::Open(fiber, slot) => {
+                suspended_fiber(fiber).state.borrow().stack[*slot].clone()
+            }
+            Upvalue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_upvalue(&mut self, upvalue: &mut Upvalue, value: Value) {
+        match upvalue {
+            Upvalue::Open(fiber, slot) if Weak::as_ptr(fiber) == Rc::as_ptr(&self.fiber) => {
+                self.stack[*slot] = value
+            }
+            Upvalue::Open(fiber, slot) => {
+                suspended_fiber(fiber).state.borrow_mut().stack[*slot] = value
+            }
+            Upvalue::Closed(old_value) => *old_value = value,
+        }
+    }
+
+    /// Counts an instruction towards the instruction limit, and checks that the
+    /// stack is within its limit.
+    fn check_limits(&mut self) -> Result<(), RuntimeError> {
+        self.instructions += 1;
+
+        if let Some(limit) = self.limits.instructions {
+            if self.instructions > limit {
+                return Err(RuntimeError::InstructionLimitExceeded { limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.stack_size {
+            if self.stack.len() > limit {
+                return Err(RuntimeError::StackSizeExceeded { limit });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_interrupt(&self) -> Result<(), RuntimeError> {
+        if self.interrupt.swap(false, Ordering::SeqCst) {
+            Err(RuntimeError::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts an allocation towards the memory limit.
+    fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.allocated = self.allocated.saturating_add(bytes);
+
+        match self.limits.memory {
+            Some(limit) if self.allocated > limit => {
+                Err(RuntimeError::MemoryLimitExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs instructions until the given fiber is running again, and its number
+    /// of call frames has dropped back to `depth`. Returns the value that was
+    /// returned by the last frame, or passed back by the last fiber to run.
+    ///
+    /// Errors are passed to the innermost exception handler in the frames being
+    /// run - if there isn't one, those frames are discarded and the error is
+    /// returned.
+    fn execute(&mut self, fiber: Rc<Fiber>, depth: usize) -> Result<Option<Value>, RuntimeError> {
+        self.executing.push(fiber.clone());
+
+        let result = loop {
+            match self.dispatch(&fiber, depth) {
+                Ok(value) => break Ok(value),
+                Err(error) => {
+                    if let Err(error) = self.handle_error(error, &fiber, depth) {
+                        break Err(error);
+                    }
+                }
+            }
+        };
+
+        self.executing.pop();
+
+        result
+    }
+
+    /// Unwinds the stack to the innermost handler that covers the instruction
+    /// that raised an error, and jumps to it.
+    ///
+    /// If the error isn't handled by a fiber, the fiber finishes and the error is
+    /// passed on to the fiber that called it.
+    fn handle_error(
+        &mut self,
+        error: RuntimeError,
+        fiber: &Rc<Fiber>,
+        depth: usize,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            if Rc::ptr_eq(&self.fiber, fiber) && self.frames.len() <= depth {
+                return Err(error);
+            }
+
+            if self.frames.is_empty() {
+                self.fiber.status.set(FiberStatus::Done);
+
+                match self.fiber.caller.take() {
+                    Some(caller) => {
+                        self.switch_fiber(caller, None);
+                        self.fiber_switched = false;
+                        continue;
+                    }
+                    None => return Err(error),
+                }
+            }
+
+            let frame = self.frames.last().unwrap();
+            let base = frame.base;
+
+            // The program counter has already moved on to the next instruction.
+            let handler = if error.is_fatal() {
+                None
+            } else {
+                frame
+                    .closure
+                    .function
+                    .chunk
+                    .find_handler(frame.pc - 1)
+                    .copied()
+            };
+
+            if let Some(handler) = handler {
+                let top = base + handler.stack_depth;
+
+                self.close_upvalues(top);
+                self.stack.truncate(top);
+                self.stack.push(Value::user_data(PendingError(Some(error))));
+                self.frame_mut().pc = handler.target;
+
+                return Ok(());
+            }
+
+            self.close_upvalues(base);
+            self.stack.truncate(base);
+            self.frames.pop();
+        }
+    }
+
+    /// Runs instructions until the given fiber is running again with `depth`
+    /// call frames, or an error is raised.
+    fn dispatch(&mut self, fiber: &Rc<Fiber>, depth: usize) -> Result<Option<Value>, RuntimeError> {
+        loop {
+            // Another fiber has passed control back, with a value on the stack.
+            if self.frames.len() == depth && Rc::ptr_eq(&self.fiber, fiber) {
+                return Ok(self.stack.pop());
+            }
+
+            let frame = self.frame_mut();
+            let closure = frame.closure.clone();
+            let base = frame.base;
+            let pc = frame.pc;
+
+            frame.pc += 1;
+
+            self.check_limits()?;
+
+            let chunk = &closure.function.chunk;
+            let instruction = chunk.get_instruction(pc);
+
+            #[cfg(feature = "trace")]
+            println!("[{:04X}] {:?}", pc, instruction);
+
+            match instruction {
+                Instruction::Return => {
+                    let value = if self.stack.len() > base + 1 {
+                        self.stack.pop()
+                    } else {
+                        None
+                    };
+
+                    self.close_upvalues(base);
+                    self.stack.truncate(base);
+                    self.frames.pop();
+
+                    if self.frames.len() == depth && Rc::ptr_eq(&self.fiber, fiber) {
+                        return Ok(value);
+                    }
+
+                    if !self.frames.is_empty() {
+                        self.stack.push(value.unwrap_or(Value::Nil));
+                        continue;
+                    }
+
+                    // The fiber has finished, so control returns to the fiber
+                    // that called it. A generator's return value isn't one of
+                    // the values it produces, so it is dropped.
+                    let value = match value {
+                        Some(value) if !self.fiber.generator => value,
+                        _ => Value::Nil,
+                    };
+                    self.fiber.status.set(FiberStatus::Done);
+
+                    match self.fiber.caller.take() {
+                        Some(caller) => {
+                            self.switch_fiber(caller, Some(value));
+                            self.fiber_switched = false;
+                        }
+                        None => return Ok(Some(value)),
+                    }
+                }
+
+                Instruction::Pop => {
+                    self.stack.pop().unwrap();
+                }
+
+                Instruction::Duplicate(count) => {
+                    let start = self.stack.len() - *count as usize;
+                    self.stack.extend_from_within(start..);
+                }
+
+                Instruction::MoveDown(count) => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.len() - *count as usize;
+                    self.stack.insert(index, value);
+                }
+
+                Instruction::PopBelow(count) => {
+                    let value = self.stack.pop().unwrap();
+                    let start = self.stack.len() - *count as usize;
+
+                    self.close_upvalues(start);
+                    self.stack.truncate(start);
+                    self.stack.push(value);
+                }
+
+                Instruction::LoadNil => {
+                    self.stack.push(Value::Nil);
+                }
+
+                Instruction::LoadTrue => {
+                    self.stack.push(Value::Boolean(true));
+                }
+
+                Instruction::LoadFalse => {
+                    self.stack.push(Value::Boolean(false));
+                }
+
+                Instruction::LoadConstant(i) => {
+                    let constant = chunk.get_constant(*i);
+                    self.stack.push(constant.clone());
+                }
+
+                Instruction::LoadGlobal(i) => {
+                    let constant = chunk.get_constant(*i);
+
+                    if let Value::String(name) = constant {
+                        let value = closure.module.globals.borrow().get(name).cloned();
+
+                        match value.or_else(|| self.builtins.get(name).cloned()) {
+                            Some(value) => self.stack.push(value),
+                            None => return Err(RuntimeError::UndefinedName { name: name.clone() }),
+                        }
+                    } else {
+                        panic!("{} is not a valid global name", constant);
+                    }
+                }
+
+                Instruction::LoadLocal(slot) => {
+                    let value = self.stack[base + *slot as usize].clone();
+                    self.stack.push(value);
+                }
+
+                Instruction::LoadUpvalue(i) => {
+                    let value = self.get_upvalue(&closure.upvalues[*i as usize].borrow());
+                    self.stack.push(value);
+                }
+
+                Instruction::DefineGlobal(i) => {
+                    let constant = chunk.get_constant(*i);
+
+                    if let Value::String(name) = constant {
+                        let value = self.stack.pop().unwrap();
+                        closure
+                            .module
+                            .globals
+                            .borrow_mut()
+                            .insert(name.clone(), value);
+                    } else {
+                        panic!("{} is not a valid global name", constant);
+                    }
+                }
+
+                Instruction::StoreGlobal(i) => {
+                    let constant = chunk.get_constant(*i);
+
+                    if let Value::String(name) = constant {
+                        match closure.module.globals.borrow_mut().get_mut(name) {
+                            Some(old_value) => *old_value = self.stack.last().unwrap().clone(),
+                            None => return Err(RuntimeError::UndefinedName { name: name.clone() }),
+                        }
+                    } else {
+                        panic!("{} is not a valid global name", constant);
+                    }
+                }
+
+                Instruction::StoreLocal(slot) => {
+                    let value = self.stack.last().unwrap().clone();
+                    self.stack[base + *slot as usize] = value;
+                }
+
+                Instruction::StoreUpvalue(i) => {
+                    let value = self.stack.last().unwrap().clone();
+
+                    self.set_upvalue(&mut closure.upvalues[*i as usize].borrow_mut(), value);
+                }
+
+                Instruction::Jump(offset) => {
+                    self.frame_mut().pc += *offset as usize;
+                }
+
+                Instruction::JumpIfTrue(offset) => {
+                    let val = self.stack.last().unwrap();
+
+                    if is_truthy(val) {
+                        self.frame_mut().pc += *offset as usize;
+                    }
+                }
+
+                Instruction::JumpIfFalse(offset) => {
+                    let val = self.stack.last().unwrap();
+
+                    if is_falsey(val) {
+                        self.frame_mut().pc += *offset as usize;
+                    }
+                }
+
+                Instruction::JumpIfDone(offset) => {
+                    let iter = &self.stack[self.stack.len() - 2];
+
+                    if let Value::Iterator(iter) = iter {
+                        if iter.borrow().is_done() {
+                            self.frame_mut().pc += *offset as usize;
+                        }
+                    } else {
+                        panic!("{} is not an iterator", iter);
+                    }
+                }
+
+                Instruction::Loop(offset) => {
+                    self.frame_mut().pc -= *offset as usize;
+                    self.check_interrupt()?;
+                }
+
+                Instruction::Equal => {
+                    let rhs = self.stack.pop().unwrap();
+                    let lhs = self.stack.pop().unwrap();
+                    let result = self.equals(lhs, rhs)?;
+                    self.stack.push(Value::Boolean(result));
+                }
+
+                Instruction::Greater => compare_impl!(self, >, "__lt", swapped),
+                Instruction::GreaterEqual => compare_impl!(self, >=, "__le", swapped),
+                Instruction::Less => compare_impl!(self, <, "__lt"),
+                Instruction::LessEqual => compare_impl!(self, <=, "__le"),
+
+                Instruction::Not => {
+                    let val = self.stack.pop().unwrap();
+                    self.stack.push(Value::Boolean(is_falsey(&val)));
+                }
+
+                Instruction::Add => arith_impl!(self, "add", "__add", +, checked_add),
+                Instruction::Subtract => arith_impl!(self, "subtract", "__sub", -, checked_sub),
+                Instruction::Multiply => arith_impl!(self, "multiply", "__mul", *, checked_mul),
+
+                // Integer division truncates towards zero, like it does in Rust.
+                Instruction::Divide => {
+                    if let [.., Value::Integer(_), Value::Integer(0)] = self.stack.as_slice() {
+                        return Err(RuntimeError::DivisionByZero);
+                    }
+
+                    arith_impl!(self, "divide", "__div", /, checked_div)
+                }
+
+                // The result of an integer modulo takes the sign of the dividend,
+                // matching the truncating behaviour of division.
+                Instruction::Modulo => match self.stack.as_slice() {
+                    [.., Value::Integer(_), Value::Integer(0)] => {
+                        return Err(RuntimeError::DivisionByZero);
+                    }
+
+                    // Every integer is divisible by -1. `checked_rem` treats
+                    // `i64::MIN % -1` as an overflow (as the quotient would
+                    // overflow), but the remainder is still 0.
+                    [.., Value::Integer(_), Value::Integer(-1)] => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.stack.push(Value::Integer(0));
+                    }
+
+                    _ => arith_impl!(self, "modulo", "__mod", %, checked_rem),
+                },
+
+                Instruction::Power => {
+                    let rhs = self.stack.pop().unwrap();
+                    let lhs = self.stack.pop().unwrap();
+
+                    self.stack.push(power(lhs, rhs)?);
+                }
+
+                Instruction::BitAnd => bitwise_impl!(self, "bitwise and", &),
+                Instruction::BitOr => bitwise_impl!(self, "bitwise or", |),
+                Instruction::BitXor => bitwise_impl!(self, "bitwise xor", ^),
+                Instruction::ShiftLeft => shift_impl!(self, checked_shl),
+                Instruction::ShiftRight => shift_impl!(self, checked_shr),
+
+                Instruction::BitNot => {
+                    let val = self.stack.pop().unwrap();
+
+                    match val {
+                        Value::Integer(i) => self.stack.push(Value::Integer(!i)),
+                        other => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!("{} is not an integer", other),
+                            })
+                        }
+                    }
+                }
+
+                Instruction::ToString => {
+                    let val = self.stack.pop().unwrap();
+                    let string = self.stringify(&val)?;
+
+                    self.allocate(string.len())?;
+                    self.stack.push(Value::String(string));
+                }
+
+                Instruction::Concat(count) => {
+                    let start = self.stack.len() - *count as usize;
+                    let mut result = String::new();
+
+                    for part in self.stack.drain(start..) {
+                        match part {
+                            Value::String(s) => result.push_str(&s),
+                            other => panic!("{} is not a string", other),
+                        }
+                    }
+
+                    self.allocate(result.len())?;
+                    self.stack.push(Value::String(result));
+                }
+
+                Instruction::BuildList(count) => {
+                    let start = self.stack.len() - *count as usize;
+                    let items = self.stack.split_off(start);
+
+                    self.allocate(items.len() * size_of::<Value>())?;
+                    self.stack.push(Value::List(Rc::new(RefCell::new(items))));
+                }
+
+                Instruction::BuildMap(count) => {
+                    let start = self.stack.len() - *count as usize * 2;
+                    let mut map = HashMap::with_capacity(*count as usize);
+
+                    let mut entries = self.stack.drain(start..);
+
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        map.insert(map_key(&key)?, value);
+                    }
+
+                    drop(entries);
+
+                    self.allocate(*count as usize * (size_of::<Key>() + size_of::<Value>()))?;
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+
+                Instruction::GetIndex => {
+                    let index = self.stack.pop().unwrap();
+                    let target = self.stack.pop().unwrap();
+
+                    let value = match target {
+                        Value::UserData(_) => self.index_userdata(target, index)?,
+                        _ => get_index(&target, &index)?,
+                    };
+
+                    self.stack.push(value);
+                }
+
+                Instruction::SetIndex => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    let target = self.stack.pop().unwrap();
+
+                    match target {
+                        Value::Map(_) => {
+                            self.allocate(size_of::<Key>() + size_of::<Value>())?;
+                            set_index(&target, &index, value.clone())?;
+                        }
+                        Value::UserData(_) => {
+                            self.set_index_userdata(target, index, value.clone())?
+                        }
+                        _ => set_index(&target, &index, value.clone())?,
+                    }
+
+                    self.stack.push(value);
+                }
+
+                Instruction::GetField(i) => {
+                    let name = chunk.get_constant(*i);
+                    let target = self.stack.pop().unwrap();
+
+                    match target {
+                        Value::Map(_) => self.stack.push(get_index(&target, name)?),
+                        Value::List(_)
+                        | Value::Range(..)
+                        | Value::String(_)
+                        | Value::Fiber(_)
+                        | Value::Iterator(_) => {
+                            let method = self.bind_method(target, name)?;
+                            self.stack.push(method);
+                        }
+                        Value::UserData(ref data) => {
+                            let field = match name {
+                                Value::String(name) => data.borrow().get_field(name),
+                                _ => None,
+                            };
+
+                            match field {
+                                Some(value) => self.stack.push(value),
+                                None => {
+                                    let method = self.bind_method(target, name)?;
+                                    self.stack.push(method);
+                                }
+                            }
+                        }
+                        Value::Module(module) => {
+                            let value = match name {
+                                Value::String(name) => module.globals.borrow().get(name).cloned(),
+                                _ => None,
+                            };
+
+                            match value {
+                                Some(value) => self.stack.push(value),
+                                None => {
+                                    return Err(RuntimeError::UndefinedName {
+                                        name: format!("{}.{}", module.name, name.stringify()),
+                                    })
+                                }
+                            }
+                        }
+                        other => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!("cannot get field {} of {}", name, other),
+                            })
+                        }
+                    }
+                }
+
+                Instruction::SetField(i) => {
+                    let name = chunk.get_constant(*i);
+                    let value = self.stack.pop().unwrap();
+                    let target = self.stack.pop().unwrap();
+
+                    match target {
+                        Value::Map(_) => set_index(&target, name, value.clone())?,
+                        Value::UserData(data) => data
+                            .borrow_mut()
+                            .set_field(&name.stringify(), value.clone())?,
+                        other => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!("cannot set field {} of {}", name, other),
+                            })
+                        }
+                    }
+
+                    self.stack.push(value);
+                }
+
+                Instruction::AppendList => {
+                    let value = self.stack.pop().unwrap();
+                    self.allocate(size_of::<Value>())?;
+
+                    match self.stack.last().unwrap() {
+                        Value::List(list) => list.borrow_mut().push(value),
+                        other => panic!("cannot append to {}", other),
+                    }
+                }
+
+                Instruction::InsertMap => {
+                    let value = self.stack.pop().unwrap();
+                    let key = map_key(&self.stack.pop().unwrap())?;
+                    self.allocate(size_of::<Key>() + size_of::<Value>())?;
+
+                    match self.stack.last().unwrap() {
+                        Value::Map(map) => map.borrow_mut().insert(key, value),
+                        other => panic!("cannot insert into {}", other),
+                    };
+                }
+
+                Instruction::ExtendList => {
+                    let items = match self.stack.pop().unwrap() {
+                        Value::List(items) => items.borrow().clone(),
+                        other => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!("cannot spread {}, as it is not a list", other),
+                            })
+                        }
+                    };
+
+                    self.allocate(items.len() * size_of::<Value>())?;
+
+                    match self.stack.last().unwrap() {
+                        Value::List(list) => list.borrow_mut().extend(items),
+                        other => panic!("cannot extend {}", other),
+                    }
+                }
+
+                Instruction::BuildRange => {
+                    let end = self.stack.pop().unwrap();
+                    let start = self.stack.pop().unwrap();
+
+                    match (start, end) {
+                        (Value::Integer(start), Value::Integer(end)) => {
+                            self.stack.push(Value::Range(start, end))
+                        }
+                        (start, end) => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!(
+                                    "ranges must be between integers, but got {} and {}",
+                                    start, end
+                                ),
+                            })
+                        }
+                    }
+                }
+
+                Instruction::Iterate => {
+                    let value = self.stack.pop().unwrap();
+                    let iter = self.iterate(&value)?;
+
+                    self.allocate(size_of::<Iter>())?;
+                    self.stack.push(Value::Iterator(iter));
+                }
+
+                Instruction::IterNext => {
+                    let iter = match self.stack.last().unwrap() {
+                        Value::Iterator(iter) => iter.clone(),
+                        other => panic!("{} is not an iterator", other),
+                    };
+
+                    match self.next_value(&iter)? {
+                        Some(value) => self.stack.push(value),
+                        None => self.fiber_switched = false,
+                    }
+                }
+
+                Instruction::Yield => {
+                    let value = self.stack.pop().unwrap();
+                    self.yield_fiber(value)?;
+                    self.fiber_switched = false;
+                }
+
+                Instruction::Closure(i) => {
+                    let function = match chunk.get_constant(*i) {
+                        Value::Function(function) => function.clone(),
+                        other => panic!("{} is not a function", other),
+                    };
+
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|source| match source {
+                            UpvalueSource::Local(slot) => {
+                                self.capture_upvalue(base + *slot as usize)
+                            }
+                            UpvalueSource::Upvalue(i) => closure.upvalues[*i as usize].clone(),
+                        })
+                        .collect();
+
+                    let closure = Closure {
+                        function,
+                        upvalues,
+                        module: closure.module.clone(),
+                    };
+
+                    self.allocate(
+                        size_of::<Closure>() + closure.upvalues.len() * size_of::<Value>(),
+                    )?;
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+
+                Instruction::Call(count) => self.call_value(*count as usize, None)?,
+
+                Instruction::CallUnpacked => {
+                    let keywords = match self.stack.pop().unwrap() {
+                        Value::Map(map) => Some(map.take()),
+                        _ => None,
+                    };
+
+                    let args = match self.stack.pop().unwrap() {
+                        Value::List(list) => list.take(),
+                        other => panic!("{} is not a list of arguments", other),
+                    };
+
+                    let count = args.len();
+                    self.stack.extend(args);
+                    self.call_value(count, keywords)?;
+                }
+
+                Instruction::IsMissing(param) => {
+                    let frame = self.frames.last().unwrap();
+                    let missing = frame.missing.contains(&(*param as usize));
+                    self.stack.push(Value::Boolean(missing));
+                }
+
+                Instruction::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop();
+                }
+
+                Instruction::Import(i) => {
+                    let path = match chunk.get_constant(*i) {
+                        Value::String(path) => path.clone(),
+                        other => panic!("{} is not a valid module path", other),
+                    };
+
+                    let module = self.import(&path, &closure.module)?;
+                    self.stack.push(Value::Module(module));
+                }
+
+                Instruction::IsList(len) => {
+                    let val = self.stack.pop().unwrap();
+                    let result =
+                        matches!(&val, Value::List(l) if l.borrow().len() == *len as usize);
+                    self.stack.push(Value::Boolean(result));
+                }
+
+                Instruction::IsListAtLeast(len) => {
+                    let val = self.stack.pop().unwrap();
+                    let result =
+                        matches!(&val, Value::List(l) if l.borrow().len() >= *len as usize);
+                    self.stack.push(Value::Boolean(result));
+                }
+
+                Instruction::IsMap => {
+                    let val = self.stack.pop().unwrap();
+                    self.stack
+                        .push(Value::Boolean(matches!(val, Value::Map(_))));
+                }
+
+                Instruction::HasKey(i) => {
+                    let key = Key::from_value(chunk.get_constant(*i));
+                    let val = self.stack.pop().unwrap();
+
+                    let result = match (val, key) {
+                        (Value::Map(map), Some(key)) => map.borrow().contains_key(&key),
+                        _ => false,
+                    };
+
+                    self.stack.push(Value::Boolean(result));
+                }
+
+                Instruction::SliceList(start) => {
+                    let val = self.stack.pop().unwrap();
+
+                    match val {
+                        Value::List(list) => {
+                            let items = list.borrow()[*start as usize..].to_vec();
+
+                            self.allocate(items.len() * size_of::<Value>())?;
+                            self.stack.push(Value::List(Rc::new(RefCell::new(items))));
+                        }
+                        other => panic!("{} is not a list", other),
+                    }
+                }
+
+                Instruction::Throw => {
+                    let value = self.stack.pop().unwrap();
+                    return Err(RuntimeError::Thrown { value });
+                }
+
+                Instruction::Catch => {
+                    let error = PendingError::take(self.stack.pop().unwrap());
+                    self.stack.push(error.into_value());
+                }
+
+                Instruction::Rethrow => {
+                    return Err(PendingError::take(self.stack.pop().unwrap()));
+                }
+
+                Instruction::NoMatch => {
+                    return Err(RuntimeError::NoMatch {
+                        value: self.stack.last().unwrap().to_string(),
+                    });
+                }
+
+                Instruction::Negate => {
+                    let val = self.stack.pop().unwrap();
+
+                    match val {
+                        Value::Integer(i) => match i.checked_neg() {
+                            Some(i) => self.stack.push(Value::Integer(i)),
+                            None => return Err(RuntimeError::IntegerOverflow),
+                        },
+                        Value::Number(i) => self.stack.push(Value::Number(-i)),
+                        other => {
+                            return Err(RuntimeError::InvalidOperation {
+                                reason: format!("{} is not a number", other),
+                            })
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> VirtualMachine {
+        VirtualMachine::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ein_syntax::parser;
+
+    fn eval(source: &str) -> Result<Value, RuntimeError> {
+        let mut compiler = Compiler::new();
+        compiler.emit(&parser::parse_expr(source).unwrap());
+
+        VirtualMachine::new()
+            .run(compiler.finish()?)
+            .map(|v| v.unwrap())
+    }
+
+    fn run_program(source: &str) -> Result<VirtualMachine, RuntimeError> {
+        run_program_in(VirtualMachine::new(), source)
+    }
+
+    /// Runs a program in a VM that has already been set up (for example, with
+    /// capabilities granted).
+    fn run_program_in(
+        mut vm: VirtualMachine,
+        source: &str,
+    ) -> Result<VirtualMachine, RuntimeError> {
+        let mut compiler = Compiler::new();
+        compiler.emit(&parser::parse_program(source).unwrap());
+
+        vm.run(compiler.finish()?)?;
+        Ok(vm)
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        assert!(matches!(eval("1 + 2"), Ok(Value::Integer(3))));
+        assert!(matches!(eval("1 - 2"), Ok(Value::Integer(-1))));
+        assert!(matches!(eval("3 * 4"), Ok(Value::Integer(12))));
+        assert!(matches!(eval("-(2 * 3)"), Ok(Value::Integer(-6))));
+
+        assert!(matches!(
+            eval("9007199254740993 + 0"),
+            Ok(Value::Integer(9007199254740993))
+        ));
+    }
+
+    #[test]
+    fn integer_division() {
+        assert!(matches!(eval("7 / 2"), Ok(Value::Integer(3))));
+        assert!(matches!(eval("-7 / 2"), Ok(Value::Integer(-3))));
+        assert!(matches!(eval("7 / -2"), Ok(Value::Integer(-3))));
+        assert!(matches!(eval("1 / 0"), Err(RuntimeError::DivisionByZero)));
+    }
+
+    #[test]
+    fn float_promotion() {
+        assert!(matches!(eval("7 / 2.0"), Ok(Value::Number(n)) if n == 3.5));
+        assert!(matches!(eval("1.5 + 1"), Ok(Value::Number(n)) if n == 2.5));
+        assert!(matches!(eval("2 * 1e3"), Ok(Value::Number(n)) if n == 2000.0));
+        assert!(matches!(eval("1 / 0.0"), Ok(Value::Number(n)) if n.is_infinite()));
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert!(matches!(
+            eval("9223372036854775807 + 1"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+
+        assert!(matches!(
+            eval("(-9223372036854775807 - 1) / -1"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+
+        assert!(matches!(
+            eval("-(-9223372036854775807 - 1)"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+
+        assert!(matches!(
+            eval("4294967296 * 4294967296"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+    }
+
+    #[test]
+    fn modulo() {
+        assert!(matches!(eval("7 % 3"), Ok(Value::Integer(1))));
+        assert!(matches!(eval("-7 % 3"), Ok(Value::Integer(-1))));
+        assert!(matches!(eval("7 % -3"), Ok(Value::Integer(1))));
+        assert!(matches!(eval("7.5 % 2"), Ok(Value::Number(n)) if n == 1.5));
+        assert!(matches!(eval("1 % 0"), Err(RuntimeError::DivisionByZero)));
+
+        assert!(matches!(
+            eval("(-9223372036854775807 - 1) % -1"),
+            Ok(Value::Integer(0))
+        ));
+        assert!(matches!(eval("7 % -1"), Ok(Value::Integer(0))));
+    }
+
+    #[test]
+    fn power() {
+        assert!(matches!(eval("2 ** 10"), Ok(Value::Integer(1024))));
+        assert!(matches!(eval("2 ** 3 ** 2"), Ok(Value::Integer(512))));
+        assert!(matches!(eval("-2 ** 2"), Ok(Value::Integer(-4))));
+        assert!(matches!(eval("2 ** -1"), Ok(Value::Number(n)) if n == 0.5));
+        assert!(matches!(eval("4 ** 0.5"), Ok(Value::Number(n)) if n == 2.0));
+        assert!(matches!(
+            eval("2 ** 63"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+    }
+
+    #[test]
+    fn bitwise() {
+        assert!(matches!(eval("6 & 3"), Ok(Value::Integer(2))));
+        assert!(matches!(eval("6 | 3"), Ok(Value::Integer(7))));
+        assert!(matches!(eval("6 ^ 3"), Ok(Value::Integer(5))));
+        assert!(matches!(eval("~0"), Ok(Value::Integer(-1))));
+        assert!(matches!(eval("1 << 4"), Ok(Value::Integer(16))));
+        assert!(matches!(eval("-16 >> 2"), Ok(Value::Integer(-4))));
+
+        for source in &[
+            "1.0 & 1", "1 | true", "~1.5", "1 << 64", "1 >> -1", "1.0 << 1",
+        ] {
+            assert!(
+                matches!(eval(source), Err(RuntimeError::InvalidOperation { .. })),
+                "{} should fail",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let vm = run_program("let x = 10; x += 5; x *= 2; let y = x -= 1; x <<= 1;").unwrap();
+
+        assert!(matches!(vm.globals().get("x"), Some(Value::Integer(58))));
+        assert!(matches!(vm.globals().get("y"), Some(Value::Integer(29))));
+
+        assert!(matches!(
+            run_program("z += 1;"),
+            Err(RuntimeError::UndefinedName { .. })
+        ));
+    }
+
+    #[test]
+    fn place_assignment() {
+        let vm = run_program(
+            "let xs = [[1, 2], [3, 4]]; xs[1][0] = 5; xs[0][1] *= 10;
+             let m = [\"a\": [\"b\": 1]]; m.a.b += 1; m.a.c = 3; m[1.0] = 4;",
+        )
+        .unwrap();
+
+        assert_eq!("[[1, 20], [5, 4]]", vm.globals()["xs"].to_string());
+        assert_eq!(
+            "[1: 4, \"a\": [\"b\": 2, \"c\": 3]]",
+            vm.globals()["m"].to_string()
+        );
+
+        for source in &[
+            "let xs = [1]; xs[1] = 2;",
+            "let xs = [1]; xs.a = 2;",
+            "let m = [:]; m[[]] = 1;",
+            "let x = 1; x[0] = 2;",
+        ] {
+            assert!(
+                matches!(
+                    run_program(source),
+                    Err(RuntimeError::InvalidOperation { .. })
+                ),
+                "{} should fail",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn compound_assignment_evaluates_target_once() {
+        // If the index was evaluated twice, the second `i += 1` would store the
+        // result in `xs[1]`.
+        let vm = run_program("let xs = [1, 2]; let i = 0; xs[i += 1] += 10;").unwrap();
+
+        assert_eq!("[1, 12]", vm.globals()["xs"].to_string());
+        assert!(matches!(vm.globals()["i"], Value::Integer(1)));
+    }
+
+    #[test]
+    fn increment() {
+        let vm = run_program(
+            "let x = 1; let a = x++; let b = ++x; let c = x--; let d = --x;
+             let xs = [1, 2]; let i = 0; let e = xs[i++]++; let f = ++xs[i];
+             let m = [\"n\": 1.5]; let g = m.n--;
+             fn count() { let n = 0; for _ in 0..3 { n++; } return n; }
+             let h = count();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(1), vm.globals()["x"]);
+        assert_eq!(Value::Integer(1), vm.globals()["a"]);
+        assert_eq!(Value::Integer(3), vm.globals()["b"]);
+        assert_eq!(Value::Integer(3), vm.globals()["c"]);
+        assert_eq!(Value::Integer(1), vm.globals()["d"]);
+
+        assert_eq!("[2, 3]", vm.globals()["xs"].to_string());
+        assert_eq!(Value::Integer(1), vm.globals()["e"]);
+        assert_eq!(Value::Integer(3), vm.globals()["f"]);
+        assert_eq!(Value::Integer(1), vm.globals()["i"]);
+
+        assert_eq!("[\"n\": 0.5]", vm.globals()["m"].to_string());
+        assert_eq!(Value::Number(1.5), vm.globals()["g"]);
+
+        assert_eq!(Value::Integer(3), vm.globals()["h"]);
+
+        assert!(matches!(
+            run_program("let s = \"a\"; s++;"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(matches!(eval("1 == 1.0"), Ok(Value::Boolean(true))));
+        assert!(matches!(eval("1 == 1.5"), Ok(Value::Boolean(false))));
+
+        // Integers are compared with floats exactly, rather than being rounded.
+        assert!(matches!(
+            eval("9007199254740993 == 9007199254740992.0"),
+            Ok(Value::Boolean(false))
+        ));
+        assert!(matches!(
+            eval("9007199254740992 == 9007199254740992.0"),
+            Ok(Value::Boolean(true))
+        ));
+        assert!(matches!(
+            eval("9223372036854775807 == 9223372036854775808.0"),
+            Ok(Value::Boolean(false))
+        ));
+
+        // Ordering is exact as well, so it agrees with equality above 2^53.
+        for (source, expected) in &[
+            ("9007199254740993 > 9007199254740992.0", true),
+            ("9007199254740993 >= 9007199254740992.0", true),
+            ("9007199254740993 < 9007199254740992.0", false),
+            ("9007199254740992.0 < 9007199254740993", true),
+            ("9007199254740992.0 <= 9007199254740992", true),
+            ("9007199254740992.0 > 9007199254740992", false),
+            ("9223372036854775807 < 9223372036854775808.0", true),
+            ("(-9223372036854775807 - 1) <= -9223372036854775808.0", true),
+            ("-9223372036854775807 > -9223372036854775808.0", true),
+            ("2 > 1.5", true),
+            ("-2 < -1.5", true),
+            ("-1 > -1.5", true),
+            ("1 < 0.0 / 0.0", false),
+            ("1 >= 0.0 / 0.0", false),
+        ] {
+            assert_eq!(
+                Value::Boolean(*expected),
+                eval(source).unwrap(),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(eval("1 != 2"), Ok(Value::Boolean(true))));
+        assert!(matches!(eval("2 > 1.5"), Ok(Value::Boolean(true))));
+        assert!(matches!(eval("2 <= 1"), Ok(Value::Boolean(false))));
+        assert!(matches!(eval("\"a\" < \"b\""), Ok(Value::Boolean(true))));
+
+        assert!(matches!(
+            eval("1 < nil"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn functions() {
+        let vm = run_program(
+            "fn add(a, b) { return a + b; }
+             fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+             let x = add(1, 2);
+             let y = fib(10);
+             let z = fn() {}();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(3), vm.globals()["x"]);
+        assert_eq!(Value::Integer(55), vm.globals()["y"]);
+        assert_eq!(Value::Nil, vm.globals()["z"]);
+
+        match run_program("fn f(a) {} f(1, 2);") {
+            Err(RuntimeError::InvalidArguments { signature, reason }) => {
+                assert_eq!("f(a)", signature);
+                assert_eq!("expected 1 arguments but got 2", reason);
+            }
+            other => panic!("expected an arity error, got {:?}", other.err()),
+        }
+
+        assert!(matches!(
+            eval("1()"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn lambdas() {
+        assert_eq!(Value::Integer(10), eval("(|x| x * 2)(5)").unwrap());
+        assert_eq!(
+            Value::Integer(3),
+            eval("(|| { let a = 1; a + 2 })()").unwrap()
+        );
+
+        let vm = run_program(
+            "let xs = map([1, 2, 3], |x| x * 2);
+             let ys = filter([1, 2, 3, 4], fn(x) { return x % 2 == 0; });
+             let zs = sort([3, 1, 2]);
+             let ws = sort([\"bb\", \"a\", \"ccc\"], |a, b| a > b);",
+        )
+        .unwrap();
+
+        assert_eq!("[2, 4, 6]", vm.globals()["xs"].to_string());
+        assert_eq!("[2, 4]", vm.globals()["ys"].to_string());
+        assert_eq!("[1, 2, 3]", vm.globals()["zs"].to_string());
+        assert_eq!("[\"ccc\", \"bb\", \"a\"]", vm.globals()["ws"].to_string());
+    }
+
+    #[test]
+    fn closures() {
+        let vm = run_program(
+            "fn counter() {
+                 let count = 0;
+                 return || { count += 1; count };
+             }
+             let a = counter();
+             let b = counter();
+             a(); a();
+             let x = a();
+             let y = b();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(3), vm.globals()["x"]);
+        assert_eq!(Value::Integer(1), vm.globals()["y"]);
+
+        // Closures share the variables they capture, even once they have gone
+        // out of scope.
+        let vm = run_program(
+            "let get = nil; let set = nil;
+             {
+                 let value = 1;
+                 get = || value;
+                 set = |v| { value = v; };
+             }
+             set(2);
+             let x = get();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(2), vm.globals()["x"]);
+
+        // Captures from more than one level up.
+        assert_eq!(
+            Value::Integer(6),
+            eval("(|a| |b| |c| a + b + c)(1)(2)(3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn locals() {
+        let vm = run_program(
+            "let x = 1;
+             {
+                 let x = 2;
+                 fn f() { return x; }
+                 let y = 10 + (if true { let z = 3; x + z + f() } else { 0 });
+                 x = y;
+             }
+             let z = fn() {
+                 fn fact(n) { return if n < 2 { 1 } else { n * fact(n - 1) }; }
+                 return fact(5);
+             }();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(1), vm.globals()["x"]);
+        assert_eq!(Value::Integer(120), vm.globals()["z"]);
+        assert!(!vm.globals().contains_key("y"));
+    }
+
+    #[test]
+    fn block_locals() {
+        // Imports declare locals too, which have to be popped from underneath
+        // the block's value.
+        let vm = run_program(
+            "let a = if true { import \"math\"; math.pi } else { 0 };
+             let b = if true { import { pi } from \"math\"; pi } else { 0 };
+             let f = if true { let x = 1; let g = || x; x = 2; g } else { nil };
+             let c = f();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Number(std::f64::consts::PI), vm.globals()["a"]);
+        assert_eq!(Value::Number(std::f64::consts::PI), vm.globals()["b"]);
+        assert_eq!(Value::Integer(2), vm.globals()["c"]);
+    }
+
+    #[test]
+    fn block_like_operands() {
+        assert_eq!(
+            Value::Integer(3),
+            eval("1 + if true { 2 } else { 3 }").unwrap()
+        );
+        assert_eq!(
+            Value::Integer(12),
+            eval("2 * match 1 { 1 => 6, _ => 0 }").unwrap()
+        );
+    }
+
+    #[test]
+    fn large_programs() {
+        // Names and literals that are used repeatedly share a constant.
+        let source = format!("let x = 0;\n{}", "x = x + 1;\n".repeat(1000));
+        let vm = run_program(&source).unwrap();
+        assert_eq!(Value::Integer(1000), vm.globals()["x"]);
+
+        let numbers: Vec<_> = (0..300).map(|i| i.to_string()).collect();
+        let entries: Vec<_> = (0..300).map(|i| format!("{}: {}", i, i)).collect();
+        let parts: Vec<_> = (0..300).map(|i| format!("${{{}}}", i)).collect();
+
+        let mut vm = run_program(&format!(
+            "let list = [{}];
+             let entries = [{}];
+             let args = fn(...rest) {{ return rest; }}({});
+             let string = \"{}\";",
+            numbers.join(", "),
+            entries.join(", "),
+            numbers.join(", "),
+            parts.join("")
+        ))
+        .unwrap();
+
+        let expected: Vec<i64> = (0..300).collect();
+        assert_eq!(expected, vm.get_global::<Vec<i64>>("list").unwrap());
+        assert_eq!(expected, vm.get_global::<Vec<i64>>("args").unwrap());
+        assert!(matches!(
+            vm.eval("entries[299]"),
+            Ok(Some(Value::Integer(299)))
+        ));
+
+        let string: String = vm.get_global("string").unwrap();
+        assert_eq!(numbers.join(""), string);
+
+        // Limits that are really reached are reported as errors.
+        let numbers: Vec<_> = (0..70000).map(|i| i.to_string()).collect();
+        assert!(matches!(
+            VirtualMachine::new().eval(&format!("[{}]", numbers.join(", "))),
+            Err(RuntimeError::CompileError { .. })
+        ));
+
+        let locals: Vec<_> = (0..300).map(|i| format!("let a{} = 0;", i)).collect();
+        let source = format!("fn f() {{ {} }}", locals.join(" "));
+
+        assert!(matches!(
+            VirtualMachine::new().eval(&source),
+            Err(RuntimeError::CompileError { .. })
+        ));
+
+        assert!(matches!(
+            run_with_modules(&[("big.ein", &source)], "import \"big\";"),
+            Err(RuntimeError::InvalidModule { .. })
+        ));
+    }
+
+    #[test]
+    fn match_literals() {
+        let source = |value| {
+            format!(
+                "match {} {{ 1 => \"one\", -2.5 => \"float\", \"a\" => \"string\", nil => \"nil\", _ => \"other\" }}",
+                value
+            )
+        };
+
+        assert_eq!(Value::String("one".into()), eval(&source("1.0")).unwrap());
+        assert_eq!(
+            Value::String("float".into()),
+            eval(&source("-2.5")).unwrap()
+        );
+        assert_eq!(
+            Value::String("string".into()),
+            eval(&source("\"a\"")).unwrap()
+        );
+        assert_eq!(Value::String("nil".into()), eval(&source("nil")).unwrap());
+        assert_eq!(
+            Value::String("other".into()),
+            eval(&source("true")).unwrap()
+        );
+    }
+
+    #[test]
+    fn match_destructuring() {
+        let vm = run_program(
+            "fn describe(value) {
+                 return match value {
+                     [] => \"empty\",
+                     [x] => \"one: ${x}\",
+                     [x, [y, _]] => \"nested: ${x} ${y}\",
+                     [first, ..rest] => \"first: ${first}, rest: ${rest}\",
+                     [\"name\": name, \"age\": age] if age >= 18 => \"adult ${name}\",
+                     [\"name\": name] => \"person ${name}\",
+                     [:] => \"map\",
+                     other => \"value ${other}\",
+                 };
+             }
+             let a = describe([]);
+             let b = describe([1]);
+             let c = describe([1, [2, 3]]);
+             let d = describe([1, 2, 3]);
+             let e = describe([\"name\": \"Ada\", \"age\": 36]);
+             let f = describe([\"name\": \"Bob\", \"age\": 12]);
+             let g = describe([\"age\": 12]);
+             let h = describe(5);",
+        )
+        .unwrap();
+
+        let results: Vec<_> = ["a", "b", "c", "d", "e", "f", "g", "h"]
+            .iter()
+            .map(|name| vm.globals()[*name].stringify())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "empty",
+                "one: 1",
+                "nested: 1 2",
+                "first: 1, rest: [2, 3]",
+                "adult Ada",
+                "person Bob",
+                "map",
+                "value 5",
+            ],
+            results
+        );
+    }
+
+    #[test]
+    fn match_scoping() {
+        // Bindings are local to their arm, and guards that fail after binding
+        // leave the stack balanced.
+        let vm = run_program(
+            "let x = 1;
+             let total = 0;
+             let i = 0;
+             while i < 3 {
+                 total += match [i, i * 2] {
+                     [x, y] if x == 2 => x + y,
+                     [_, y] if y == 0 => 100,
+                     [x, _] => { let z = x * 10; z },
+                 };
+                 i += 1;
+             }
+             let f = match 5 { n => || n };
+             let y = f();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(1), vm.globals()["x"]);
+        assert_eq!(Value::Integer(116), vm.globals()["total"]);
+        assert_eq!(Value::Integer(5), vm.globals()["y"]);
+    }
+
+    #[test]
+    fn match_failure() {
+        assert!(matches!(
+            eval("match 3 { 1 => 1, 2 => 2 }"),
+            Err(RuntimeError::NoMatch { .. })
+        ));
+
+        assert!(matches!(
+            eval("match [1, 2] { [x] => x, [\"a\": x] => x }"),
+            Err(RuntimeError::NoMatch { .. })
+        ));
+    }
+
+    /// Runs a program that can import the given set of in-memory modules.
+    fn run_with_modules(
+        files: &[(&str, &str)],
+        source: &str,
+    ) -> Result<VirtualMachine, RuntimeError> {
+        let mut loader = MemoryLoader::new();
+
+        for (path, contents) in files {
+            loader.add_module(path, contents);
+        }
+
+        let mut vm = VirtualMachine::new();
+        vm.set_module_loader(loader);
+
+        run_program_in(vm, source)
+    }
+
+    #[test]
+    fn modules() {
+        let vm = run_with_modules(
+            &[
+                (
+                    "lib/util.ein",
+                    "import \"./helper\"; let x = helper.value; fn get() { return x; }",
+                ),
+                ("lib/helper.ein", "let value = 42;"),
+            ],
+            "let x = 1;
+             import \"lib/util\";
+             import \"lib/util.ein\" as again;
+             import { get } from \"lib/util\";
+             let same = util == again;
+             let a = util.get();
+             let b = get();",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(1), vm.globals()["x"]);
+        assert_eq!(Value::Boolean(true), vm.globals()["same"]);
+        assert_eq!(Value::Integer(42), vm.globals()["a"]);
+        assert_eq!(Value::Integer(42), vm.globals()["b"]);
+        assert!(!vm.globals().contains_key("helper"));
+    }
+
+    #[test]
+    fn module_errors() {
+        let result = run_with_modules(
+            &[("a.ein", "import \"b\";"), ("b.ein", "import \"a\";")],
+            "import \"a\";",
+        );
+
+        match result {
+            Err(RuntimeError::ImportCycle { cycle }) => {
+                assert_eq!(3, cycle.len());
+                assert_eq!(vec!["a.ein", "b.ein", "a.ein"], cycle);
+            }
+            other => panic!("expected an import cycle, got {:?}", other.map(|_| ())),
+        }
+
+        assert!(matches!(
+            run_with_modules(&[], "import \"missing\";"),
+            Err(RuntimeError::ModuleNotFound { .. })
+        ));
+
+        assert!(matches!(
+            run_with_modules(&[("bad.ein", "let = ;")], "import \"bad\";"),
+            Err(RuntimeError::InvalidModule { .. })
+        ));
+
+        assert!(matches!(
+            run_with_modules(&[("m.ein", "let a = 1;")], "import { b } from \"m\";"),
+            Err(RuntimeError::UndefinedName { .. })
+        ));
+    }
+
+    #[test]
+    fn filesystem_loader() {
+        let dir = std::env::temp_dir().join(format!("ein_loader_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/util.ein"), "import \"helper\";").unwrap();
+        std::fs::write(dir.join("lib/helper.ein"), "let value = 1;").unwrap();
+
+        let loader = FileSystemLoader::new(vec![dir.clone()]);
+        let util = loader.resolve("lib/util", None);
+        let helper = util
+            .as_deref()
+            .and_then(|util| loader.resolve("helper", Some(util)));
+        let missing = loader.resolve("helper", None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(util.unwrap().ends_with("util.ein"));
+        assert!(helper.unwrap().ends_with("helper.ein"));
+        assert_eq!(None, missing);
+    }
+
+    #[test]
+    fn math_module() {
+        let vm = run_program(
+            "import \"math\";
+             let root = math.sqrt(16);
+             let floored = math.floor(2.7);
+             let int = math.round(3);
+             let abs = math.abs(-5);
+             let smallest = math.min(3, 1.5, 2);
+             let largest = math.max(3, 1.5, 2);
+             let nan = math.is_nan(math.nan);
+             let inf = math.is_infinite(-math.inf);
+             let angle = math.atan2(1, 1) * 4;",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Number(4.0), vm.globals()["root"]);
+        assert_eq!(Value::Number(2.0), vm.globals()["floored"]);
+        assert_eq!(Value::Integer(3), vm.globals()["int"]);
+        assert_eq!(Value::Integer(5), vm.globals()["abs"]);
+        assert_eq!(Value::Number(1.5), vm.globals()["smallest"]);
+        assert_eq!(Value::Integer(3), vm.globals()["largest"]);
+        assert_eq!(Value::Boolean(true), vm.globals()["nan"]);
+        assert_eq!(Value::Boolean(true), vm.globals()["inf"]);
+        assert_eq!(Value::Number(std::f64::consts::PI), vm.globals()["angle"]);
+
+        assert!(matches!(
+            run_program("import { sqrt } from \"math\"; sqrt(\"4\");"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            run_program("import \"math\"; math.abs(nil);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            run_program("import \"math\"; math.min();"),
+            Err(RuntimeError::InvalidArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn native_arity_errors() {
+        let error = |source| match run_program(source) {
+            Err(RuntimeError::InvalidArguments { signature, reason }) => {
+                format!("{}: {}", signature, reason)
+            }
+            other => panic!("expected an argument error, got {:?}", other.err()),
+        };
+
+        assert_eq!(
+            "sort: expected 1 to 2 arguments but got 0",
+            error("sort();")
+        );
+        assert_eq!(
+            "sort: expected 1 to 2 arguments but got 3",
+            error("sort([], nil, 1);")
+        );
+        assert_eq!(
+            "split: expected 0 to 1 arguments but got 2",
+            error("\"a b\".split(\" \", 1);")
+        );
+        assert_eq!(
+            "slice: expected 1 to 2 arguments but got 0",
+            error("\"abc\".slice();")
+        );
+        assert_eq!(
+            "call: expected 0 to 1 arguments but got 2",
+            error("Fiber.new(|| 1).call(1, 2);")
+        );
+        assert_eq!(
+            "yield: expected 0 to 1 arguments but got 2",
+            error("Fiber.yield(1, 2);")
+        );
+        assert_eq!(
+            "min: expected at least 1 arguments but got 0",
+            error("import \"math\"; math.min();")
+        );
+
+        // Natives with a fixed arity report their errors in the same way.
+        assert_eq!(
+            "filter: expected 2 arguments but got 1",
+            error("filter([]);")
+        );
+        assert_eq!(
+            "upper: expected 0 arguments but got 1",
+            error("\"abc\".upper(1);")
+        );
+        assert_eq!(
+            "isDone: expected 0 arguments but got 1",
+            error("Fiber.new(|| 1).isDone(1);")
+        );
+    }
+
+    #[test]
+    fn string_methods() {
+        let vm = run_program(
+            "let s = \"  Héllo, Wörld  \".trim();
+             let len = s.len();
+             let upper = s.upper();
+             let parts = s.split(\", \");
+             let words = \" a  b \".split();
+             let joined = \"-\".join([1, \"b\", nil]);
+             let replaced = s.replace(\"l\", \"L\");
+             let found = s.find(\"W\");
+             let missing = s.find(\"x\");
+             let sliced = s.slice(1, 5);
+             let rest = s.slice(7);
+             let flags = [s.contains(\"llo\"), s.starts_with(\"Hé\"), s.ends_with(\"x\")];
+             let chars = \"e\\u{301}\".chars();
+             let graphemes = \"e\\u{301}\".graphemes();
+             let method = s.upper;
+             let bound = method();",
+        )
+        .unwrap();
+
+        let string = |s: &str| Value::String(s.to_string());
+
+        assert_eq!(Value::Integer(12), vm.globals()["len"]);
+        assert_eq!(string("HÉLLO, WÖRLD"), vm.globals()["upper"]);
+        assert_eq!("[\"Héllo\", \"Wörld\"]", vm.globals()["parts"].to_string());
+        assert_eq!("[\"a\", \"b\"]", vm.globals()["words"].to_string());
+        assert_eq!(string("1-b-nil"), vm.globals()["joined"]);
+        assert_eq!(string("HéLLo, WörLd"), vm.globals()["replaced"]);
+        assert_eq!(Value::Integer(7), vm.globals()["found"]);
+        assert_eq!(Value::Nil, vm.globals()["missing"]);
+        assert_eq!(string("éllo"), vm.globals()["sliced"]);
+        assert_eq!(string("Wörld"), vm.globals()["rest"]);
+        assert_eq!("[true, true, false]", vm.globals()["flags"].to_string());
+        assert_eq!("[\"e\", \"\u{301}\"]", vm.globals()["chars"].to_string());
+        assert_eq!("[\"e\u{301}\"]", vm.globals()["graphemes"].to_string());
+        assert_eq!(vm.globals()["upper"], vm.globals()["bound"]);
+
+        assert!(matches!(
+            eval("\"abc\".slice(2, 4)"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            eval("\"abc\".contains(1)"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            eval("\"abc\".nope()"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        match eval("\"abc\".upper(1)") {
+            Err(RuntimeError::InvalidArguments { signature, reason }) => {
+                assert_eq!("upper", signature);
+                assert_eq!("expected 0 arguments but got 1", reason);
+            }
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fs_module() {
+        let dir = std::env::temp_dir().join(format!("ein_fs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let source = format!(
+            "import \"fs\";
+             let path = \"{dir}/out.txt\";
+             fs.write(path, \"a\");
+             fs.append(path, 1);
+             let contents = fs.read(path);
+             let exists = [fs.exists(path), fs.exists(\"{dir}/missing.txt\")];
+             let entries = fs.list_dir(\"{dir}\");",
+            dir = dir.display()
+        );
+
+        let mut vm = VirtualMachine::new();
+        vm.grant_fs();
+
+        let result = run_program_in(vm, &source);
+        std::fs::remove_dir_all(dir).unwrap();
+        let vm = result.unwrap();
+
+        assert_eq!(Value::String("a1".to_string()), vm.globals()["contents"]);
+        assert_eq!("[true, false]", vm.globals()["exists"].to_string());
+        assert_eq!("[\"out.txt\"]", vm.globals()["entries"].to_string());
+
+        assert!(matches!(
+            run_program_in(vm, "fs.read(\"missing.txt\");"),
+            Err(RuntimeError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn capabilities_are_not_granted_by_default() {
+        assert!(matches!(
+            run_program("import \"fs\";"),
+            Err(RuntimeError::ModuleNotFound { .. })
+        ));
+
+        assert!(matches!(
+            run_program("import \"io\";"),
+            Err(RuntimeError::ModuleNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn exceptions() {
+        let vm = run_program(
+            "let thrown = nil;
+             try { throw \"boom\"; } catch (e) { thrown = e; }
+
+             let kind = nil;
+             try { missing; } catch (e) { kind = e[\"kind\"]; }
+
+             fn fail(x) { throw x; }
+             let unwound = nil;
+             try { fail(1); } catch (e) { unwound = e; }
+
+             let from_native = nil;
+             try { map([2], fail); } catch (e) { from_native = e; }
+
+             fn locals() {
+                 let a = 1;
+                 try { let b = 2; throw b; } catch (e) { return a + e; }
+             }
+             let sum = locals();
+
+             let after = 0;
+             try { after = 1; } catch (e) { after = 2; }",
+        )
+        .unwrap();
+
+        assert_eq!(Value::String("boom".to_string()), vm.globals()["thrown"]);
+        assert_eq!(
+            Value::String("UndefinedName".to_string()),
+            vm.globals()["kind"]
+        );
+        assert_eq!(Value::Integer(1), vm.globals()["unwound"]);
+        assert_eq!(Value::Integer(2), vm.globals()["from_native"]);
+        assert_eq!(Value::Integer(3), vm.globals()["sum"]);
+        assert_eq!(Value::Integer(1), vm.globals()["after"]);
+
+        assert!(matches!(
+            run_program("fn f() { throw 1; } f();"),
+            Err(RuntimeError::Thrown {
+                value: Value::Integer(1)
+            })
+        ));
+    }
+
+    #[test]
+    fn finally() {
+        let vm = run_program(
+            "let count = 0;
+
+             try { count = count + 1; } finally { count = count + 10; }
+
+             try { throw 1; } catch (e) { count = count + 100; } finally { count = count + 1000; }
+
+             fn early() {
+                 try { return 1; } finally { count = count + 10000; }
+             }
+             let returned = early();
+
+             let rethrown = nil;
+             try {
+                 try { throw 2; } finally { count = count + 100000; }
+             } catch (e) {
+                 rethrown = e;
+             }
+
+             let from_catch = nil;
+             try {
+                 try { throw 3; } catch (e) { throw e + 1; } finally { count = count + 1000000; }
+             } catch (e) {
+                 from_catch = e;
+             }",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(1111111), vm.globals()["count"]);
+        assert_eq!(Value::Integer(1), vm.globals()["returned"]);
+        assert_eq!(Value::Integer(2), vm.globals()["rethrown"]);
+        assert_eq!(Value::Integer(4), vm.globals()["from_catch"]);
+
+        // Errors that aren't caught reach the host unchanged.
+        let mut vm = VirtualMachine::new();
+
+        match vm.eval("let ran = false; try { undefined_fn(); } finally { ran = true; }") {
+            Err(RuntimeError::UndefinedName { name }) => assert_eq!("undefined_fn", name),
+            other => panic!("expected an undefined name, got {:?}", other),
+        }
+
+        assert_eq!(Value::Boolean(true), vm.globals()["ran"]);
+
+        assert!(matches!(
+            vm.eval("try { throw 1; } catch (e) { 1 / 0; } finally {}"),
+            Err(RuntimeError::DivisionByZero)
+        ));
+
+        // The error is still converted when it's caught further out.
+        let vm = run_program(
+            "let kind = nil;
+             try {
+                 try { undefined_fn(); } finally {}
+             } catch (e) {
+                 kind = e[\"kind\"];
+             }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Value::String("UndefinedName".to_string()),
+            vm.globals()["kind"]
+        );
+    }
+
+    #[test]
+    fn fibers() {
+        let vm = run_program(
+            "let f = Fiber.new(fn(first) {
+                 let a = Fiber.yield(first + 1);
+                 let b = Fiber.yield(a + 1);
+                 return b + 1;
+             });
+
+             let results = [f.call(1), f.call(10), f.isDone(), f.call(100), f.isDone()];
+
+             fn shared() {
+                 let count = 0;
+                 let counter = Fiber.new(|| {
+                     while true {
+                         count = count + 1;
+                         Fiber.yield(count);
+                     }
+                 });
+
+                 counter.call();
+                 counter.call();
+                 return count;
+             }
+
+             let count = shared();
+
+             let main = Fiber.current();
+             let received = nil;
+             let other = Fiber.new(fn(v) { received = v; main.transfer(v + 1); });
+             let transferred = other.transfer(5);
+             let other_done = other.is_done();",
+        )
+        .unwrap();
+
+        assert_eq!(
+            "[2, 11, false, 101, true]",
+            vm.globals()["results"].to_string()
+        );
+        assert_eq!(Value::Integer(2), vm.globals()["count"]);
+        assert_eq!(Value::Integer(5), vm.globals()["received"]);
+        assert_eq!(Value::Integer(6), vm.globals()["transferred"]);
+        assert_eq!(Value::Boolean(false), vm.globals()["other_done"]);
+    }
+
+    #[test]
+    fn fiber_errors() {
+        let vm = run_program(
+            "let thrown = nil;
+             let bad = Fiber.new(|| { throw \"oops\"; });
+             try { bad.call(); } catch (e) { thrown = e; }
+
+             let finished = nil;
+             try { bad.call(); } catch (e) { finished = e[\"kind\"]; }
+
+             let nested = nil;
+             let yields_in_native = Fiber.new(|| map([1], |x| Fiber.yield(x)));
+             try { yields_in_native.call(); } catch (e) { nested = e[\"kind\"]; }",
+        )
+        .unwrap();
+
+        let invalid = Value::String("InvalidOperation".to_string());
+
+        assert_eq!(Value::String("oops".to_string()), vm.globals()["thrown"]);
+        assert_eq!(invalid, vm.globals()["finished"]);
+        assert_eq!(invalid, vm.globals()["nested"]);
+
+        assert!(matches!(
+            run_program("Fiber.yield(1);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            run_program("Fiber.new(|a, b| a);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn resume_fiber_from_host() {
+        let mut vm =
+            run_program("let f = Fiber.new(|x| { let y = Fiber.yield(x + 1); return y * 2; });")
+                .unwrap();
+
+        let fiber = match &vm.globals()["f"] {
+            Value::Fiber(fiber) => fiber.clone(),
+            other => panic!("expected a fiber, got {}", other),
+        };
+
+        assert_eq!(
+            Value::Integer(2),
+            vm.resume(&fiber, Value::Integer(1)).unwrap()
+        );
+        assert_eq!(
+            Value::Integer(10),
+            vm.resume(&fiber, Value::Integer(5)).unwrap()
+        );
+        assert!(fiber.is_done());
+        assert!(vm.resume(&fiber, Value::Nil).is_err());
+    }
+
+    #[test]
+    fn for_loops() {
+        let vm = run_program(
+            "let sum = 0;
+             for x in [1, 2, 3] { sum += x; }
+
+             let keys = \"\";
+             for key in [\"b\": 2, \"a\": 1] { keys = \"${keys}${key}\"; }
+
+             let range_sum = 0;
+             for i in 0..5 { range_sum += i; }
+
+             let empty = true;
+             for i in 5..0 { empty = false; }
+
+             let chars = \"\";
+             for ch in \"héllo\" { chars = \"${ch}-${chars}\"; }
+
+             let closures = [:];
+             for i in 0..3 { closures[i] = || i; }
+             let captured = closures[0]() + closures[1]() * 10 + closures[2]() * 100;
+
+             fn find(items, target) {
+                 for item in items {
+                     if item == target { return true; }
+                 }
+
+                 return false;
+             }
+
+             let found = [find(1..10, 5), find(1..10, 20)];",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(6), vm.globals()["sum"]);
+        assert_eq!(Value::String("ab".to_string()), vm.globals()["keys"]);
+        assert_eq!(Value::Integer(10), vm.globals()["range_sum"]);
+        assert_eq!(Value::Boolean(true), vm.globals()["empty"]);
+        assert_eq!(
+            Value::String("o-l-l-é-h-".to_string()),
+            vm.globals()["chars"]
+        );
+        assert_eq!(Value::Integer(210), vm.globals()["captured"]);
+        assert_eq!("[true, false]", vm.globals()["found"].to_string());
+
+        assert!(matches!(
+            run_program("for x in 1 {}"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            run_program("let r = 0..1.5;"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn generators() {
+        let vm = run_program(
+            "fn count_to(n) {
+                 let i = 1;
+
+                 while i <= n {
+                     yield i;
+                     i += 1;
+                 }
+             }
+
+             let total = 0;
+             for i in count_to(4) { total += i; }
+
+             fn evens(values) {
+                 for v in values {
+                     if v % 2 == 0 { yield v; }
+                 }
+             }
+
+             let even_total = 0;
+             for v in evens(count_to(10)) { even_total += v; }
+
+             let it = count_to(2);
+             let steps = [it.next(), it.is_done(), it.next(), it.is_done(), it.next(), it.is_done()];
+
+             fn returns() { yield 1; return 5; }
+             let returned = returns();
+             let return_steps = [returned.next(), returned.next(), returned.is_done()];
+             let for_values = 0;
+             for v in returns() { for_values += v; }
+
+             let methods = [[1, 2].iter().next(), (3..5).iter().next(), \"ab\".iter().next()];
+             let same = returned.iter() == returned;
+
+             let list_it = iterate([nil]);
+             let list_steps = [list_it.next(), list_it.is_done(), list_it.next(), list_it.is_done()];
+
+             let outer = map([1, 2], |x| {
+                 let sum = 0;
+                 for i in count_to(x) { sum += i; }
+                 sum
+             });
+
+             let failed = nil;
+             fn broken() { yield 1; throw \"broken\"; }
+             try {
+                 for x in broken() {}
+             } catch (e) {
+                 failed = e;
+             }",
+        )
+        .unwrap();
+
+        assert_eq!(Value::Integer(10), vm.globals()["total"]);
+        assert_eq!(Value::Integer(30), vm.globals()["even_total"]);
+        assert_eq!(
+            "[1, false, 2, false, nil, true]",
+            vm.globals()["steps"].to_string()
+        );
+        assert_eq!(
+            "[nil, false, nil, true]",
+            vm.globals()["list_steps"].to_string()
+        );
+        assert_eq!("[1, nil, true]", vm.globals()["return_steps"].to_string());
+        assert_eq!(Value::Integer(1), vm.globals()["for_values"]);
+        assert_eq!(r#"[1, 3, "a"]"#, vm.globals()["methods"].to_string());
+        assert_eq!(Value::Boolean(true), vm.globals()["same"]);
+        assert_eq!("[1, 3]", vm.globals()["outer"].to_string());
+        assert_eq!(Value::String("broken".to_string()), vm.globals()["failed"]);
+    }
+
+    #[test]
+    fn parameters() {
+        let vm = run_program(
+            "import \"math\";
+
+             fn greet(name, greeting = \"Hello\", punctuation = \"!\") {
+                 return \"${greeting}, ${name}${punctuation}\";
+             }
+
+             fn count(first, ...rest) {
+                 let total = 1;
+                 for item in rest { total += 1; }
+                 return [first, total];
+             }
+
+             let calls = 0;
+             fn next_call() { calls += 1; return calls; }
+             fn counted(value = next_call()) { return value; }
+
+             let defaults = greet(\"Ein\");
+             let positional = greet(\"Ein\", \"Hi\");
+             let keyword = greet(\"Ein\", punctuation: \"?\");
+             let all_keywords = greet(punctuation: \".\", name: \"Ein\", greeting: \"Bye\");
+             let explicit_nil = greet(\"Ein\", nil, \"\");
+
+             let rest = count(1, 2, 3);
+             let no_rest = count(1);
+
+             let args = [\"Ein\", \"Hey\"];
+             let spread = greet(...args);
+             let spread_rest = count(...[1, 2], 3, ...[4]);
+             let spread_native = math.max(...[3, 9, 4]);
+
+             let lazy = [counted(), counted(10), counted()];
+             let lambda = (|...xs| xs)(1, 2);
+
+             fn upto(n = 3) {
+                 let i = 0;
+                 while i < n { yield i; i += 1; }
+             }
+
+             let generated = 0;
+             for i in upto() { generated += i; }",
+        )
+        .unwrap();
+
+        let string = |s: &str| Value::String(s.to_string());
+
+        assert_eq!(string("Hello, Ein!"), vm.globals()["defaults"]);
+        assert_eq!(string("Hi, Ein!"), vm.globals()["positional"]);
+        assert_eq!(string("Hello, Ein?"), vm.globals()["keyword"]);
+        assert_eq!(string("Bye, Ein."), vm.globals()["all_keywords"]);
+        assert_eq!(string("nil, Ein"), vm.globals()["explicit_nil"]);
+        assert_eq!("[1, 3]", vm.globals()["rest"].to_string());
+        assert_eq!("[1, 1]", vm.globals()["no_rest"].to_string());
+        assert_eq!(string("Hey, Ein!"), vm.globals()["spread"]);
+        assert_eq!("[1, 4]", vm.globals()["spread_rest"].to_string());
+        assert_eq!(Value::Integer(9), vm.globals()["spread_native"]);
+        assert_eq!("[1, 10, 2]", vm.globals()["lazy"].to_string());
+        assert_eq!("[1, 2]", vm.globals()["lambda"].to_string());
+        assert_eq!(Value::Integer(3), vm.globals()["generated"]);
+
+        let error = |source: &str| match run_program(source) {
+            Err(RuntimeError::InvalidArguments { signature, reason }) => (signature, reason),
+            other => panic!("expected an argument error, got {:?}", other.err()),
+        };
+
+        let greet = "fn greet(name, greeting = 1, ...rest) {}";
+
+        assert_eq!(
+            (
+                "greet(name, greeting?, ...rest)".to_string(),
+                "expected at least 1 arguments but got 0".to_string()
+            ),
+            error(&format!("{} greet();", greet))
+        );
+
+        assert_eq!(
+            "expected 1 to 2 arguments but got 3",
+            error("fn f(a, b = 1) {} f(1, 2, 3);").1
+        );
+
+        assert_eq!(
+            "there is no parameter rest",
+            error(&format!("{} greet(1, rest: 2);", greet)).1
+        );
+
+        assert_eq!(
+            "name was given more than once",
+            error(&format!("{} greet(1, name: 2);", greet)).1
+        );
+
+        assert_eq!(
+            "name was not given",
+            error(&format!("{} greet(greeting: 2);", greet)).1
+        );
+
+        assert_eq!("fn(a)", error("(|a| a)();").0);
+
+        assert!(matches!(
+            run_program("print(end: 1);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            run_program("print(...1);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn embedding() {
+        let mut vm = VirtualMachine::new();
+
+        assert!(matches!(vm.eval("1 + 2"), Ok(Some(Value::Integer(3)))));
+        assert!(matches!(vm.eval("let x = 1;"), Ok(None)));
+        assert!(matches!(
+            vm.eval("let = 1;"),
+            Err(RuntimeError::InvalidSyntax { .. })
+        ));
+
+        let mut scores = std::collections::HashMap::new();
+        scores.insert("alice".to_string(), 3);
+
+        vm.set_global("names", vec!["alice", "bob"]);
+        vm.set_global("scores", scores);
+        vm.set_global("scale", 1.5);
+
+        vm.eval(
+            "fn total(a, b) { return a + b; }
+             let first = names[0];
+             let score = scores.alice * scale;
+             let missing = scores.bob;",
+        )
+        .unwrap();
+
+        assert_eq!("alice", vm.get_global::<String>("first").unwrap());
+        assert_eq!(4.5, vm.get_global::<f64>("score").unwrap());
+        assert_eq!(None, vm.get_global::<Option<i64>>("missing").unwrap());
+
+        assert_eq!(
+            vec!["alice".to_string(), "bob".to_string()],
+            vm.get_global::<Vec<String>>("names").unwrap()
+        );
+
+        let scores: std::collections::HashMap<String, i32> = vm.get_global("scores").unwrap();
+        assert_eq!(Some(&3), scores.get("alice"));
+
+        let total: Value = vm.get_global("total").unwrap();
+        let result = vm.call(&total, &[1.into_value(), 2.into_value()]);
+        assert!(matches!(result, Ok(Value::Integer(3))));
+
+        assert!(matches!(
+            vm.get_global::<i64>("first"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            vm.get_global::<i64>("nothing"),
+            Err(RuntimeError::UndefinedName { .. })
+        ));
+
+        vm.set_global("big", i64::MAX);
+
+        assert!(matches!(
+            vm.get_global::<i32>("big"),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+    }
+
+    #[test]
+    fn userdata() {
+        struct Counter {
+            count: i64,
+        }
+
+        impl UserData for Counter {
+            fn type_name(&self) -> &str {
+                "Counter"
+            }
+
+            fn methods(&self) -> Vec<(&'static str, Option<usize>, Method)> {
+                vec![("increment", Some(2), |_, args| {
+                    let mut counter = args[0].downcast_mut::<Counter>().unwrap();
+                    counter.count += i64::from_value(&args[1])?;
+                    Ok(Value::Integer(counter.count))
+                })]
+            }
+
+            fn get_field(&self, name: &str) -> Option<Value> {
+                match name {
+                    "count" => Some(Value::Integer(self.count)),
+                    _ => None,
+                }
+            }
+
+            fn set_field(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
+                match name {
+                    "count" => self.count = i64::from_value(&value)?,
+                    _ => {
+                        return Err(RuntimeError::InvalidOperation {
+                            reason: format!("Counter has no field {}", name),
+                        })
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        struct Name(String);
+
+        impl UserData for Name {
+            fn type_name(&self) -> &str {
+                "Name"
+            }
+        }
+
+        let mut vm = VirtualMachine::new();
+
+        vm.define_native("reset", Some(1), |_, args| {
+            match args[0].downcast_mut::<Counter>() {
+                Some(mut counter) => counter.count = 0,
+                None => {
+                    return Err(RuntimeError::InvalidOperation {
+                        reason: format!("{} is not a counter", args[0]),
+                    })
+                }
+            }
+
+            Ok(Value::Nil)
+        });
+
+        vm.set_global("counter", Value::user_data(Counter { count: 1 }));
+        vm.set_global("name", Value::user_data(Name("Ein".to_string())));
+
+        assert!(matches!(
+            vm.eval("counter.increment(2) + counter.count"),
+            Ok(Some(Value::Integer(6)))
+        ));
+
+        vm.eval("counter.count = 10; let copy = counter; copy.increment(5);")
+            .unwrap();
+
+        let counter = vm.get_global::<Value>("counter").unwrap();
+        assert_eq!(15, counter.downcast_ref::<Counter>().unwrap().count);
+        assert!(counter.downcast_ref::<Name>().is_none());
+
+        vm.eval("reset(counter);").unwrap();
+        assert_eq!(0, counter.downcast_ref::<Counter>().unwrap().count);
+
+        let name = vm.get_global::<Value>("name").unwrap();
+        assert_eq!("Ein", name.downcast_ref::<Name>().unwrap().0);
+        assert_eq!("<Name>", name.to_string());
+
+        assert!(matches!(
+            vm.eval("counter == copy"),
+            Ok(Some(Value::Boolean(true)))
+        ));
+        assert!(matches!(
+            vm.eval("reset(name);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+        assert!(matches!(
+            vm.eval("name.increment(1);"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+        assert!(matches!(
+            vm.eval("name.value = 1;"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+        assert!(matches!(
+            vm.eval("counter.size = 1;"),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn userdata_iteration() {
+        struct Countdown(i64);
+
+        impl UserData for Countdown {
+            fn type_name(&self) -> &str {
+                "Countdown"
+            }
+
+            fn methods(&self) -> Vec<(&'static str, Option<usize>, Method)> {
+                vec![("__next", Some(1), |_, args| {
+                    let mut countdown = args[0].downcast_mut::<Countdown>().unwrap();
+                    countdown.0 -= 1;
+
+                    Ok(match countdown.0 {
+                        n if n >= 0 => Value::Integer(n),
+                        _ => Value::Nil,
+                    })
+                })]
+            }
+        }
+
+        struct Bag(Vec<Value>);
+
+        impl UserData for Bag {
+            fn type_name(&self) -> &str {
+                "Bag"
+            }
+
+            fn methods(&self) -> Vec<(&'static str, Option<usize>, Method)> {
+                vec![("__iter", Some(1), |_, args| {
+                    let bag = args[0].downcast_ref::<Bag>().unwrap();
+                    Ok(Value::List(Rc::new(RefCell::new(bag.0.clone()))))
+                })]
+            }
+        }
+
+        struct Name;
+
+        impl UserData for Name {
+            fn type_name(&self) -> &str {
+                "Name"
+            }
+        }
+
+        let mut vm = VirtualMachine::new();
+        vm.set_global("countdown", Value::user_data(Countdown(3)));
+        vm.set_global(
+            "bag",
+            Value::user_data(Bag(vec![Value::Integer(1), Value::Integer(2)])),
+        );
+        vm.set_global("name", Value::user_data(Name));
+
+        vm.eval(
+            "let counted = \"\";
+             for n in countdown { counted = \"${counted}${n}\"; }
+
+             let bagged = 0;
+             for item in bag { bagged += item * 10; }
+
+             let it = iterate(bag);
+             let steps = [it.next(), it.next(), it.next(), it.is_done()];",
+        )
+        .unwrap();
+
+        assert_eq!(Value::String("210".to_string()), vm.globals()["counted"]);
+        assert_eq!(Value::Integer(30), vm.globals()["bagged"]);
+        assert_eq!("[1, 2, nil, true]", vm.globals()["steps"].to_string());
+
+        assert!(matches!(
+            vm.eval("for x in name {}"),
+            Err(RuntimeError::InvalidOperation { reason }) if reason.contains("__next")
+        ));
+    }
+
+    #[test]
+    fn operator_overloading() {
+        struct Vector(i64, i64);
+
+        fn vector(value: &Value) -> Result<Ref<'_, Vector>, RuntimeError> {
+            value
+                .downcast_ref::<Vector>()
+                .ok_or_else(|| RuntimeError::InvalidOperation {
+                    reason: format!("{} is not a vector", value),
+                })
+        }
+
+        impl UserData for Vector {
+            fn type_name(&self) -> &str {
+                "Vector"
+            }
+
+            fn methods(&self) -> Vec<(&'static str, Option<usize>, Method)> {
+                vec![
+                    ("__add", Some(2), |_, args| {
+                        let (a, b) = (vector(&args[0])?, vector(&args[1])?);
+                        Ok(Value::user_data(Vector(a.0 + b.0, a.1 + b.1)))
+                    }),
+                    ("__mul", Some(2), |_, args| {
+                        let (v, n) = match (&args[0], &args[1]) {
+                            (Value::Integer(n), v) | (v, Value::Integer(n)) => (vector(v)?, *n),
+                            _ => {
+                                return Err(RuntimeError::InvalidOperation {
+                                    reason: "vectors can only be multiplied by integers"
+                                        .to_string(),
+                                })
+                            }
+                        };
+
+                        Ok(Value::user_data(Vector(v.0 * n, v.1 * n)))
+                    }),
+                    ("__eq", Some(2), |_, args| {
+                        Ok(Value::Boolean(match (vector(&args[0]), vector(&args[1])) {
+                            (Ok(a), Ok(b)) => a.0 == b.0 && a.1 == b.1,
+                            _ => false,
+                        }))
+                    }),
+                    ("__lt", Some(2), |_, args| {
+                        let (a, b) = (vector(&args[0])?, vector(&args[1])?);
+                        Ok(Value::Boolean(
+                            a.0 * a.0 + a.1 * a.1 < b.0 * b.0 + b.1 * b.1,
+                        ))
+                    }),
+                    ("__index", Some(2), |_, args| {
+                        let v = vector(&args[0])?;
+
+                        match args[1] {
+                            Value::Integer(0) => Ok(Value::Integer(v.0)),
+                            Value::Integer(1) => Ok(Value::Integer(v.1)),
+                            ref other => Err(RuntimeError::InvalidOperation {
+                                reason: format!("{} is not a valid vector index", other),
+                            }),
+                        }
+                    }),
+                    ("__set_index", Some(3), |_, args| {
+                        let mut v = args[0].downcast_mut::<Vector>().unwrap();
+
+                        match (&args[1], &args[2]) {
+                            (Value::Integer(0), Value::Integer(n)) => v.0 = *n,
+                            (Value::Integer(1), Value::Integer(n)) => v.1 = *n,
+                            _ => {
+                                return Err(RuntimeError::InvalidOperation {
+                                    reason: "invalid vector assignment".to_string(),
+                                })
+                            }
+                        }
+
+                        Ok(Value::Nil)
+                    }),
+                    ("__to_string", Some(1), |_, args| {
+                        let v = vector(&args[0])?;
+                        Ok(Value::String(format!("({}, {})", v.0, v.1)))
+                    }),
+                ]
+            }
+        }
+
+        let mut vm = VirtualMachine::new();
+        vm.set_global("a", Value::user_data(Vector(1, 2)));
+        vm.set_global("b", Value::user_data(Vector(3, 4)));
+
+        let string = |vm: &mut VirtualMachine, source: &str| match vm.eval(source) {
+            Ok(Some(Value::String(s))) => s,
+            other => panic!("expected a string, but got {:?}", other),
+        };
+
+        assert_eq!("(4, 6)", string(&mut vm, "\"${a + b}\""));
+        assert_eq!("(2, 4) (3, 6)", string(&mut vm, "\"${a * 2} ${3 * a}\""));
+
+        assert!(matches!(
+            vm.eval("a + b == b + a"),
+            Ok(Some(Value::Boolean(true)))
+        ));
+        assert!(matches!(vm.eval("a != b"), Ok(Some(Value::Boolean(true)))));
+        assert!(matches!(vm.eval("a == 1"), Ok(Some(Value::Boolean(false)))));
+        assert!(matches!(vm.eval("a < b"), Ok(Some(Value::Boolean(true)))));
+        assert!(matches!(vm.eval("a > b"), Ok(Some(Value::Boolean(false)))));
+        assert!(matches!(vm.eval("b > a"), Ok(Some(Value::Boolean(true)))));
+        assert!(matches!(vm.eval("1 < 2"), Ok(Some(Value::Boolean(true)))));
+        assert!(matches!(vm.eval("2 > 1"), Ok(Some(Value::Boolean(true)))));
+        assert!(matches!(vm.eval("b[1]"), Ok(Some(Value::Integer(4)))));
+
+        vm.eval("b[0] = 10; b[1] += 1;").unwrap();
+        assert_eq!("(10, 5)", string(&mut vm, "\"${b}\""));
+
+        let error = |vm: &mut VirtualMachine, source: &str| match vm.eval(source) {
+            Err(RuntimeError::InvalidOperation { reason }) => reason,
+            other => panic!("expected an error, but got {:?}", other),
+        };
+
+        assert_eq!(
+            "cannot subtract <Vector> and <Vector>, as Vector has no __sub method",
+            error(&mut vm, "a - b")
+        );
+
+        assert_eq!(
+            "cannot compare <Vector> and 1, as Vector has no __le method",
+            error(&mut vm, "a <= 1")
+        );
+
+        assert_eq!(
+            "vectors can only be multiplied by integers",
+            error(&mut vm, "a * 1.5")
+        );
+        assert_eq!("cannot add 1 and true", error(&mut vm, "1 + true"));
+    }
+
+    #[test]
+    fn limits() {
+        let mut vm = VirtualMachine::new();
+
+        vm.set_limits(Limits {
+            instructions: Some(1000),
+            ..Limits::default()
+        });
+
+        assert!(matches!(
+            vm.eval("while true {}"),
+            Err(RuntimeError::InstructionLimitExceeded { limit: 1000 })
+        ));
+
+        // Scripts can't catch limit errors, even inside of other fibers.
+        assert!(matches!(
+            vm.eval("try { while true {} } catch (e) {} finally { print(1); }"),
+            Err(RuntimeError::InstructionLimitExceeded { .. })
+        ));
+
+        assert!(matches!(
+            vm.eval("Fiber.new(|| { try { while true {} } catch (e) {} }).call();"),
+            Err(RuntimeError::InstructionLimitExceeded { .. })
+        ));
+
+        // The count starts again on each run.
+        assert!(matches!(vm.eval("1 + 2"), Ok(Some(Value::Integer(3)))));
+
+        vm.set_limits(Limits {
+            memory: Some(1 << 20),
+            ..Limits::default()
+        });
+
+        assert!(matches!(
+            vm.eval("let s = \"ab\"; while true { s = \"${s}${s}\"; }"),
+            Err(RuntimeError::MemoryLimitExceeded { .. })
+        ));
+
+        assert!(matches!(
+            vm.eval("let s = \"ab\"; while true { s = \"\".join([s, s]); }"),
+            Err(RuntimeError::MemoryLimitExceeded { .. })
+        ));
+
+        // New collections returned by natives are counted along with their
+        // contents, but existing ones aren't counted again.
+        vm.define_native("words", Some(0), |_, _| {
+            let words = vec![Value::String("x".repeat(1000)); 2000];
+            Ok(Value::List(Rc::new(RefCell::new(words))))
+        });
+
+        assert!(matches!(
+            vm.eval("words();"),
+            Err(RuntimeError::MemoryLimitExceeded { .. })
+        ));
+
+        let list = Value::List(Rc::new(RefCell::new(vec![Value::Nil; 1 << 12])));
+
+        vm.define_native("existing", Some(0), move |_, _| Ok(list.clone()));
+
+        vm.eval("let i = 0; while i < 1000 { existing(); i += 1; }")
+            .unwrap();
+
+        vm.set_limits(Limits {
+            call_depth: Some(64),
+            ..Limits::default()
+        });
+
+        assert!(matches!(
+            vm.eval("fn f(n) { return f(n + 1); } f(0);"),
+            Err(RuntimeError::CallDepthExceeded { limit: 64 })
+        ));
+
+        vm.eval("fn g(n) { if n == 0 { return 0; } return g(n - 1); }")
+            .unwrap();
+
+        assert!(matches!(vm.eval("g(50)"), Ok(Some(Value::Integer(0)))));
+
+        vm.set_limits(Limits {
+            stack_size: Some(100),
+            ..Limits::default()
+        });
+
+        assert!(matches!(
+            vm.eval("fn f(a, b, c) { return f(a, b, c); } f(1, 2, 3);"),
+            Err(RuntimeError::StackSizeExceeded { limit: 100 })
+        ));
+
+        assert!(matches!(
+            vm.eval("fn f(a, b, c) { return f(a, b, c); } try { f(1, 2, 3); } catch (e) {}"),
+            Err(RuntimeError::StackSizeExceeded { .. })
+        ));
+
+        // Calls made from outside of a script are counted separately too.
+        vm.set_limits(Limits {
+            instructions: Some(1000),
+            ..Limits::default()
+        });
+
+        vm.eval("fn count(n) { let i = 0; while i < n { i += 1; } return i; }")
+            .unwrap();
+
+        let count: Value = vm.get_global("count").unwrap();
+
+        for _ in 0..3 {
+            assert!(matches!(
+                vm.call(&count, &[Value::Integer(50)]),
+                Ok(Value::Integer(50))
+            ));
+        }
+
+        // A failed call leaves the stack as it was.
+        let depth = vm.stack.len();
+
+        assert!(vm.call(&Value::Nil, &[Value::Integer(1)]).is_err());
+        assert!(vm.call(&count, &[]).is_err());
+        assert_eq!(depth, vm.stack.len());
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut vm = VirtualMachine::new();
+        let interrupt = vm.interrupt_handle();
+
+        vm.define_native("stop", Some(0), move |_, _| {
+            interrupt.store(true, Ordering::SeqCst);
+            Ok(Value::Nil)
+        });
+
+        assert!(matches!(
+            vm.eval("try { stop(); while true {} } catch (e) {}"),
+            Err(RuntimeError::Interrupted)
+        ));
+
+        assert!(matches!(
+            vm.eval("fn f() { return f(); } stop(); f();"),
+            Err(RuntimeError::Interrupted)
+        ));
+
+        // The flag is cleared once the script has been stopped.
+        assert!(!vm.interrupt_handle().load(Ordering::SeqCst));
+        assert!(matches!(vm.eval("1 + 2"), Ok(Some(Value::Integer(3)))));
+
+        // An interrupt that is requested before a run starts isn't lost.
+        vm.interrupt_handle().store(true, Ordering::SeqCst);
+
+        assert!(matches!(
+            vm.eval("while true {}"),
+            Err(RuntimeError::Interrupted)
+        ));
+
+        let f: Value = vm.eval("|| { while true {} }").unwrap().unwrap();
+        vm.interrupt_handle().store(true, Ordering::SeqCst);
+
+        assert!(matches!(vm.call(&f, &[]), Err(RuntimeError::Interrupted)));
+        assert!(!vm.interrupt_handle().load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: i32, h: i32 },
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Level {
+        name: String,
+        size: (u32, u32),
+        spawn: Option<Vec<i64>>,
+        shapes: Vec<Shape>,
+        tags: std::collections::HashMap<String, bool>,
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut tags = std::collections::HashMap::new();
+        tags.insert("hard".to_string(), true);
+
+        let level = Level {
+            name: "Cave".to_string(),
+            size: (64, 32),
+            spawn: None,
+            shapes: vec![Shape::Point, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+            tags,
+        };
+
+        let value = to_value(&level).unwrap();
+
+        assert_eq!(
+            "[\"name\": \"Cave\", \"shapes\": [\"Point\", [\"Circle\": 1.5], [\"Rect\": [\"h\": 3, \"w\": 2]]], \"size\": [64, 32], \"spawn\": nil, \"tags\": [\"hard\": true]]",
+            value.to_string()
+        );
+
+        assert_eq!(level, from_value(value.clone()).unwrap());
+
+        let copy: Value = from_value(value.clone()).unwrap();
+        assert_eq!(value.to_string(), to_value(&copy).unwrap().to_string());
+
+        let mut vm = VirtualMachine::new();
+        vm.set_global("level", value);
+        vm.eval("level.spawn = [1, 2]; level.size[0] = 128;")
+            .unwrap();
+
+        let level: Level = from_value(vm.get_global("level").unwrap()).unwrap();
+        assert_eq!(Some(vec![1, 2]), level.spawn);
+        assert_eq!((128, 32), level.size);
+
+        assert!(matches!(
+            from_value::<Level>(Value::Integer(1)),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            to_value(&vm.eval("|x| x").unwrap().unwrap()),
+            Err(RuntimeError::InvalidOperation { .. })
+        ));
+
+        assert!(matches!(
+            to_value(&u64::MAX),
+            Err(RuntimeError::IntegerOverflow)
+        ));
     }
 }
@@ -0,0 +1,28 @@
+/// Limits on the resources that scripts can use, so that untrusted code can be
+/// stopped if it runs away.
+///
+/// Usage is counted from the start of each call to `VirtualMachine::run` (and
+/// so each call to `eval` or `exec_file`), and of each call to `call` or
+/// `resume` that isn't made from inside a script. Exceeding a limit raises an
+/// error that scripts cannot catch. Each limit is disabled if it is set to `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of instructions that can be executed.
+    pub instructions: Option<u64>,
+
+    /// The number of bytes that can be allocated for strings, lists, maps and
+    /// other objects on the heap.
+    ///
+    /// This is an estimate of the total amount allocated, rather than the amount
+    /// in use at any one time - memory that is freed doesn't count towards the
+    /// limit again. Objects that scripts create are counted as they're created,
+    /// and the values that native functions return are counted once they
+    /// return, but memory that natives only use while they're running isn't.
+    pub memory: Option<usize>,
+
+    /// The number of function calls that can be nested within a fiber.
+    pub call_depth: Option<usize>,
+
+    /// The number of values that can be on a fiber's stack.
+    pub stack_size: Option<usize>,
+}
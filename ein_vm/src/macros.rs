@@ -1,15 +1,118 @@
 #![macro_use]
 
+/// Implements an arithmetic instruction.
+///
+/// Operations on two integers are checked, and produce an integer. If either side
+/// is a float, the other side is promoted to a float as well. Other operands are
+/// passed to the operator method of userdata.
 macro_rules! arith_impl {
+    ($self:ident, $name:literal, $method:literal, $op:tt, $checked:ident) => {
+        {
+            let rhs = $self.stack.pop().unwrap();
+            let lhs = $self.stack.pop().unwrap();
+
+            let result = match (lhs, rhs) {
+                (Value::Integer(a), Value::Integer(b)) => match a.$checked(b) {
+                    Some(v) => Value::Integer(v),
+                    None => return Err(RuntimeError::IntegerOverflow),
+                },
+                (Value::Integer(a), Value::Number(b)) => Value::Number(a as f64 $op b),
+                (Value::Number(a), Value::Integer(b)) => Value::Number(a $op b as f64),
+                (Value::Number(a), Value::Number(b)) => Value::Number(a $op b),
+                (other_a, other_b) => $self.binary_operator($name, $method, other_a, other_b)?,
+            };
+
+            $self.stack.push(result);
+        }
+    }
+}
+
+/// Implements a comparison instruction.
+///
+/// Integers are compared against floats exactly, in the same way as `==`. If
+/// either side is userdata, its operator method is called instead - with the
+/// operands in reverse order if `swapped` is given, so that `a > b` can be
+/// implemented as `b < a`.
+macro_rules! compare_impl {
+    ($self:ident, $op:tt, $method:literal, swapped) => {
+        compare_impl!(@impl $self, $op, $method, true)
+    };
+
+    ($self:ident, $op:tt, $method:literal) => {
+        compare_impl!(@impl $self, $op, $method, false)
+    };
+
+    (@impl $self:ident, $op:tt, $method:literal, $swapped:expr) => {
+        {
+            let rhs = $self.stack.pop().unwrap();
+            let lhs = $self.stack.pop().unwrap();
+
+            let result = match (&lhs, &rhs) {
+                (Value::Integer(a), Value::Integer(b)) => a $op b,
+                (Value::Integer(a), Value::Number(b)) => compare_integer_number(*a, *b)
+                    .is_some_and(|ordering| ordering $op std::cmp::Ordering::Equal),
+                (Value::Number(a), Value::Integer(b)) => compare_integer_number(*b, *a)
+                    .is_some_and(|ordering| ordering.reverse() $op std::cmp::Ordering::Equal),
+                (Value::Number(a), Value::Number(b)) => a $op b,
+                (Value::String(a), Value::String(b)) => a $op b,
+                (Value::UserData(_), _) | (_, Value::UserData(_)) => {
+                    let (a, b) = if $swapped { (rhs, lhs) } else { (lhs, rhs) };
+                    is_truthy(&$self.binary_operator("compare", $method, a, b)?)
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidOperation {
+                        reason: format!("cannot compare {} and {}", lhs, rhs),
+                    })
+                }
+            };
+
+            $self.stack.push(Value::Boolean(result));
+        }
+    };
+}
+
+/// Implements a bitwise instruction, which is only valid on integers.
+macro_rules! bitwise_impl {
     ($self:ident, $name:literal, $op:tt) => {
         {
             let rhs = $self.stack.pop().unwrap();
             let lhs = $self.stack.pop().unwrap();
 
             match (lhs, rhs) {
-                (Value::Number(a), Value::Number(b)) => $self.stack.push(Value::Number(a $op b)),
-                (other_a, other_b) => panic!("Cannot {} {} and {}", $name, other_a, other_b),
+                (Value::Integer(a), Value::Integer(b)) => $self.stack.push(Value::Integer(a $op b)),
+                (other_a, other_b) => {
+                    return Err(RuntimeError::InvalidOperation {
+                        reason: format!("cannot {} {} and {}", $name, other_a, other_b),
+                    })
+                }
             }
         }
     }
 }
+
+/// Implements a shift instruction. Shifting by a negative amount, or by more
+/// than 63 bits, is an error.
+macro_rules! shift_impl {
+    ($self:ident, $checked:ident) => {{
+        let rhs = $self.stack.pop().unwrap();
+        let lhs = $self.stack.pop().unwrap();
+
+        match (lhs, rhs) {
+            (Value::Integer(a), Value::Integer(b)) => {
+                match u32::try_from(b).ok().and_then(|b| a.$checked(b)) {
+                    Some(v) => $self.stack.push(Value::Integer(v)),
+                    None => {
+                        return Err(RuntimeError::InvalidOperation {
+                            reason: format!("cannot shift by {} bits", b),
+                        })
+                    }
+                }
+            }
+            (other_a, other_b) => {
+                return Err(RuntimeError::InvalidOperation {
+                    reason: format!("cannot shift {} by {}", other_a, other_b),
+                })
+            }
+        }
+    }};
+}
@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::{NativeFunction, RuntimeError, Value, VirtualMachine};
+
+/// A namespace of global variables.
+///
+/// Each module that gets imported is run in its own namespace, and any code that
+/// isn't imported (e.g. the file passed to the CLI, or the REPL) is run in the
+/// main module.
+pub struct Module {
+    pub name: String,
+
+    /// The identifier that the module loader resolved the module to, if it was
+    /// imported. Imports within the module are resolved relative to this.
+    pub id: Option<String>,
+
+    pub globals: RefCell<HashMap<String, Value>>,
+}
+
+impl Module {
+    pub fn new(name: &str, id: Option<String>) -> Module {
+        Module {
+            name: name.to_string(),
+            id,
+            globals: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Defines a global variable in the module.
+    pub fn define(&self, name: &str, value: Value) {
+        self.globals.borrow_mut().insert(name.to_string(), value);
+    }
+
+    /// Defines a function in the module that is implemented in Rust.
+    ///
+    /// If `arity` is `None`, the function can be called with any number of
+    /// arguments.
+    pub fn define_native<F>(&self, name: &str, arity: Option<usize>, function: F)
+    where
+        F: Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.define(name, Value::NativeFunction(Rc::new(native)));
+    }
+}
+
+// The globals aren't included, as they usually contain functions that refer
+// back to the module.
+impl Debug for Module {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Module")
+            .field("name", &self.name)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// Finds and loads the source code of modules, on behalf of the VM.
+pub trait ModuleLoader {
+    /// Resolves the path given in an import to a unique identifier for the
+    /// module, or returns `None` if it cannot be found.
+    ///
+    /// `from` is the identifier of the importing module, or `None` if the
+    /// import is in the main module.
+    fn resolve(&self, path: &str, from: Option<&str>) -> Option<String>;
+
+    /// Loads the source code of a module, given its resolved identifier.
+    fn load(&self, id: &str) -> Result<String, Box<dyn Error>>;
+}
+
+/// Loads modules from the filesystem.
+///
+/// Imports are first resolved relative to the directory of the importing
+/// module, and then relative to each of the search paths in turn. If an import
+/// has no extension, `.ein` is added.
+#[derive(Debug, Clone)]
+pub struct FileSystemLoader {
+    search_paths: Vec<PathBuf>,
+}
+
+impl FileSystemLoader {
+    pub fn new(search_paths: Vec<PathBuf>) -> FileSystemLoader {
+        FileSystemLoader { search_paths }
+    }
+
+    pub fn add_search_path<P>(&mut self, path: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.search_paths.push(path.into());
+    }
+}
+
+/// By default, modules are searched for in the current directory.
+impl Default for FileSystemLoader {
+    fn default() -> FileSystemLoader {
+        FileSystemLoader::new(vec![PathBuf::from(".")])
+    }
+}
+
+impl ModuleLoader for FileSystemLoader {
+    fn resolve(&self, path: &str, from: Option<&str>) -> Option<String> {
+        let mut file = PathBuf::from(path);
+
+        if file.extension().is_none() {
+            file.set_extension("ein");
+        }
+
+        from.and_then(|from| Path::new(from).parent())
+            .into_iter()
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find(|candidate| candidate.is_file())
+            .and_then(|found| found.canonicalize().ok())
+            .map(|found| found.to_string_lossy().into_owned())
+    }
+
+    fn load(&self, id: &str) -> Result<String, Box<dyn Error>> {
+        Ok(fs::read_to_string(id)?)
+    }
+}
+
+/// Loads modules from an in-memory map of paths to source code.
+///
+/// Paths are separated by `/`. Imports that start with `./` or `../` are
+/// resolved relative to the importing module - anything else is looked up as-is.
+/// If an import can't be found, it is retried with `.ein` added.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader::default()
+    }
+
+    pub fn add_module(&mut self, path: &str, source: &str) {
+        self.modules.insert(path.to_string(), source.to_string());
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn resolve(&self, path: &str, from: Option<&str>) -> Option<String> {
+        let mut segments: Vec<&str> = vec![];
+
+        if path.starts_with("./") || path.starts_with("../") {
+            if let Some(from) = from {
+                segments.extend(from.split('/'));
+                segments.pop();
+            }
+        }
+
+        for segment in path.split('/') {
+            match segment {
+                "." | "" => {}
+                ".." => {
+                    segments.pop()?;
+                }
+                segment => segments.push(segment),
+            }
+        }
+
+        let id = segments.join("/");
+
+        if self.modules.contains_key(&id) {
+            Some(id)
+        } else {
+            let id = id + ".ein";
+            self.modules.get(&id).map(|_| id)
+        }
+    }
+
+    fn load(&self, id: &str) -> Result<String, Box<dyn Error>> {
+        match self.modules.get(id) {
+            Some(source) => Ok(source.clone()),
+            None => Err(format!("{} does not exist", id).into()),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use super::{arity_error, method_table};
+use crate::{Fiber, Module, RuntimeError, Value, VirtualMachine};
+
+/// Creates the `Fiber` global, which holds the functions for creating fibers,
+/// getting the current one, and yielding from it.
+pub fn module() -> Module {
+    let fiber = Module::new("Fiber", None);
+
+    fiber.define_native("new", Some(1), new);
+    fiber.define_native("current", Some(0), current);
+    fiber.define_native("yield", None, yield_value);
+
+    fiber
+}
+
+/// Creates the methods that can be called on fibers.
+///
+/// `isDone` is named after Wren's method, and `is_done` is an alias for it that
+/// matches the snake_case names of the other built-in methods.
+pub fn methods() -> HashMap<String, Value> {
+    method_table(&[
+        ("call", None, call),
+        ("transfer", None, transfer),
+        ("isDone", Some(1), is_done),
+        ("is_done", Some(1), is_done),
+    ])
+}
+
+fn fiber<'a>(method: &str, value: &'a Value) -> Result<&'a Rc<Fiber>, RuntimeError> {
+    match value {
+        Value::Fiber(fiber) => Ok(fiber),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} expects a fiber, but got {}", method, other),
+        }),
+    }
+}
+
+/// Splits the arguments of a method that can optionally pass a value to a
+/// fiber.
+fn fiber_and_value<'a>(
+    method: &str,
+    args: &'a [Value],
+) -> Result<(&'a Rc<Fiber>, Value), RuntimeError> {
+    match args {
+        [f] => Ok((fiber(method, f)?, Value::Nil)),
+        [f, value] => Ok((fiber(method, f)?, value.clone())),
+        _ => Err(arity_error(method, 0, Some(1), args.len() - 1)),
+    }
+}
+
+/// Creates a fiber that will run a function, which can take up to one argument.
+fn new(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Closure(closure) if closure.function.required() <= 1 => {
+            Ok(Value::Fiber(Rc::new(Fiber::new(closure.clone()))))
+        }
+        Value::Closure(_) => Err(RuntimeError::InvalidOperation {
+            reason: "a fiber's function cannot take more than one argument".to_string(),
+        }),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} cannot be run in a fiber", other),
+        }),
+    }
+}
+
+fn current(vm: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Fiber(vm.fiber.clone()))
+}
+
+/// Pauses the current fiber, passing a value back to the fiber that called it.
+fn yield_value(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args {
+        [] => vm.yield_fiber(Value::Nil)?,
+        [value] => vm.yield_fiber(value.clone())?,
+        _ => return Err(arity_error("yield", 0, Some(1), args.len())),
+    }
+
+    Ok(Value::Nil)
+}
+
+/// Runs a fiber until it yields or finishes, passing it a value. The result is
+/// the value it yields or returns.
+fn call(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (fiber, value) = fiber_and_value("call", args)?;
+    vm.call_fiber(fiber, value)?;
+
+    Ok(Value::Nil)
+}
+
+/// Switches to a fiber, passing it a value. Unlike `call`, the fiber won't
+/// return to the current one when it yields or finishes.
+fn transfer(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (fiber, value) = fiber_and_value("transfer", args)?;
+    vm.transfer_fiber(fiber, value)?;
+
+    Ok(Value::Nil)
+}
+
+/// Whether the fiber's function has returned (or thrown an error).
+fn is_done(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(fiber("isDone", &args[0])?.is_done()))
+}
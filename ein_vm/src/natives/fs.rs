@@ -0,0 +1,74 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use super::io::io_error;
+use super::new_list;
+use crate::{Module, RuntimeError, Value, VirtualMachine};
+
+/// Creates the `fs` module, which gives access to the filesystem.
+///
+/// Relative paths are resolved relative to the current directory of the
+/// process.
+pub fn module() -> Module {
+    let fs = Module::new("fs", None);
+
+    fs.define_native("read", Some(1), read);
+    fs.define_native("write", Some(2), write);
+    fs.define_native("append", Some(2), append);
+    fs.define_native("exists", Some(1), exists);
+    fs.define_native("list_dir", Some(1), list_dir);
+
+    fs
+}
+
+fn path<'a>(function: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(v) => Ok(v),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("fs.{} expects a path, but got {}", function, other),
+        }),
+    }
+}
+
+/// Reads the contents of a file as a string.
+fn read(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let contents = fs::read_to_string(path("read", &args[0])?).map_err(io_error)?;
+    Ok(Value::String(contents))
+}
+
+/// Writes a value to a file, replacing the file if it already exists.
+fn write(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    fs::write(path("write", &args[0])?, args[1].stringify()).map_err(io_error)?;
+    Ok(Value::Nil)
+}
+
+/// Writes a value to the end of a file, creating the file if it doesn't exist.
+fn append(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path("append", &args[0])?)
+        .and_then(|mut file| file.write_all(args[1].stringify().as_bytes()))
+        .map_err(io_error)?;
+
+    Ok(Value::Nil)
+}
+
+fn exists(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = path("exists", &args[0])?;
+    Ok(Value::Boolean(fs::metadata(path).is_ok()))
+}
+
+/// Lists the names of the entries in a directory, in sorted order.
+fn list_dir(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut names = vec![];
+
+    for entry in fs::read_dir(path("list_dir", &args[0])?).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+
+    names.sort();
+
+    Ok(new_list(names.into_iter().map(Value::String).collect()))
+}
@@ -0,0 +1,75 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{Module, RuntimeError, Value, VirtualMachine};
+
+/// Creates the `io` module, which gives access to the standard input and
+/// output streams.
+pub fn module() -> Module {
+    let io = Module::new("io", None);
+
+    io.define_native("read_line", Some(0), read_line);
+    io.define_native("write", None, write);
+    io.define_native("write_err", None, write_err);
+    io.define_native("eprint", None, eprint);
+
+    io
+}
+
+pub fn io_error(error: io::Error) -> RuntimeError {
+    RuntimeError::Io {
+        reason: error.to_string(),
+    }
+}
+
+fn stringify_all(args: &[Value]) -> String {
+    args.iter().map(Value::stringify).collect()
+}
+
+/// Reads a line from stdin, without the trailing newline. Returns nil once
+/// there is no more input.
+fn read_line(_: &mut VirtualMachine, _: &[Value]) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+
+    if io::stdin().lock().read_line(&mut line).map_err(io_error)? == 0 {
+        return Ok(Value::Nil);
+    }
+
+    if line.ends_with('\n') {
+        line.pop();
+
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+
+    Ok(Value::String(line))
+}
+
+/// Writes the arguments to stdout, without adding spaces or a newline.
+fn write(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut stdout = io::stdout();
+
+    stdout
+        .write_all(stringify_all(args).as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(io_error)?;
+
+    Ok(Value::Nil)
+}
+
+/// Writes the arguments to stderr, without adding spaces or a newline.
+fn write_err(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    io::stderr()
+        .write_all(stringify_all(args).as_bytes())
+        .map_err(io_error)?;
+
+    Ok(Value::Nil)
+}
+
+/// Prints the arguments to stderr, separated by spaces.
+fn eprint(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let parts: Vec<String> = args.iter().map(Value::stringify).collect();
+    eprintln!("{}", parts.join(" "));
+
+    Ok(Value::Nil)
+}
@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+
+use hashbrown::HashMap;
+
+use super::method_table;
+use crate::{Iter, RuntimeError, Value, VirtualMachine};
+
+/// Creates an iterator over a list, map, range, string or userdata - this is
+/// what `for` loops use to get their values. Maps are iterated over by key.
+pub fn iterate(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Iterator(vm.iterate(&args[0])?))
+}
+
+/// Creates the `iter` method of lists and ranges, which is the same as calling
+/// `iterate` on them.
+///
+/// Maps don't have methods, as `map.name` gets the value of a key, so they
+/// have to be passed to `iterate` instead.
+pub fn iterable_methods() -> HashMap<String, Value> {
+    method_table(&[("iter", Some(1), iterate)])
+}
+
+/// Creates the methods that can be called on iterators.
+///
+/// `next` returns nil once the iterator has run out of values, and `is_done`
+/// can be used to tell that apart from a nil value. A generator's return value
+/// is never returned by `next`, just as it isn't seen by `for` loops. `iter`
+/// returns the iterator itself.
+pub fn methods() -> HashMap<String, Value> {
+    method_table(&[
+        ("next", Some(1), next),
+        ("is_done", Some(1), is_done),
+        ("iter", Some(1), iterate),
+    ])
+}
+
+fn iter<'a>(method: &str, value: &'a Value) -> Result<&'a RefCell<Iter>, RuntimeError> {
+    match value {
+        Value::Iterator(iter) => Ok(iter),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} expects an iterator, but got {}", method, other),
+        }),
+    }
+}
+
+/// Gets the next value from the iterator. If it is a generator, the value is
+/// passed back when it yields, rather than being returned here.
+fn next(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let iter = iter("next", &args[0])?;
+    Ok(vm.next_value(iter)?.unwrap_or(Value::Nil))
+}
+
+fn is_done(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(
+        iter("is_done", &args[0])?.borrow().is_done(),
+    ))
+}
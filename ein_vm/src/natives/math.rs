@@ -0,0 +1,123 @@
+use std::f64::consts;
+
+use super::arity_error;
+use crate::{Module, RuntimeError, Value};
+
+/// Creates the `math` module.
+///
+/// Every function accepts both integers and floats, and raises an error if it
+/// is passed anything else.
+pub fn module() -> Module {
+    let math = Module::new("math", None);
+
+    math.define("pi", Value::Number(consts::PI));
+    math.define("tau", Value::Number(consts::TAU));
+    math.define("e", Value::Number(consts::E));
+    math.define("inf", Value::Number(f64::INFINITY));
+    math.define("nan", Value::Number(f64::NAN));
+
+    define_float(&math, "sqrt", f64::sqrt);
+    define_float(&math, "exp", f64::exp);
+    define_float(&math, "ln", f64::ln);
+    define_float(&math, "log2", f64::log2);
+    define_float(&math, "log10", f64::log10);
+    define_float(&math, "sin", f64::sin);
+    define_float(&math, "cos", f64::cos);
+    define_float(&math, "tan", f64::tan);
+    define_float(&math, "asin", f64::asin);
+    define_float(&math, "acos", f64::acos);
+    define_float(&math, "atan", f64::atan);
+
+    define_rounding(&math, "floor", f64::floor);
+    define_rounding(&math, "ceil", f64::ceil);
+    define_rounding(&math, "round", f64::round);
+    define_rounding(&math, "trunc", f64::trunc);
+
+    math.define_native("atan2", Some(2), |_, args| {
+        let y = number("atan2", &args[0])?;
+        let x = number("atan2", &args[1])?;
+
+        Ok(Value::Number(y.atan2(x)))
+    });
+
+    math.define_native("abs", Some(1), |_, args| match args[0] {
+        Value::Integer(v) => v
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or(RuntimeError::IntegerOverflow),
+        ref other => Ok(Value::Number(number("abs", other)?.abs())),
+    });
+
+    math.define_native("min", None, |_, args| extreme("min", args, |a, b| a < b));
+    math.define_native("max", None, |_, args| extreme("max", args, |a, b| a > b));
+
+    math.define_native("is_nan", Some(1), |_, args| {
+        Ok(Value::Boolean(number("is_nan", &args[0])?.is_nan()))
+    });
+
+    math.define_native("is_infinite", Some(1), |_, args| {
+        Ok(Value::Boolean(
+            number("is_infinite", &args[0])?.is_infinite(),
+        ))
+    });
+
+    math.define_native("is_finite", Some(1), |_, args| {
+        Ok(Value::Boolean(number("is_finite", &args[0])?.is_finite()))
+    });
+
+    math
+}
+
+/// Converts a numeric argument to a float.
+fn number(function: &str, value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Integer(v) => Ok(*v as f64),
+        Value::Number(v) => Ok(*v),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("math.{} expects a number, but got {}", function, other),
+        }),
+    }
+}
+
+/// Defines a function of one argument that is always calculated as a float.
+fn define_float(math: &Module, name: &'static str, function: fn(f64) -> f64) {
+    math.define_native(name, Some(1), move |_, args| {
+        Ok(Value::Number(function(number(name, &args[0])?)))
+    });
+}
+
+/// Defines a rounding function. Integers are already rounded, so they are
+/// returned unchanged - floats are rounded to another float, so that infinity
+/// and NaN can pass through.
+fn define_rounding(math: &Module, name: &'static str, function: fn(f64) -> f64) {
+    math.define_native(name, Some(1), move |_, args| match args[0] {
+        Value::Integer(v) => Ok(Value::Integer(v)),
+        ref other => Ok(Value::Number(function(number(name, other)?))),
+    });
+}
+
+/// Finds the smallest or largest of the arguments, depending on `replaces`.
+///
+/// The original value is returned, so integers stay as integers.
+fn extreme<F>(name: &str, args: &[Value], replaces: F) -> Result<Value, RuntimeError>
+where
+    F: Fn(f64, f64) -> bool,
+{
+    let (first, rest) = args
+        .split_first()
+        .ok_or_else(|| arity_error(name, 1, None, 0))?;
+
+    let mut result = first;
+    let mut result_number = number(name, first)?;
+
+    for arg in rest {
+        let arg_number = number(name, arg)?;
+
+        if replaces(arg_number, result_number) {
+            result = arg;
+            result_number = arg_number;
+        }
+    }
+
+    Ok(result.clone())
+}
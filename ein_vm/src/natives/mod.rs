@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::{is_truthy, NativeFunction, RuntimeError, Value, VirtualMachine};
+
+mod fiber;
+pub mod fs;
+pub mod io;
+mod iterator;
+mod math;
+mod string;
+
+pub fn register(vm: &mut VirtualMachine) {
+    vm.define_native("print", None, print);
+    vm.define_native("map", Some(2), map);
+    vm.define_native("filter", Some(2), filter);
+    vm.define_native("sort", None, sort);
+    vm.define_native("iterate", Some(1), iterator::iterate);
+
+    vm.define_module(math::module());
+
+    vm.list_methods = iterator::iterable_methods();
+    vm.range_methods = iterator::iterable_methods();
+    vm.string_methods = string::methods();
+
+    vm.builtins
+        .insert("Fiber".to_string(), Value::Module(Rc::new(fiber::module())));
+
+    vm.fiber_methods = fiber::methods();
+    vm.iterator_methods = iterator::methods();
+}
+
+/// Gets a copy of the items in a list argument.
+///
+/// The items are copied so that callbacks are free to modify the original list.
+fn list_items(value: &Value) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(list.borrow().clone()),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} is not a list", other),
+        }),
+    }
+}
+
+type Method = fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>;
+
+/// Creates the table of methods for one of the built-in types.
+///
+/// The arities include the value that the method is called on.
+pub(crate) fn method_table(methods: &[(&str, Option<usize>, Method)]) -> HashMap<String, Value> {
+    methods
+        .iter()
+        .map(|(name, arity, method)| {
+            let native = NativeFunction::new(name, *arity, *method);
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect()
+}
+
+/// The error for a function that is called with too few or too many arguments,
+/// which is used for natives and functions defined in scripts alike. If `max`
+/// is `None`, there's no upper limit.
+///
+/// For methods, the counts don't include the value that the method was called
+/// on.
+pub(crate) fn arity_error(
+    signature: &str,
+    min: usize,
+    max: Option<usize>,
+    found: usize,
+) -> RuntimeError {
+    let expected = match max {
+        Some(max) if max == min => min.to_string(),
+        Some(max) => format!("{} to {}", min, max),
+        None => format!("at least {}", min),
+    };
+
+    RuntimeError::InvalidArguments {
+        signature: signature.to_string(),
+        reason: format!("expected {} arguments but got {}", expected, found),
+    }
+}
+
+fn new_list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+/// Prints the arguments, separated by spaces.
+fn print(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let parts = args
+        .iter()
+        .map(|arg| vm.stringify(arg))
+        .collect::<Result<Vec<String>, RuntimeError>>()?;
+
+    println!("{}", parts.join(" "));
+
+    Ok(Value::Nil)
+}
+
+/// Creates a new list by calling a function on each item of a list.
+fn map(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = list_items(&args[0])?;
+    let mut result = Vec::with_capacity(items.len());
+
+    for item in items {
+        result.push(vm.call(&args[1], &[item])?);
+    }
+
+    Ok(new_list(result))
+}
+
+/// Creates a new list containing the items of a list for which a function
+/// returns a truthy value.
+fn filter(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = list_items(&args[0])?;
+    let mut result = vec![];
+
+    for item in items {
+        if is_truthy(&vm.call(&args[1], std::slice::from_ref(&item))?) {
+            result.push(item);
+        }
+    }
+
+    Ok(new_list(result))
+}
+
+/// Creates a sorted copy of a list.
+///
+/// By default, the items are sorted in ascending order. Alternatively, a
+/// function can be passed as the second argument, which should return whether
+/// its first argument should come before its second.
+fn sort(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = match args {
+        [list] | [list, _] => list_items(list)?,
+        _ => return Err(arity_error("sort", 1, Some(2), args.len())),
+    };
+
+    let sorted = match args.get(1) {
+        Some(compare) => merge_sort(items, &mut |a, b| {
+            Ok(is_truthy(&vm.call(compare, &[a.clone(), b.clone()])?))
+        })?,
+        None => merge_sort(items, &mut less_than)?,
+    };
+
+    Ok(new_list(sorted))
+}
+
+fn less_than(lhs: &Value, rhs: &Value) -> Result<bool, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(a < b),
+        (Value::Integer(a), Value::Number(b)) => Ok((*a as f64) < *b),
+        (Value::Number(a), Value::Integer(b)) => Ok(*a < (*b as f64)),
+        (Value::Number(a), Value::Number(b)) => Ok(a < b),
+        (Value::String(a), Value::String(b)) => Ok(a < b),
+        _ => Err(RuntimeError::InvalidOperation {
+            reason: format!("cannot compare {} and {}", lhs, rhs),
+        }),
+    }
+}
+
+/// A stable merge sort, which (unlike the standard library's sorts) allows the
+/// comparison to fail, and copes with comparisons that are inconsistent.
+fn merge_sort<F>(mut items: Vec<Value>, less_than: &mut F) -> Result<Vec<Value>, RuntimeError>
+where
+    F: FnMut(&Value, &Value) -> Result<bool, RuntimeError>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+
+    let left = merge_sort(items, less_than)?;
+    let right = merge_sort(right, less_than)?;
+
+    let mut result = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Items from the left are taken first when they're equal, to keep the
+        // sort stable.
+        if less_than(r, l)? {
+            result.extend(right.next());
+        } else {
+            result.extend(left.next());
+        }
+    }
+
+    result.extend(left);
+    result.extend(right);
+
+    Ok(result)
+}
@@ -0,0 +1,204 @@
+use hashbrown::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::iterator::iterate;
+use super::{arity_error, list_items, method_table, new_list};
+use crate::{RuntimeError, Value, VirtualMachine};
+
+/// Creates the methods that can be called on strings.
+///
+/// Strings are indexed and measured in chars (i.e. Unicode scalar values), rather
+/// than bytes - so `"héllo".len()` is 5, and slicing can never split a char in
+/// half. Note that a single user-perceived character (a grapheme cluster, such
+/// as an emoji with a skin tone modifier) can be made up of several chars - the
+/// `graphemes` method can be used when that distinction matters.
+pub fn methods() -> HashMap<String, Value> {
+    method_table(&[
+        ("len", Some(1), len),
+        ("chars", Some(1), chars),
+        ("graphemes", Some(1), graphemes),
+        ("upper", Some(1), upper),
+        ("lower", Some(1), lower),
+        ("trim", Some(1), trim),
+        ("trim_start", Some(1), trim_start),
+        ("trim_end", Some(1), trim_end),
+        ("split", None, split),
+        ("join", Some(2), join),
+        ("replace", Some(3), replace),
+        ("contains", Some(2), contains),
+        ("starts_with", Some(2), starts_with),
+        ("ends_with", Some(2), ends_with),
+        ("find", Some(2), find),
+        ("slice", None, slice),
+        ("iter", Some(1), iterate),
+    ])
+}
+
+fn string<'a>(method: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(v) => Ok(v),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} expects a string, but got {}", method, other),
+        }),
+    }
+}
+
+/// Converts a char index into a byte offset, allowing the index one past the
+/// end of the string.
+fn byte_offset(s: &str, index: &Value) -> Result<usize, RuntimeError> {
+    let len = s.chars().count();
+
+    match index {
+        Value::Integer(i) if *i >= 0 && (*i as usize) <= len => Ok(s
+            .char_indices()
+            .nth(*i as usize)
+            .map_or(s.len(), |(offset, _)| offset)),
+        Value::Integer(i) => Err(RuntimeError::InvalidOperation {
+            reason: format!(
+                "index {} is out of bounds for a string of length {}",
+                i, len
+            ),
+        }),
+        other => Err(RuntimeError::InvalidOperation {
+            reason: format!("{} cannot be used as a string index", other),
+        }),
+    }
+}
+
+fn strings<'a, I>(parts: I) -> Value
+where
+    I: Iterator<Item = &'a str>,
+{
+    new_list(parts.map(|s| Value::String(s.to_string())).collect())
+}
+
+/// The number of chars in the string.
+fn len(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("len", &args[0])?;
+    Ok(Value::Integer(s.chars().count() as i64))
+}
+
+/// Splits the string into a list of chars.
+fn chars(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("chars", &args[0])?;
+    Ok(new_list(
+        s.chars().map(|c| Value::String(c.to_string())).collect(),
+    ))
+}
+
+/// Splits the string into a list of extended grapheme clusters.
+fn graphemes(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("graphemes", &args[0])?;
+    Ok(strings(s.graphemes(true)))
+}
+
+fn upper(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(string("upper", &args[0])?.to_uppercase()))
+}
+
+fn lower(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(string("lower", &args[0])?.to_lowercase()))
+}
+
+fn trim(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::String(string("trim", &args[0])?.trim().to_string()))
+}
+
+fn trim_start(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("trim_start", &args[0])?;
+    Ok(Value::String(s.trim_start().to_string()))
+}
+
+fn trim_end(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("trim_end", &args[0])?;
+    Ok(Value::String(s.trim_end().to_string()))
+}
+
+/// Splits the string on a separator, or on whitespace if no separator is given.
+fn split(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args {
+        [s] => Ok(strings(string("split", s)?.split_whitespace())),
+        [s, separator] => {
+            let s = string("split", s)?;
+            let separator = string("split", separator)?;
+
+            if separator.is_empty() {
+                return Err(RuntimeError::InvalidOperation {
+                    reason: "cannot split on an empty string".to_string(),
+                });
+            }
+
+            Ok(strings(s.split(separator)))
+        }
+        _ => Err(arity_error("split", 0, Some(1), args.len() - 1)),
+    }
+}
+
+/// Joins the items of a list together, using the string as a separator.
+fn join(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let separator = string("join", &args[0])?;
+    let parts: Vec<String> = list_items(&args[1])?.iter().map(Value::stringify).collect();
+
+    Ok(Value::String(parts.join(separator)))
+}
+
+fn replace(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("replace", &args[0])?;
+    let from = string("replace", &args[1])?;
+    let to = string("replace", &args[2])?;
+
+    Ok(Value::String(s.replace(from, to)))
+}
+
+fn contains(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("contains", &args[0])?;
+    Ok(Value::Boolean(s.contains(string("contains", &args[1])?)))
+}
+
+fn starts_with(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("starts_with", &args[0])?;
+    Ok(Value::Boolean(
+        s.starts_with(string("starts_with", &args[1])?),
+    ))
+}
+
+fn ends_with(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("ends_with", &args[0])?;
+    Ok(Value::Boolean(s.ends_with(string("ends_with", &args[1])?)))
+}
+
+/// Finds the char index of the first occurrence of a substring, or returns nil
+/// if it doesn't occur.
+fn find(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = string("find", &args[0])?;
+    let pattern = string("find", &args[1])?;
+
+    Ok(match s.find(pattern) {
+        Some(offset) => Value::Integer(s[..offset].chars().count() as i64),
+        None => Value::Nil,
+    })
+}
+
+/// Gets the chars between a start index (inclusive) and an end index
+/// (exclusive). If no end index is given, the rest of the string is returned.
+fn slice(_: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, start, end) = match args {
+        [s, start] => (string("slice", s)?, start, None),
+        [s, start, end] => (string("slice", s)?, start, Some(end)),
+        _ => return Err(arity_error("slice", 1, Some(2), args.len() - 1)),
+    };
+
+    let start = byte_offset(s, start)?;
+    let end = match end {
+        Some(end) => byte_offset(s, end)?,
+        None => s.len(),
+    };
+
+    if start > end {
+        return Err(RuntimeError::InvalidOperation {
+            reason: "the start of a slice cannot be after its end".to_string(),
+        });
+    }
+
+    Ok(Value::String(s[start..end].to_string()))
+}
@@ -0,0 +1,752 @@
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize, Serializer};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::{Key, RuntimeError, Value};
+
+/// Converts a Rust value into an Ein value.
+///
+/// Sequences and tuples become lists, and structs and maps become maps. Enum
+/// variants are represented the same way as in JSON: unit variants become a
+/// string, and other variants become a map with the variant name as its only
+/// key.
+pub fn to_value<T>(value: &T) -> Result<Value, RuntimeError>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(ValueSerializer)
+}
+
+/// Converts an Ein value into a Rust value, using the same representation as
+/// `to_value`.
+pub fn from_value<T>(value: Value) -> Result<T, RuntimeError>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(ValueDeserializer(value))
+}
+
+impl ser::Error for RuntimeError {
+    fn custom<T: Display>(msg: T) -> RuntimeError {
+        RuntimeError::InvalidOperation {
+            reason: msg.to_string(),
+        }
+    }
+}
+
+impl de::Error for RuntimeError {
+    fn custom<T: Display>(msg: T) -> RuntimeError {
+        RuntimeError::InvalidOperation {
+            reason: msg.to_string(),
+        }
+    }
+}
+
+fn new_list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+fn new_map(map: HashMap<Key, Value>) -> Value {
+    Value::Map(Rc::new(RefCell::new(map)))
+}
+
+/// Creates the map that represents an enum variant with data.
+fn variant(name: &str, value: Value) -> Value {
+    let mut map = HashMap::new();
+    map.insert(Key::String(name.to_string()), value);
+    new_map(map)
+}
+
+fn map_key(value: Value) -> Result<Key, RuntimeError> {
+    Key::from_value(&value).ok_or_else(|| RuntimeError::InvalidOperation {
+        reason: format!("{} cannot be used as a map key", value),
+    })
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Boolean(v) => serializer.serialize_bool(*v),
+            Value::Integer(v) => serializer.serialize_i64(*v),
+            Value::Number(v) => serializer.serialize_f64(*v),
+            Value::String(v) => serializer.serialize_str(v),
+            Value::List(v) => serializer.collect_seq(v.borrow().iter()),
+
+            Value::Map(v) => {
+                let map = v.borrow();
+
+                // Sort the entries so that the output is predictable.
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+
+                serializer.collect_map(entries)
+            }
+
+            other => Err(ser::Error::custom(format!(
+                "{} cannot be serialized",
+                other
+            ))),
+        }
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Key::Boolean(v) => serializer.serialize_bool(*v),
+            Key::Integer(v) => serializer.serialize_i64(*v),
+            Key::String(v) => serializer.serialize_str(v),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a value that can be represented in Ein")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E>
+    where
+        E: de::Error,
+    {
+        if v <= i64::MAX as u64 {
+            Ok(Value::Integer(v as i64))
+        } else {
+            Err(E::custom(format!("{} is too large for an integer", v)))
+        }
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Number(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+
+        Ok(new_list(items))
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut map = HashMap::new();
+
+        while let Some((key, value)) = access.next_entry::<Value, Value>()? {
+            let key = map_key(key).map_err(de::Error::custom)?;
+            map.insert(key, value);
+        }
+
+        Ok(new_map(map))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+/// Serializes Rust values into Ein values.
+pub struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, RuntimeError> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, RuntimeError> {
+        if v <= i64::MAX as u64 {
+            Ok(Value::Integer(v as i64))
+        } else {
+            Err(RuntimeError::IntegerOverflow)
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(f64::from(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, RuntimeError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, RuntimeError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, RuntimeError> {
+        let items = v.iter().map(|b| Value::Integer(i64::from(*b))).collect();
+        Ok(new_list(items))
+    }
+
+    fn serialize_none(self) -> Result<Value, RuntimeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value, RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, RuntimeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, RuntimeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, RuntimeError> {
+        Ok(Value::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<Value, RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(variant(variant_name, to_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, RuntimeError> {
+        Ok(SerializeList {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, RuntimeError> {
+        Ok(SerializeList {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, RuntimeError> {
+        Ok(SerializeMap {
+            variant: None,
+            map: HashMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeMap, RuntimeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, RuntimeError> {
+        Ok(SerializeMap {
+            variant: Some(variant),
+            map: HashMap::new(),
+            next_key: None,
+        })
+    }
+}
+
+/// Builds a list, for sequences, tuples and tuple variants.
+pub struct SerializeList {
+    variant: Option<&'static str>,
+    items: Vec<Value>,
+}
+
+impl SerializeList {
+    fn push<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.items.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, RuntimeError> {
+        let list = new_list(self.items);
+
+        match self.variant {
+            Some(name) => Ok(variant(name, list)),
+            None => Ok(list),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+/// Builds a map, for maps, structs and struct variants.
+pub struct SerializeMap {
+    variant: Option<&'static str>,
+    map: HashMap<Key, Value>,
+    next_key: Option<Key>,
+}
+
+impl SerializeMap {
+    fn finish(self) -> Result<Value, RuntimeError> {
+        let map = new_map(self.map);
+
+        match self.variant {
+            Some(name) => Ok(variant(name, map)),
+            None => Ok(map),
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.next_key = Some(map_key(to_value(key)?)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .next_key
+            .take()
+            .expect("serialize_value was called before serialize_key");
+
+        self.map.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.map
+            .insert(Key::String(key.to_string()), to_value(value)?);
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Value;
+    type Error = RuntimeError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Value, RuntimeError> {
+        self.finish()
+    }
+}
+
+/// Deserializes Rust values from Ein values.
+///
+/// This is created by calling `into_deserializer` on a value.
+pub struct ValueDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, RuntimeError> for Value {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> ValueDeserializer {
+        ValueDeserializer(self)
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = RuntimeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(v) => visitor.visit_bool(v),
+            Value::Integer(v) => visitor.visit_i64(v),
+            Value::Number(v) => visitor.visit_f64(v),
+            Value::String(v) => visitor.visit_string(v),
+
+            Value::List(v) => {
+                let items = v.borrow().clone();
+                let mut seq = SeqDeserializer::new(items.into_iter());
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+
+            Value::Map(v) => {
+                let entries: Vec<(Value, Value)> = v
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                    .collect();
+
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            }
+
+            other => Err(de::Error::custom(format!(
+                "{} cannot be deserialized",
+                other
+            ))),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Value::Nil => visitor.visit_none(),
+            other => visitor.visit_some(ValueDeserializer(other)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Value::String(name) => visitor.visit_enum(EnumDeserializer {
+                name,
+                value: Value::Nil,
+            }),
+
+            Value::Map(map) if map.borrow().len() == 1 => {
+                let (key, value) = map
+                    .borrow()
+                    .iter()
+                    .next()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .unwrap();
+
+                match key {
+                    Key::String(name) => visitor.visit_enum(EnumDeserializer { name, value }),
+                    other => Err(de::Error::custom(format!(
+                        "{} is not a variant name",
+                        Value::from(other)
+                    ))),
+                }
+            }
+
+            other => Err(de::Error::custom(format!(
+                "expected an enum variant, but got {}",
+                other
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Deserializes an enum variant, from its name and the data it contains.
+struct EnumDeserializer {
+    name: String,
+    value: Value,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = RuntimeError;
+    type Variant = ValueDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, ValueDeserializer), RuntimeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.name.into_deserializer())?;
+        Ok((variant, ValueDeserializer(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueDeserializer {
+    type Error = RuntimeError;
+
+    fn unit_variant(self) -> Result<(), RuntimeError> {
+        match self.0 {
+            Value::Nil => Ok(()),
+            other => Err(de::Error::custom(format!(
+                "expected a unit variant, but got {}",
+                other
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, RuntimeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RuntimeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+}
@@ -0,0 +1,291 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::{is_truthy, natives, Iter, RuntimeError, Value, VirtualMachine};
+
+/// A method of a userdata type, which receives the userdata as its first
+/// argument.
+pub type Method = fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A Rust type that can be passed to scripts by reference, without being
+/// converted into an Ein value.
+///
+/// Scripts can access the type's fields and call its methods using the `.`
+/// operator. Native functions can get the Rust value back out of a `Value`
+/// with `downcast_ref` or `downcast_mut`.
+///
+/// Operators can be overloaded by defining methods with special names:
+///
+/// * `__add`, `__sub`, `__mul`, `__div` and `__mod` implement arithmetic.
+/// * `__eq` implements `==` and `!=`. Without it, userdata values are only equal
+///   to themselves.
+/// * `__lt` and `__le` implement `<` and `<=`, and are called with their
+///   operands swapped to implement `>` and `>=`.
+/// * `__index` and `__set_index` implement getting and setting `value[index]`.
+/// * `__to_string` is used by string interpolation and `print`, and must return
+///   a string.
+/// * `__iter` is used by `for` loops and `iterate`, and returns the value to
+///   iterate over instead (such as a list, or a generator's iterator).
+/// * `__next` makes the value an iterator itself - it is called to get each
+///   value, and iteration ends when it returns nil.
+///
+/// The binary operators are used when either operand is userdata (the left one
+/// is checked first), and are passed both operands in the order they were
+/// written - so `2 * money` calls the `__mul` method of `money` with `2` and
+/// `money`.
+pub trait UserData: AsAny {
+    /// The name of the type, which is used when the value is printed and in
+    /// error messages.
+    fn type_name(&self) -> &str;
+
+    /// The methods that can be called on values of this type, along with their
+    /// arity. As with the methods of built-in types, the arity includes the
+    /// value that the method is called on.
+    ///
+    /// This is called once per type, the first time that one of its methods is
+    /// looked up.
+    fn methods(&self) -> Vec<(&'static str, Option<usize>, Method)> {
+        vec![]
+    }
+
+    /// Gets the value of a field, or returns `None` if there is no field with
+    /// that name (in which case the methods are searched instead).
+    fn get_field(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    /// Sets the value of a field.
+    fn set_field(&mut self, name: &str, _value: Value) -> Result<(), RuntimeError> {
+        Err(RuntimeError::InvalidOperation {
+            reason: format!("cannot set field {} of {}", name, self.type_name()),
+        })
+    }
+}
+
+impl Debug for dyn UserData {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<{}>", self.type_name())
+    }
+}
+
+/// Allows userdata to be downcast back to its original type.
+///
+/// This is implemented automatically for every type.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &(dyn Any + 'static);
+    fn as_any_mut(&mut self) -> &mut (dyn Any + 'static);
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &(dyn Any + 'static) {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut (dyn Any + 'static) {
+        self
+    }
+}
+
+impl Value {
+    /// Wraps a Rust value so that it can be passed to scripts.
+    pub fn user_data<T>(data: T) -> Value
+    where
+        T: UserData,
+    {
+        Value::UserData(Rc::new(RefCell::new(data)))
+    }
+
+    /// Borrows the Rust value inside of a userdata value, if it is of type `T`.
+    pub fn downcast_ref<T>(&self) -> Option<Ref<'_, T>>
+    where
+        T: UserData,
+    {
+        match self {
+            Value::UserData(data) => {
+                Ref::filter_map(data.borrow(), |data| data.as_any().downcast_ref()).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Mutably borrows the Rust value inside of a userdata value, if it is of
+    /// type `T`.
+    pub fn downcast_mut<T>(&self) -> Option<RefMut<'_, T>>
+    where
+        T: UserData,
+    {
+        match self {
+            Value::UserData(data) => {
+                RefMut::filter_map(data.borrow_mut(), |data| data.as_any_mut().downcast_mut()).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// Gets the method table for a type of userdata, creating it the first time
+    /// that it's needed.
+    pub(crate) fn userdata_methods(&mut self, data: &dyn UserData) -> &HashMap<String, Value> {
+        self.userdata_methods
+            .entry(data.as_any().type_id())
+            .or_insert_with(|| natives::method_table(&data.methods()))
+    }
+
+    /// Looks up one of the methods of a userdata value, returning the name of
+    /// its type as well.
+    fn operator_method(&mut self, value: &Value, name: &str) -> (String, Option<Value>) {
+        match value {
+            Value::UserData(data) => {
+                let data: &dyn UserData = &*data.borrow();
+                let method = self.userdata_methods(data).get(name).cloned();
+
+                (data.type_name().to_string(), method)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Applies a binary operator that isn't defined for the operands, by calling
+    /// an operator method if either of them is userdata.
+    pub(crate) fn binary_operator(
+        &mut self,
+        verb: &str,
+        name: &str,
+        lhs: Value,
+        rhs: Value,
+    ) -> Result<Value, RuntimeError> {
+        let receiver = match (&lhs, &rhs) {
+            (Value::UserData(_), _) => lhs.clone(),
+            (_, Value::UserData(_)) => rhs.clone(),
+            _ => {
+                return Err(RuntimeError::InvalidOperation {
+                    reason: format!("cannot {} {} and {}", verb, lhs, rhs),
+                })
+            }
+        };
+
+        match self.operator_method(&receiver, name) {
+            (_, Some(method)) => self.call(&method, &[lhs, rhs]),
+            (type_name, None) => Err(RuntimeError::InvalidOperation {
+                reason: format!(
+                    "cannot {} {} and {}, as {} has no {} method",
+                    verb, lhs, rhs, type_name, name
+                ),
+            }),
+        }
+    }
+
+    /// Compares two values for equality, using the `__eq` method of userdata if
+    /// it has one.
+    pub(crate) fn equals(&mut self, lhs: Value, rhs: Value) -> Result<bool, RuntimeError> {
+        let receiver = match (&lhs, &rhs) {
+            (Value::UserData(_), _) => lhs.clone(),
+            (_, Value::UserData(_)) => rhs.clone(),
+            _ => return Ok(lhs == rhs),
+        };
+
+        match self.operator_method(&receiver, "__eq") {
+            (_, Some(method)) => Ok(is_truthy(&self.call(&method, &[lhs, rhs])?)),
+            (_, None) => Ok(lhs == rhs),
+        }
+    }
+
+    /// Gets `target[index]`, using the `__index` method of userdata.
+    pub(crate) fn index_userdata(
+        &mut self,
+        target: Value,
+        index: Value,
+    ) -> Result<Value, RuntimeError> {
+        match self.operator_method(&target, "__index") {
+            (_, Some(method)) => self.call(&method, &[target, index]),
+            (type_name, None) => Err(RuntimeError::InvalidOperation {
+                reason: format!(
+                    "{} cannot be indexed, as it has no __index method",
+                    type_name
+                ),
+            }),
+        }
+    }
+
+    /// Sets `target[index]`, using the `__set_index` method of userdata.
+    pub(crate) fn set_index_userdata(
+        &mut self,
+        target: Value,
+        index: Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        match self.operator_method(&target, "__set_index") {
+            (_, Some(method)) => self.call(&method, &[target, index, value]).map(|_| ()),
+            (type_name, None) => Err(RuntimeError::InvalidOperation {
+                reason: format!(
+                    "{} cannot be indexed, as it has no __set_index method",
+                    type_name
+                ),
+            }),
+        }
+    }
+
+    /// Converts a value to a string, as used by string interpolation. Userdata
+    /// can override this with a `__to_string` method.
+    pub(crate) fn stringify(&mut self, value: &Value) -> Result<String, RuntimeError> {
+        if let Value::UserData(_) = value {
+            if let (type_name, Some(method)) = self.operator_method(value, "__to_string") {
+                return match self.call(&method, std::slice::from_ref(value))? {
+                    Value::String(s) => Ok(s),
+                    other => Err(RuntimeError::InvalidOperation {
+                        reason: format!(
+                            "the __to_string method of {} returned {}, rather than a string",
+                            type_name, other
+                        ),
+                    }),
+                };
+            }
+        }
+
+        Ok(value.stringify())
+    }
+
+    /// Creates an iterator over a value, as used by `for` loops and `iterate`.
+    /// Userdata is iterated over using its `__iter` or `__next` method.
+    pub(crate) fn iterate(&mut self, value: &Value) -> Result<Rc<RefCell<Iter>>, RuntimeError> {
+        if let Value::UserData(_) = value {
+            if let (_, Some(method)) = self.operator_method(value, "__iter") {
+                return match self.call(&method, std::slice::from_ref(value))? {
+                    iterable @ Value::UserData(_) => self.userdata_iterator(iterable),
+                    iterable => Iter::from_value(&iterable),
+                };
+            }
+
+            return self.userdata_iterator(value.clone());
+        }
+
+        Iter::from_value(value)
+    }
+
+    fn userdata_iterator(&mut self, value: Value) -> Result<Rc<RefCell<Iter>>, RuntimeError> {
+        match self.operator_method(&value, "__next") {
+            (_, Some(_)) => Ok(Rc::new(RefCell::new(Iter::UserData(value)))),
+            (type_name, None) => Err(RuntimeError::InvalidOperation {
+                reason: format!(
+                    "cannot iterate over {}, as it has no __iter or __next method",
+                    type_name
+                ),
+            }),
+        }
+    }
+
+    /// Gets the next value from a userdata iterator, using its `__next` method.
+    pub(crate) fn next_userdata(&mut self, value: &Value) -> Result<Value, RuntimeError> {
+        match self.operator_method(value, "__next") {
+            (_, Some(method)) => self.call(&method, std::slice::from_ref(value)),
+            (type_name, None) => Err(RuntimeError::InvalidOperation {
+                reason: format!("{} has no __next method", type_name),
+            }),
+        }
+    }
+}
@@ -1,11 +1,34 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::fiber::Fiber;
+use crate::function::{BoundMethod, Closure, Function, NativeFunction};
+use crate::iterator::Iter;
+use crate::module::Module;
+use crate::userdata::UserData;
 
 #[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
+    Integer(i64),
     Number(f64),
     String(String), // TODO: Interning
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<Key, Value>>>),
+    Range(i64, i64),
+    Iterator(Rc<RefCell<Iter>>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    NativeFunction(Rc<NativeFunction>),
+    BoundMethod(Rc<BoundMethod>),
+    Module(Rc<Module>),
+    Fiber(Rc<Fiber>),
+    UserData(Rc<RefCell<dyn UserData>>),
 }
 
 impl Display for Value {
@@ -13,8 +36,171 @@ impl Display for Value {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(v) => write!(f, "{}", v),
-            Value::Number(v) => write!(f, "{}", v),
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Number(v) => write!(f, "{:?}", v),
             Value::String(v) => write!(f, "\"{}\"", v),
+
+            Value::List(v) => {
+                write!(f, "[")?;
+
+                for (i, item) in v.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", item)?;
+                }
+
+                write!(f, "]")
+            }
+
+            Value::Map(v) => {
+                let map = v.borrow();
+
+                if map.is_empty() {
+                    return write!(f, "[:]");
+                }
+
+                // Sort the entries so that the output is predictable.
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+
+                write!(f, "[")?;
+
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}: {}", Value::from(key.clone()), value)?;
+                }
+
+                write!(f, "]")
+            }
+
+            Value::Range(start, end) => write!(f, "{}..{}", start, end),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+
+            Value::Function(v) => fmt_function(f, v),
+            Value::Closure(v) => fmt_function(f, &v.function),
+            Value::NativeFunction(v) => write!(f, "<native fn {}>", v.name),
+            Value::BoundMethod(v) => write!(f, "{}", v.method),
+            Value::Module(v) => write!(f, "<module {}>", v.name),
+            Value::Fiber(_) => write!(f, "<fiber>"),
+            Value::UserData(v) => write!(f, "<{}>", v.borrow().type_name()),
+        }
+    }
+}
+
+fn fmt_function(f: &mut Formatter, function: &Function) -> fmt::Result {
+    match &function.name {
+        Some(name) => write!(f, "<fn {}>", name),
+        None => write!(f, "<fn>"),
+    }
+}
+
+/// Compares an integer and a float exactly - converting the integer to a float
+/// would round it if it's above 2^53. Returns `None` if the float is NaN.
+pub(crate) fn compare_integer_number(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+
+    // `i64::MAX as f64` rounds up to 2^63, which is out of range, whereas
+    // `i64::MIN as f64` is exactly -2^63.
+    if b >= i64::MAX as f64 {
+        return Some(Ordering::Less);
+    }
+
+    if b < i64::MIN as f64 {
+        return Some(Ordering::Greater);
+    }
+
+    // The float is in range, so its integer part can be converted exactly, and
+    // its fractional part breaks ties.
+    let ordering = a
+        .cmp(&(b.trunc() as i64))
+        .then_with(|| 0.0.partial_cmp(&b.fract()).unwrap());
+
+    Some(ordering)
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(a), Value::Number(b)) | (Value::Number(b), Value::Integer(a)) => {
+                compare_integer_number(*a, *b) == Some(Ordering::Equal)
+            }
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+
+            // Collections are compared by identity, rather than by their contents.
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+
+            (Value::Range(a, b), Value::Range(c, d)) => a == c && b == d,
+            (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFunction(a), Value::NativeFunction(b)) => Rc::ptr_eq(a, b),
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            (Value::Fiber(a), Value::Fiber(b)) => Rc::ptr_eq(a, b),
+            (Value::UserData(a), Value::UserData(b)) => Rc::ptr_eq(a, b),
+
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// Converts the value to a string, as used by string interpolation.
+    ///
+    /// Unlike the `Display` implementation, strings are not quoted.
+    pub fn stringify(&self) -> String {
+        match self {
+            Value::String(v) => v.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// A value that can be used as the key of a map.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl Key {
+    /// Converts a value into a map key, if it is hashable.
+    ///
+    /// Floats with an integral value are converted to integers, so that `1` and
+    /// `1.0` refer to the same entry.
+    pub fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Boolean(v) => Some(Key::Boolean(*v)),
+            Value::Integer(v) => Some(Key::Integer(*v)),
+            Value::Number(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => {
+                Some(Key::Integer(*v as i64))
+            }
+            Value::String(v) => Some(Key::String(v.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Value {
+        match key {
+            Key::Boolean(v) => Value::Boolean(v),
+            Key::Integer(v) => Value::Integer(v),
+            Key::String(v) => Value::String(v),
         }
     }
 }
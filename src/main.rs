@@ -1,17 +1,33 @@
-use std::fmt::{self, Display, Formatter};
-use std::fs;
 use std::io;
 use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::Ordering;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use structopt::StructOpt;
 
-use ein_syntax::parser::{self, ParseError};
-use ein_vm::{Chunk, Instruction, RuntimeError, Value, VirtualMachine};
+use ein_vm::{FileSystemLoader, VirtualMachine};
 
 #[derive(StructOpt, Debug)]
 struct Options {
+    /// Adds a directory to search for imported modules in
+    #[structopt(
+        short = "I",
+        long = "module-path",
+        parse(from_os_str),
+        number_of_values = 1
+    )]
+    module_paths: Vec<PathBuf>,
+
+    /// Allows scripts to use the io module, to access stdin, stdout and stderr
+    #[structopt(long = "allow-io")]
+    allow_io: bool,
+
+    /// Allows scripts to use the fs module, to read and write files
+    #[structopt(long = "allow-fs")]
+    allow_fs: bool,
+
     #[structopt(name = "FILE", parse(from_os_str))]
     file: Option<PathBuf>,
 }
@@ -19,42 +35,64 @@ struct Options {
 fn main() {
     let options = Options::from_args();
 
-    match options.file {
-        Some(path) => run_file(&path),
-        None => repl(),
+    let mut vm = VirtualMachine::new();
+
+    // Ctrl-C stops the script that is running, rather than killing the process,
+    // so that the REPL can carry on (and save its history when it exits). While
+    // the REPL is waiting for input, rustyline handles Ctrl-C itself.
+    //
+    // A script that is blocked in a native function (such as `io.read_line`)
+    // can't notice the interrupt, so if it hasn't been handled by the time
+    // Ctrl-C is pressed again, the process exits immediately. The REPL's history
+    // isn't saved in that case, as the editor is owned by the main thread.
+    let interrupt = vm.interrupt_handle();
+
+    let handler = move || {
+        if interrupt.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    };
+
+    if let Err(e) = ctrlc::set_handler(handler) {
+        eprintln!("Failed to set Ctrl-C handler: {}\n", e);
     }
-}
 
-fn run<'a>(input: &'a str, vm: &mut VirtualMachine) -> Result<'a, Option<Value>> {
-    let mut chunk = Chunk::new();
+    // Imports are resolved relative to the script's directory (or the current
+    // directory, in the REPL) before trying the paths given on the command line.
+    let root = match options.file.as_ref().and_then(|path| path.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
 
-    match parser::parse_expr(input) {
-        Ok(expr) => chunk.emit(&expr),
-        Err(_) => {
-            let ast = parser::parse_program(input)?;
-            chunk.emit(&ast);
-        }
+    let mut loader = FileSystemLoader::new(vec![root]);
+
+    for path in options.module_paths {
+        loader.add_search_path(path);
     }
 
-    chunk.add_instruction(Instruction::Return);
+    vm.set_module_loader(loader);
+
+    if options.allow_io {
+        vm.grant_io();
+    }
 
-    let return_value = vm.run(&chunk)?;
+    if options.allow_fs {
+        vm.grant_fs();
+    }
 
-    Ok(return_value)
+    match options.file {
+        Some(path) => run_file(&path, &mut vm),
+        None => repl(&mut vm),
+    }
 }
 
-fn run_file(path: &PathBuf) {
-    match fs::read_to_string(path) {
-        Ok(program) => {
-            if let Err(e) = run(&program, &mut VirtualMachine::new()) {
-                eprintln!("Error: {}\n", e);
-            }
-        }
-        Err(e) => eprintln!("Error: {}\n", e),
+fn run_file(path: &PathBuf, vm: &mut VirtualMachine) {
+    if let Err(e) = vm.exec_file(path) {
+        eprintln!("Error: {}\n", e);
     }
 }
 
-fn repl() {
+fn repl(vm: &mut VirtualMachine) {
     println!("| Ein {}", env!("CARGO_PKG_VERSION"));
     println!("| Copyright © 2018-2020 Joe Clay");
     println!("| Released under the MIT License\n");
@@ -67,11 +105,12 @@ fn repl() {
         }
     }
 
-    let mut ctx = VirtualMachine::new();
-
     loop {
         let line = match editor.readline(">> ") {
             Ok(line) => line,
+            // Ctrl-C discards the line that is being typed, and Ctrl-D exits.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
             Err(e) => {
                 eprintln!("Error: {}\n", e);
                 break;
@@ -80,7 +119,7 @@ fn repl() {
 
         editor.add_history_entry(line.as_str());
 
-        match run(&line, &mut ctx) {
+        match vm.eval(&line) {
             Ok(Some(value)) => println!("{}\n", value),
             Ok(None) => {}
             Err(e) => eprintln!("Error: {}\n", e),
@@ -94,54 +133,7 @@ fn repl() {
 
 fn is_not_found_error(error: &ReadlineError) -> bool {
     match error {
-        ReadlineError::Io(inner_error) => match inner_error.kind() {
-            io::ErrorKind::NotFound => true,
-            _ => false,
-        },
+        ReadlineError::Io(inner_error) => inner_error.kind() == io::ErrorKind::NotFound,
         _ => false,
     }
 }
-
-type Result<'a, T = ()> = std::result::Result<T, EinError<'a>>;
-
-enum EinError<'a> {
-    Parse(ParseError<'a>),
-    Runtime(RuntimeError),
-    Io(io::Error),
-    Readline(ReadlineError),
-}
-
-impl<'a> From<ParseError<'a>> for EinError<'a> {
-    fn from(err: ParseError<'a>) -> EinError<'a> {
-        EinError::Parse(err)
-    }
-}
-
-impl<'a> From<RuntimeError> for EinError<'a> {
-    fn from(err: RuntimeError) -> EinError<'a> {
-        EinError::Runtime(err)
-    }
-}
-
-impl<'a> From<io::Error> for EinError<'a> {
-    fn from(err: io::Error) -> EinError<'a> {
-        EinError::Io(err)
-    }
-}
-
-impl<'a> From<ReadlineError> for EinError<'a> {
-    fn from(err: ReadlineError) -> EinError<'a> {
-        EinError::Readline(err)
-    }
-}
-
-impl<'a> Display for EinError<'a> {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            EinError::Parse(e) => e.fmt(f),
-            EinError::Runtime(e) => e.fmt(f),
-            EinError::Io(e) => e.fmt(f),
-            EinError::Readline(e) => e.fmt(f),
-        }
-    }
-}